@@ -1,4 +1,4 @@
-import { type ExtensionContext, workspace, window, commands } from "vscode";
+import { type ExtensionContext, workspace, window, commands, ConfigurationTarget } from "vscode";
 import * as path from "path";
 import * as fs from "fs";
 
@@ -19,7 +19,10 @@ export function activate(context: ExtensionContext): Promise<void> {
     };
 
     const clientOptions: LanguageClientOptions = {
-        documentSelector: [{ scheme: "file", language: "typst" }],
+        documentSelector: [
+            { scheme: "file", language: "typst" },
+            { scheme: "untitled", language: "typst" },
+        ],
     };
 
     client = new LanguageClient("typst-lsp", "Typst Language Server", serverOptions, clientOptions);
@@ -31,7 +34,10 @@ export function activate(context: ExtensionContext): Promise<void> {
     }, null);
 
     context.subscriptions.push(
-        commands.registerCommand("typst-lsp.exportCurrentPdf", commandExportCurrentPdf)
+        commands.registerCommand("typst-lsp.exportCurrentPdf", commandExportCurrentPdf),
+        commands.registerCommand("typst-lsp.exportCurrentPng", commandExportCurrentPng),
+        commands.registerCommand("typst-lsp.pinMainFile", commandPinMainFile),
+        commands.registerCommand("typst-lsp.unpinMainFile", commandUnpinMainFile)
     );
 
     return client.start();
@@ -77,3 +83,36 @@ async function commandExportCurrentPdf(): Promise<void> {
         arguments: [uri],
     });
 }
+
+async function commandExportCurrentPng(): Promise<void> {
+    const activeEditor = window.activeTextEditor;
+    if (activeEditor === undefined) {
+        return;
+    }
+
+    const uri = activeEditor.document.uri.toString();
+
+    await client?.sendRequest("workspace/executeCommand", {
+        command: "typst-lsp.doPngExport",
+        arguments: [uri],
+    });
+}
+
+async function commandPinMainFile(): Promise<void> {
+    const activeEditor = window.activeTextEditor;
+    if (activeEditor === undefined || activeEditor.document.uri.scheme !== "file") {
+        return;
+    }
+
+    // Saved in the workspace's settings, so that the pin is kept for the next session
+    const mainFile = workspace.asRelativePath(activeEditor.document.uri, false);
+    await workspace
+        .getConfiguration("typst-lsp")
+        .update("mainFile", mainFile, ConfigurationTarget.Workspace);
+}
+
+async function commandUnpinMainFile(): Promise<void> {
+    await workspace
+        .getConfiguration("typst-lsp")
+        .update("mainFile", undefined, ConfigurationTarget.Workspace);
+}
@@ -1,16 +1,446 @@
-use tower_lsp::lsp_types;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
 
+use itertools::Itertools;
+use serde_json::{Map, Value as JsonValue};
+use tower_lsp::lsp_types::{self, FormattingOptions, MessageType};
+
+/// When PDFs are exported without being asked for through a command
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
 pub enum ExportPdfMode {
+    /// Only export through commands
     Never,
+    /// Export whenever a file is saved, leaving edits in between alone
     #[default]
     OnSave,
+    /// Export after every edit, once diagnostics are updated
     OnType,
 }
 
-#[derive(Debug, Clone, Default, PartialEq, Eq)]
+impl ExportPdfMode {
+    fn from_setting(setting: Option<&JsonValue>) -> Self {
+        match setting {
+            Some(JsonValue::String(mode)) => match mode.as_str() {
+                "never" => Self::Never,
+                "onSave" => Self::OnSave,
+                "onType" => Self::OnType,
+                _ => Self::default(),
+            },
+            _ => Self::default(),
+        }
+    }
+}
+
+/// Standard exported PDFs conform to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PdfStandard {
+    /// Plain PDF 1.7
+    #[default]
+    V1_7,
+    /// PDF/A-2b, which archives and universities often require
+    A2b,
+}
+
+impl PdfStandard {
+    fn from_setting(setting: Option<&JsonValue>) -> Self {
+        match setting.and_then(JsonValue::as_str) {
+            Some("a-2b") => Self::A2b,
+            _ => Self::default(),
+        }
+    }
+}
+
+/// Format documents can be exported in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExportFormat {
+    Pdf,
+    Png,
+}
+
+impl ExportFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "pdf" => Some(Self::Pdf),
+            "png" => Some(Self::Png),
+            _ => None,
+        }
+    }
+}
+
+/// Severity to report diagnostics with, or `Off` to not report them at all
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticLevel {
+    Error,
+    Warning,
+    Information,
+    Hint,
+    Off,
+}
+
+impl DiagnosticLevel {
+    fn from_setting(setting: &JsonValue) -> Option<Self> {
+        match setting.as_str()? {
+            "error" => Some(Self::Error),
+            "warning" => Some(Self::Warning),
+            "info" => Some(Self::Information),
+            "hint" => Some(Self::Hint),
+            "off" => Some(Self::Off),
+            _ => None,
+        }
+    }
+}
+
+/// Changes the level of diagnostics whose message contains `message`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiagnosticRule {
+    pub message: String,
+    pub level: DiagnosticLevel,
+}
+
+/// A completion inserting a template, written in the LSP snippet syntax with tab stops like `$1`
+/// and placeholders like `${1:caption}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snippet {
+    pub name: String,
+    pub body: String,
+}
+
+/// How to format a document, which is set by the options the editor sends along with each
+/// formatting request, like its own indentation settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FormatConfig {
+    /// Number of spaces per indentation level, when not using tabs
+    pub indent_width: usize,
+    pub use_tabs: bool,
+    pub trim_trailing_whitespace: bool,
+    /// Whether the document should end with a newline
+    pub insert_final_newline: bool,
+    /// Whether to remove blank lines at the end of the document
+    pub trim_final_newlines: bool,
+}
+
+impl FormatConfig {
+    /// Gets the config for the options the editor sent along with a formatting request. Options
+    /// the editor may leave out default to trimming trailing whitespace, and to leaving the end of
+    /// the document as it is.
+    pub fn from_options(options: &FormattingOptions) -> Self {
+        Self {
+            indent_width: options.tab_size as usize,
+            use_tabs: !options.insert_spaces,
+            trim_trailing_whitespace: options.trim_trailing_whitespace.unwrap_or(true),
+            insert_final_newline: options.insert_final_newline.unwrap_or(false),
+            trim_final_newlines: options.trim_final_newlines.unwrap_or(false),
+        }
+    }
+}
+
+/// Which of the fonts found are used, and which are preferred
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FontConfig {
+    /// Families to prefer when Typst falls back to another font for a character, in order
+    pub fallback: Vec<String>,
+    /// Whether to include the fonts embedded in typst-lsp
+    pub embedded: bool,
+}
+
+impl Default for FontConfig {
+    fn default() -> Self {
+        Self {
+            fallback: Vec::new(),
+            embedded: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Config {
     pub export_pdf: ExportPdfMode,
+    pub pdf_standard: PdfStandard,
+    pub inlay_hints: bool,
+    /// Resolution of exported PNGs, in dots per inch
+    pub png_dpi: u32,
+    /// Formats the export all command writes the document in
+    pub export_formats: Vec<ExportFormat>,
+    /// Template for the directory exports are written to, relative to the workspace root unless
+    /// absolute. May contain `{name}`, `{dir}`, `{date}`, `{time}`, `{title}` and `{git}`. The
+    /// last two are empty when the document has no title or isn't in a Git repository.
+    pub export_dir: String,
+    /// Template for the name of exported files, without the extension
+    pub export_file_name: String,
+    /// Extra font files and directories, relative to the workspace root unless absolute
+    pub font_paths: Vec<PathBuf>,
+    pub fonts: FontConfig,
+    pub diagnostic_rules: Vec<DiagnosticRule>,
+    /// How long to wait after an edit before updating diagnostics, in milliseconds. Edits made in
+    /// the meantime restart the wait.
+    pub debounce_ms: u64,
+    /// How long a file which isn't open can go unused before it's dropped from memory, in
+    /// seconds, or 0 to keep such files loaded
+    pub closed_source_timeout_secs: u64,
+    /// Number of compilations after which unused results are evicted from Typst's incremental
+    /// cache, or 0 to never evict them. Keeping results longer speeds up recompiling at the cost
+    /// of memory.
+    pub cache_max_age: usize,
+    /// Longest time compiling or evaluating a file may take before it's reported as timed out,
+    /// in seconds, or 0 for no limit. A compilation which times out keeps running in the
+    /// background until it ends, while later ones run on another thread.
+    pub compile_timeout_secs: u64,
+    /// Whether compilations which needed a file that couldn't be loaded, and compiled it as empty
+    /// instead, fail rather than just warn about it. Failed compilations aren't exported.
+    pub strict_sources: bool,
+    /// Type of message to log how long parsing, compiling and exporting take with, or `None` to
+    /// not log them
+    pub log_timings: Option<MessageType>,
+    /// Directory which absolute paths in imports and file loads are resolved against, relative to
+    /// the workspace root unless absolute. When `None`, it's detected using `root_markers`.
+    pub root_path: Option<PathBuf>,
+    /// File compiled for diagnostics and exports whenever any file is edited, relative to the
+    /// workspace root unless absolute. Files of a document split into chapters don't compile on
+    /// their own. Files the main file doesn't include or import, and every file when `None`, are
+    /// compiled on their own.
+    pub main_file: Option<PathBuf>,
+    /// Names of files marking the root directory, which is the closest directory containing one of
+    /// them above the file being compiled
+    pub root_markers: Vec<String>,
+    /// Largest file, in megabytes, which can be loaded by Typst code, like images, or `None` for
+    /// no limit
+    pub max_resource_size_mb: Option<u64>,
+    /// How long after the last edit, in seconds, to stop recompiling in the background and free
+    /// Typst's incremental cache, or 0 to never do so. The next edit resumes background work.
+    pub idle_timeout_secs: u64,
+    /// Globs of files and directories to skip when loading every Typst file in the workspace for
+    /// workspace-wide queries, on top of those ignored by `.gitignore` files
+    pub index_exclude: Vec<String>,
+    /// Files and packages whose names are available in every source without importing them, like
+    /// `"@preview/example:0.1.0"` or a path relative to the workspace root
+    pub auto_imports: Vec<String>,
+    /// Values available to documents as `sys.inputs`, like the `--input key=value` of Typst's CLI
+    pub inputs: BTreeMap<String, String>,
+    /// Snippets offered by completion in markup, besides the built-in ones. A snippet with the
+    /// same name as a built-in one replaces it.
+    pub snippets: Vec<Snippet>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            export_pdf: ExportPdfMode::default(),
+            pdf_standard: PdfStandard::default(),
+            inlay_hints: true,
+            png_dpi: 144,
+            export_formats: vec![ExportFormat::Pdf, ExportFormat::Png],
+            export_dir: "{dir}".to_owned(),
+            export_file_name: "{name}".to_owned(),
+            font_paths: Vec::new(),
+            fonts: FontConfig::default(),
+            diagnostic_rules: Vec::new(),
+            debounce_ms: 200,
+            closed_source_timeout_secs: 600,
+            cache_max_age: 30,
+            compile_timeout_secs: 30,
+            strict_sources: false,
+            log_timings: None,
+            root_path: None,
+            main_file: None,
+            root_markers: vec!["typst.toml".to_owned()],
+            max_resource_size_mb: Some(100),
+            idle_timeout_secs: 900,
+            index_exclude: Vec::new(),
+            auto_imports: Vec::new(),
+            inputs: BTreeMap::new(),
+            snippets: Vec::new(),
+        }
+    }
+}
+
+impl Config {
+    /// Whether the setting has the value it has when missing, like settings clients send without
+    /// the user having set them
+    pub fn is_default_setting(key: &str, value: &JsonValue) -> bool {
+        let mut config = Self::default();
+        config.update(&Map::from_iter([(key.to_owned(), value.clone())]));
+        config == Self::default()
+    }
+
+    /// Updates the configuration from the settings object sent by the client. Missing or invalid
+    /// settings are reset to their defaults.
+    pub fn update(&mut self, settings: &Map<String, JsonValue>) {
+        let defaults = Self::default();
+
+        self.export_pdf = ExportPdfMode::from_setting(settings.get("exportPdf"));
+        self.pdf_standard = PdfStandard::from_setting(settings.get("pdfStandard"));
+        self.inlay_hints = settings
+            .get("inlayHints")
+            .and_then(JsonValue::as_bool)
+            .unwrap_or(defaults.inlay_hints);
+        self.png_dpi = settings
+            .get("pngDpi")
+            .and_then(JsonValue::as_u64)
+            .filter(|dpi| *dpi > 0)
+            .map_or(defaults.png_dpi, |dpi| dpi as u32);
+        self.export_formats = settings
+            .get("exportFormats")
+            .and_then(JsonValue::as_array)
+            .map(|formats| {
+                formats
+                    .iter()
+                    .filter_map(JsonValue::as_str)
+                    .filter_map(ExportFormat::from_name)
+                    // Each format is exported once, even if listed more than once
+                    .unique()
+                    .collect::<Vec<_>>()
+            })
+            .filter(|formats| !formats.is_empty())
+            .unwrap_or(defaults.export_formats);
+        self.export_dir = settings
+            .get("exportDir")
+            .and_then(JsonValue::as_str)
+            .filter(|dir| !dir.is_empty())
+            .map_or(defaults.export_dir, ToOwned::to_owned);
+        self.export_file_name = settings
+            .get("exportFileName")
+            .and_then(JsonValue::as_str)
+            .filter(|file_name| !file_name.is_empty())
+            .map_or(defaults.export_file_name, ToOwned::to_owned);
+        self.font_paths = settings
+            .get("fontPaths")
+            .and_then(JsonValue::as_array)
+            .map_or(defaults.font_paths, |paths| {
+                paths
+                    .iter()
+                    .filter_map(JsonValue::as_str)
+                    .map(PathBuf::from)
+                    .collect()
+            });
+        self.fonts = FontConfig {
+            fallback: settings
+                .get("fontFallback")
+                .and_then(JsonValue::as_array)
+                .map_or(defaults.fonts.fallback, |families| {
+                    families
+                        .iter()
+                        .filter_map(JsonValue::as_str)
+                        .map(ToOwned::to_owned)
+                        .collect()
+                }),
+            embedded: settings
+                .get("embeddedFonts")
+                .and_then(JsonValue::as_bool)
+                .unwrap_or(defaults.fonts.embedded),
+        };
+        self.diagnostic_rules = settings
+            .get("diagnosticSeverity")
+            .and_then(JsonValue::as_object)
+            .map_or(defaults.diagnostic_rules, |rules| {
+                rules
+                    .iter()
+                    .filter_map(|(message, level)| {
+                        Some(DiagnosticRule {
+                            message: message.clone(),
+                            level: DiagnosticLevel::from_setting(level)?,
+                        })
+                    })
+                    .collect()
+            });
+        self.debounce_ms = settings
+            .get("debounceMs")
+            .and_then(JsonValue::as_u64)
+            .unwrap_or(defaults.debounce_ms);
+        self.closed_source_timeout_secs = settings
+            .get("closedSourceTimeout")
+            .and_then(JsonValue::as_u64)
+            .unwrap_or(defaults.closed_source_timeout_secs);
+        self.cache_max_age = settings
+            .get("cacheMaxAge")
+            .and_then(JsonValue::as_u64)
+            .map_or(defaults.cache_max_age, |max_age| max_age as usize);
+        self.compile_timeout_secs = settings
+            .get("compileTimeout")
+            .and_then(JsonValue::as_u64)
+            .unwrap_or(defaults.compile_timeout_secs);
+        self.strict_sources = settings
+            .get("strictSources")
+            .and_then(JsonValue::as_bool)
+            .unwrap_or(defaults.strict_sources);
+        self.log_timings = match settings.get("logTimings").and_then(JsonValue::as_str) {
+            Some("info") => Some(MessageType::INFO),
+            Some("log") => Some(MessageType::LOG),
+            _ => defaults.log_timings,
+        };
+        self.root_path = settings
+            .get("rootPath")
+            .and_then(JsonValue::as_str)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        self.main_file = settings
+            .get("mainFile")
+            .and_then(JsonValue::as_str)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+        self.root_markers = settings
+            .get("rootMarkers")
+            .and_then(JsonValue::as_array)
+            .map_or(defaults.root_markers, |markers| {
+                markers
+                    .iter()
+                    .filter_map(JsonValue::as_str)
+                    .map(ToOwned::to_owned)
+                    .collect()
+            });
+        self.max_resource_size_mb =
+            match settings.get("maxResourceSize").and_then(JsonValue::as_u64) {
+                Some(0) => None,
+                Some(max_size) => Some(max_size),
+                None => defaults.max_resource_size_mb,
+            };
+        self.idle_timeout_secs = settings
+            .get("idleTimeout")
+            .and_then(JsonValue::as_u64)
+            .unwrap_or(defaults.idle_timeout_secs);
+        self.index_exclude = settings
+            .get("indexExclude")
+            .and_then(JsonValue::as_array)
+            .map_or(defaults.index_exclude, |globs| {
+                globs
+                    .iter()
+                    .filter_map(JsonValue::as_str)
+                    .map(ToOwned::to_owned)
+                    .collect()
+            });
+        self.auto_imports = settings
+            .get("autoImports")
+            .and_then(JsonValue::as_array)
+            .map_or(defaults.auto_imports, |imports| {
+                imports
+                    .iter()
+                    .filter_map(JsonValue::as_str)
+                    .map(ToOwned::to_owned)
+                    .collect()
+            });
+        self.inputs = settings
+            .get("inputs")
+            .and_then(JsonValue::as_object)
+            .map_or(defaults.inputs, |inputs| {
+                inputs
+                    .iter()
+                    .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_owned())))
+                    .collect()
+            });
+        self.snippets = settings
+            .get("snippets")
+            .and_then(JsonValue::as_object)
+            .map_or(defaults.snippets, |snippets| {
+                snippets
+                    .iter()
+                    .filter_map(|(name, body)| {
+                        Some(Snippet {
+                            name: name.clone(),
+                            body: body.as_str()?.to_owned(),
+                        })
+                    })
+                    .collect()
+            });
+    }
 }
 
 /// What counts as "1 character" for string indexing. We should always prefer UTF-8, but support
@@ -43,4 +473,13 @@ impl From<PositionEncoding> for lsp_types::PositionEncodingKind {
 #[derive(Debug)]
 pub struct ConstConfig {
     pub position_encoding: PositionEncoding,
+    /// Local directories of the folders open in the client's workspace
+    pub root_paths: Vec<PathBuf>,
+    /// Whether the client can show the progress of tasks started by the server
+    pub supports_work_done_progress: bool,
+    /// Whether the client asks for diagnostics when it needs them, in which case they aren't
+    /// published on their own
+    pub supports_pull_diagnostics: bool,
+    /// Whether the client can be told to ask for diagnostics again after they changed
+    pub supports_diagnostic_refresh: bool,
 }
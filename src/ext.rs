@@ -1,3 +1,5 @@
+use std::path::PathBuf;
+
 use tower_lsp::lsp_types::{InitializeParams, PositionEncodingKind};
 use typst::util::StrExt as TypstStrExt;
 
@@ -5,6 +7,10 @@ use crate::config::PositionEncoding;
 
 pub trait InitializeParamsExt {
     fn position_encodings(&self) -> &[PositionEncodingKind];
+    fn root_paths(&self) -> Vec<PathBuf>;
+    fn supports_work_done_progress(&self) -> bool;
+    fn supports_pull_diagnostics(&self) -> bool;
+    fn supports_diagnostic_refresh(&self) -> bool;
 }
 
 static DEFAULT_ENCODING: [PositionEncodingKind; 1] = [PositionEncodingKind::UTF16];
@@ -18,6 +24,49 @@ impl InitializeParamsExt for InitializeParams {
             .map(|encodings| encodings.as_slice())
             .unwrap_or(&DEFAULT_ENCODING)
     }
+
+    fn root_paths(&self) -> Vec<PathBuf> {
+        match &self.workspace_folders {
+            Some(folders) => folders
+                .iter()
+                .filter_map(|folder| folder.uri.to_file_path().ok())
+                .collect(),
+            None => {
+                // `root_uri` is deprecated in favor of `workspace_folders`, but older clients only
+                // send the former
+                #[allow(deprecated)]
+                let root_uri = self.root_uri.as_ref();
+                root_uri
+                    .and_then(|uri| uri.to_file_path().ok())
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+
+    fn supports_work_done_progress(&self) -> bool {
+        self.capabilities
+            .window
+            .as_ref()
+            .and_then(|window| window.work_done_progress)
+            .unwrap_or(false)
+    }
+
+    fn supports_pull_diagnostics(&self) -> bool {
+        self.capabilities
+            .text_document
+            .as_ref()
+            .map_or(false, |text_document| text_document.diagnostic.is_some())
+    }
+
+    fn supports_diagnostic_refresh(&self) -> bool {
+        self.capabilities
+            .workspace
+            .as_ref()
+            .and_then(|workspace| workspace.diagnostic.as_ref())
+            .and_then(|diagnostic| diagnostic.refresh_support)
+            .unwrap_or(false)
+    }
 }
 
 pub trait StrExt {
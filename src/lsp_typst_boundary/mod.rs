@@ -13,6 +13,7 @@ pub type TypstPathOwned = std::path::PathBuf;
 
 pub type TypstSource = typst::syntax::Source;
 pub type TypstSourceId = typst::syntax::SourceId;
+pub type TypstSpan = typst::syntax::Span;
 
 pub type LspPosition = lsp_types::Position;
 /// The interpretation of an `LspCharacterOffset` depends on the `LspPositionEncoding`
@@ -71,11 +72,24 @@ pub mod lsp_to_typst {
     ) -> TypstOffset {
         match lsp_position_encoding {
             LspPositionEncoding::Utf8 => {
+                // Typst counts columns in characters, but a UTF-8 offset into the line is a byte
+                // offset. Offsets past the end of the line are clamped to it, and offsets inside
+                // a character are moved back to its start.
                 let line_index = lsp_position.line as usize;
-                let column_index = lsp_position.character as usize;
-                typst_source
-                    .line_column_to_byte(line_index, column_index)
-                    .unwrap()
+                let byte_offset_in_line = lsp_position.character as usize;
+
+                let byte_line_offset = typst_source.line_to_byte(line_index).unwrap();
+                let byte_line_end = typst_source
+                    .line_to_byte(line_index + 1)
+                    .unwrap_or_else(|| typst_source.len_bytes());
+                let line = typst_source.text()[byte_line_offset..byte_line_end]
+                    .trim_end_matches(['\r', '\n']);
+
+                let mut byte_offset_in_line = byte_offset_in_line.min(line.len());
+                while !line.is_char_boundary(byte_offset_in_line) {
+                    byte_offset_in_line -= 1;
+                }
+                byte_line_offset + byte_offset_in_line
             }
             LspPositionEncoding::Utf16 => {
                 // We have a line number and a UTF-16 offset into that line. We want a byte offset into
@@ -125,7 +139,8 @@ pub mod typst_to_lsp {
     use lazy_static::lazy_static;
     use regex::{Captures, Regex};
     use tower_lsp::lsp_types::{
-        DiagnosticSeverity, InsertTextFormat, LanguageString, MarkedString,
+        DiagnosticRelatedInformation, DiagnosticSeverity, InsertTextFormat, LanguageString,
+        Location, MarkedString,
     };
     use typst::World;
     use typst_library::prelude::EcoString;
@@ -149,11 +164,14 @@ pub mod typst_to_lsp {
         typst_source: &TypstSource,
     ) -> LspPosition {
         let line_index = typst_source.byte_to_line(typst_offset).unwrap();
-        let column_index = typst_source.byte_to_column(typst_offset).unwrap();
 
         let lsp_line = line_index as u32;
         let lsp_column = match lsp_position_encoding {
-            LspPositionEncoding::Utf8 => column_index as LspCharacterOffset,
+            LspPositionEncoding::Utf8 => {
+                // Typst's columns are in characters, so compute the byte offset into the line
+                let byte_line_offset = typst_source.line_to_byte(line_index).unwrap();
+                (typst_offset - byte_line_offset) as LspCharacterOffset
+            }
             LspPositionEncoding::Utf16 => {
                 // See the implementation of `lsp_to_typst::position_to_offset` for discussion
                 // relevant to this function.
@@ -234,9 +252,14 @@ pub mod typst_to_lsp {
         const_config: &ConstConfig,
     ) -> (Url, LspDiagnostic) {
         let typst_span = typst_error.span;
-        let typst_source = world.source(typst_span.source());
-
-        let typst_range = typst_source.range(typst_span);
+        // Errors without a location, like some about the document as a whole, are shown at the
+        // start of the main source
+        let (typst_source, typst_range) = if typst_span == TypstSpan::detached() {
+            (world.main(), 0..0)
+        } else {
+            let typst_source = world.source(typst_span.source());
+            (typst_source, typst_source.range(typst_span))
+        };
         let lsp_range = range(typst_range, typst_source, const_config.position_encoding);
 
         let lsp_message = typst_error.message.to_string();
@@ -245,12 +268,45 @@ pub mod typst_to_lsp {
             range: lsp_range.raw_range,
             severity: Some(DiagnosticSeverity::ERROR),
             message: lsp_message,
+            related_information: related_information(typst_error, world, const_config),
             ..Default::default()
         };
 
-        let uri = path_to_uri(typst_source.path()).unwrap();
+        (source_uri(typst_source, world), diagnostic)
+    }
+
+    /// The calls, show rules and imports the error occurred in, so editors can link to them. The
+    /// innermost one comes first, like in Typst's CLI.
+    fn related_information(
+        typst_error: &TypstSourceError,
+        world: &WorkspaceWorld,
+        const_config: &ConstConfig,
+    ) -> Option<Vec<DiagnosticRelatedInformation>> {
+        let related_information = typst_error
+            .trace
+            .iter()
+            .filter(|tracepoint| tracepoint.span != TypstSpan::detached())
+            .map(|tracepoint| {
+                let typst_source = world.source(tracepoint.span.source());
+                let typst_range = typst_source.range(tracepoint.span);
+                let lsp_range = range(typst_range, typst_source, const_config.position_encoding);
+                DiagnosticRelatedInformation {
+                    location: Location::new(source_uri(typst_source, world), lsp_range.raw_range),
+                    message: tracepoint.v.to_string(),
+                }
+            })
+            .collect_vec();
+
+        (!related_information.is_empty()).then_some(related_information)
+    }
 
-        (uri, diagnostic)
+    fn source_uri(typst_source: &TypstSource, world: &WorkspaceWorld) -> LspUri {
+        // Sources which aren't files, like `untitled:` documents, have paths which don't exist.
+        // Detached sources, like text compiled on its own, have no path at all.
+        world
+            .source_uri(typst_source.id())
+            .or_else(|| path_to_uri(typst_source.path()).ok())
+            .unwrap_or_else(|| Url::parse("untitled:detached").expect("URI should be valid"))
     }
 
     pub fn source_errors_to_diagnostics<'a>(
@@ -278,8 +334,17 @@ pub mod typst_to_lsp {
 
 #[cfg(test)]
 mod test {
-    use crate::config::PositionEncoding;
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+
+    use tokio::sync::RwLock;
+
+    use crate::config::{ConstConfig, PositionEncoding};
     use crate::lsp_typst_boundary::lsp_to_typst;
+    use crate::lsp_typst_boundary::world::WorkspaceWorld;
+    use crate::test_utils::TempDir;
+    use crate::workspace::Workspace;
 
     use super::*;
 
@@ -368,4 +433,252 @@ mod test {
         assert_eq!(post_emoji_position, post_emoji_actual);
         assert_eq!(end_position, end_actual);
     }
+
+    #[test]
+    fn utf8_position_to_utf8_offset() {
+        let source = TypstSource::detached(ENCODING_TEST_STRING);
+
+        let emoji = LspPosition {
+            line: 0,
+            character: 5,
+        };
+        let post_emoji = LspPosition {
+            line: 0,
+            character: 9,
+        };
+        let end = LspPosition {
+            line: 0,
+            character: 14,
+        };
+
+        let emoji_offset = lsp_to_typst::position_to_offset(emoji, PositionEncoding::Utf8, &source);
+        let post_emoji_offset =
+            lsp_to_typst::position_to_offset(post_emoji, PositionEncoding::Utf8, &source);
+        let end_offset = lsp_to_typst::position_to_offset(end, PositionEncoding::Utf8, &source);
+
+        assert_eq!(emoji_offset, 5);
+        assert_eq!(post_emoji_offset, 9);
+        assert_eq!(end_offset, 14);
+    }
+
+    #[test]
+    fn utf8_position_past_line_end_is_clamped() {
+        let source = TypstSource::detached(format!("{ENCODING_TEST_STRING}\nnext"));
+
+        let past_end = LspPosition {
+            line: 0,
+            character: 20,
+        };
+        let past_end_offset =
+            lsp_to_typst::position_to_offset(past_end, PositionEncoding::Utf8, &source);
+
+        assert_eq!(past_end_offset, 14);
+    }
+
+    #[test]
+    fn utf8_position_inside_character_is_moved_to_its_start() {
+        let source = TypstSource::detached(ENCODING_TEST_STRING);
+
+        let inside_emoji = LspPosition {
+            line: 0,
+            character: 7,
+        };
+        let inside_emoji_offset =
+            lsp_to_typst::position_to_offset(inside_emoji, PositionEncoding::Utf8, &source);
+
+        assert_eq!(inside_emoji_offset, 5);
+    }
+
+    #[test]
+    fn utf8_offset_to_utf8_position() {
+        let source = TypstSource::detached(ENCODING_TEST_STRING);
+
+        let post_emoji_position = LspPosition {
+            line: 0,
+            character: 9,
+        };
+        let post_emoji_actual =
+            typst_to_lsp::offset_to_position(9, PositionEncoding::Utf8, &source);
+
+        let end_position = LspPosition {
+            line: 0,
+            character: 14,
+        };
+        let end_actual = typst_to_lsp::offset_to_position(14, PositionEncoding::Utf8, &source);
+
+        assert_eq!(post_emoji_position, post_emoji_actual);
+        assert_eq!(end_position, end_actual);
+    }
+
+    #[test]
+    fn multiline_range_round_trip() {
+        // "é" is 2 UTF-8 bytes and 1 UTF-16 code unit, "🥺" is 4 bytes and 2 code units
+        let source = TypstSource::detached("é 🥺\nété 🥺 x");
+        let typst_range = 13..18;
+
+        let utf16_range =
+            typst_to_lsp::range(typst_range.clone(), &source, PositionEncoding::Utf16);
+        assert_eq!(utf16_range.raw_range.start, LspPosition::new(1, 3));
+        assert_eq!(utf16_range.raw_range.end, LspPosition::new(1, 6));
+        assert_eq!(lsp_to_typst::range(&utf16_range, &source), typst_range);
+
+        let utf8_range = typst_to_lsp::range(typst_range.clone(), &source, PositionEncoding::Utf8);
+        assert_eq!(utf8_range.raw_range.start, LspPosition::new(1, 5));
+        assert_eq!(utf8_range.raw_range.end, LspPosition::new(1, 10));
+        assert_eq!(lsp_to_typst::range(&utf8_range, &source), typst_range);
+    }
+
+    /// Writes the files to a new directory, which is the root, and returns it along with the URI
+    /// of each file
+    fn write_files(test_name: &str, files: &[(&str, &str)]) -> (TempDir, HashMap<String, LspUri>) {
+        let dir = TempDir::new(test_name);
+        let mut uris = HashMap::new();
+        for (name, text) in files {
+            let path = dir.write(name, text);
+            uris.insert(name.to_string(), typst_to_lsp::path_to_uri(&path).unwrap());
+        }
+        (dir, uris)
+    }
+
+    /// Opens the main file in a workspace rooted at the directory, and gets a world compiling it
+    fn open_world(dir: &Path, main_uri: &LspUri) -> WorkspaceWorld {
+        let mut workspace = Workspace::default();
+        workspace.set_root(Some(dir.to_owned()), Vec::new());
+        let main_text = fs::read_to_string(main_uri.to_file_path().unwrap()).unwrap();
+        workspace.sources.insert_open(main_uri, main_text).unwrap();
+        let main = workspace.sources.get_id_by_uri(main_uri).unwrap();
+        let workspace = Arc::new(RwLock::new(workspace)).try_read_owned().unwrap();
+        WorkspaceWorld::new(workspace, main)
+    }
+
+    /// Writes the files to a new directory, then compiles `main.typ` with it open and returns the
+    /// diagnostics, along with the URI of each file
+    fn compile_diagnostics(
+        test_name: &str,
+        files: &[(&str, &str)],
+    ) -> (LspDiagnostics, HashMap<String, LspUri>) {
+        let (dir, uris) = write_files(test_name, files);
+        let world = open_world(&dir.path(), &uris["main.typ"]);
+
+        let const_config = ConstConfig {
+            position_encoding: PositionEncoding::Utf16,
+            root_paths: vec![dir.path()],
+            supports_work_done_progress: false,
+            supports_pull_diagnostics: false,
+            supports_diagnostic_refresh: false,
+        };
+        let errors = typst::compile(&world).expect_err("compiling should fail");
+        let diagnostics =
+            typst_to_lsp::source_errors_to_diagnostics(errors.iter(), &world, &const_config);
+        (diagnostics, uris)
+    }
+
+    /// Writes the files to a new directory, then checks whether `main` compiles with it open
+    fn compiles(test_name: &str, files: &[(&str, &str)], main: &str) -> bool {
+        let (dir, uris) = write_files(test_name, files);
+        let world = open_world(&dir.path(), &uris[main]);
+        typst::compile(&world).is_ok()
+    }
+
+    #[test]
+    fn root_relative_import_resolves_in_root() {
+        assert!(compiles(
+            "root-relative-import",
+            &[
+                (
+                    "chapters/intro.typ",
+                    "#import \"/lib/util.typ\": title\n#title",
+                ),
+                ("lib/util.typ", "#let title = [Intro]"),
+            ],
+            "chapters/intro.typ",
+        ));
+    }
+
+    #[test]
+    fn relative_import_resolves_next_to_file() {
+        assert!(compiles(
+            "relative-import",
+            &[
+                (
+                    "chapters/intro.typ",
+                    "#import \"../lib/util.typ\": title\n#include \"part.typ\"\n#title",
+                ),
+                ("chapters/part.typ", "Part"),
+                ("lib/util.typ", "#let title = [Intro]"),
+            ],
+            "chapters/intro.typ",
+        ));
+    }
+
+    #[test]
+    fn root_relative_import_prefers_root_over_file_system() {
+        // A file which doesn't compile at the absolute path, and one which does at the same path
+        // in the root, which the import means
+        let decoy_dir = TempDir::new("decoy");
+        let decoy_path = decoy_dir.write("decoy.typ", "#undefined");
+        let absolute = decoy_path.to_string_lossy().into_owned();
+        let main = format!("#include \"{absolute}\"");
+
+        let compiles = compiles(
+            "root-over-file-system",
+            &[
+                ("main.typ", &main),
+                (absolute.trim_start_matches('/'), "Part"),
+            ],
+            "main.typ",
+        );
+        assert!(compiles);
+    }
+
+    #[test]
+    fn error_in_main_goes_to_main() {
+        let (diagnostics, uris) = compile_diagnostics(
+            "error-in-main",
+            &[
+                (
+                    "main.typ",
+                    "#import \"chapter.typ\": value\n#(value + \"a\")",
+                ),
+                ("chapter.typ", "#let value = 1"),
+            ],
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        let main_diagnostics = &diagnostics[&uris["main.typ"]];
+        assert_eq!(main_diagnostics.len(), 1);
+        assert_eq!(main_diagnostics[0].range.start.line, 1);
+    }
+
+    #[test]
+    fn error_in_import_goes_to_imported_file() {
+        let (diagnostics, uris) = compile_diagnostics(
+            "error-in-import",
+            &[
+                ("main.typ", "#import \"chapter.typ\": value\n#value"),
+                ("chapter.typ", "Chapter\n#let value = 1 + \"a\""),
+            ],
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        let chapter_diagnostics = &diagnostics[&uris["chapter.typ"]];
+        assert_eq!(chapter_diagnostics.len(), 1);
+        assert_eq!(chapter_diagnostics[0].range.start.line, 1);
+    }
+
+    #[test]
+    fn error_in_include_goes_to_included_file() {
+        let (diagnostics, uris) = compile_diagnostics(
+            "error-in-include",
+            &[
+                ("main.typ", "= Title\n#include \"chapter.typ\""),
+                ("chapter.typ", "= Chapter\n\n#undefined"),
+            ],
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+        let chapter_diagnostics = &diagnostics[&uris["chapter.typ"]];
+        assert_eq!(chapter_diagnostics.len(), 1);
+        assert_eq!(chapter_diagnostics[0].range.start.line, 2);
+    }
 }
@@ -1,29 +1,305 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
 use comemo::Prehashed;
+use once_cell::sync::Lazy;
+use parking_lot::{Mutex, RwLock};
 use tokio::sync::OwnedRwLockReadGuard;
-use typst::diag::FileResult;
+use tower_lsp::lsp_types::Url;
+use typst::diag::{FileError, FileResult};
 use typst::eval::Library;
 use typst::font::{Font, FontBook};
 use typst::util::Buffer;
 use typst::World;
 
+use crate::workspace::font_manager::FontManager;
+use crate::workspace::package::PackageSpec;
+use crate::workspace::resource_manager::ResourceManager;
 use crate::workspace::source_manager::SourceId;
 use crate::workspace::Workspace;
 
-use super::{typst_to_lsp, TypstPath, TypstSource, TypstSourceId};
+use super::{typst_to_lsp, TypstPath, TypstPathOwned, TypstSource, TypstSourceId};
+
+/// Stands in for sources which aren't loaded when Typst asks for them
+static EMPTY_SOURCE: Lazy<TypstSource> = Lazy::new(|| TypstSource::detached(""));
 
+#[derive(Clone)]
 pub struct WorkspaceWorld {
-    workspace: OwnedRwLockReadGuard<Workspace>,
-    main: SourceId,
+    workspace: Arc<OwnedRwLockReadGuard<Workspace>>,
+    /// The main source, or `None` for worlds whose main source is `detached_main`
+    main: Option<SourceId>,
+    /// The main source of worlds compiling snippets on their own, which isn't in the workspace
+    detached_main: Option<TypstSource>,
+    /// Sources Typst asked for which weren't loaded, and were replaced with an empty one. Shared
+    /// with clones, and with snapshots, which compilations run on.
+    missing_sources: Arc<Mutex<HashSet<SourceId>>>,
 }
 
 impl WorkspaceWorld {
     pub fn new(workspace: OwnedRwLockReadGuard<Workspace>, main: SourceId) -> Self {
-        Self { workspace, main }
+        Self {
+            workspace: Arc::new(workspace),
+            main: Some(main),
+            detached_main: None,
+            missing_sources: Default::default(),
+        }
+    }
+
+    /// Gets a world whose main source is the text, which isn't a file in the workspace. Given a
+    /// file, the text stands in for it, taking its id and path, so that relative paths resolve
+    /// like in the file; Typst doesn't let detached sources access files. Without a file, like in
+    /// Typst's playground, relative imports and file loads fail.
+    pub fn new_detached(
+        workspace: impl Into<Arc<OwnedRwLockReadGuard<Workspace>>>,
+        text: String,
+        file: Option<&TypstSource>,
+    ) -> Self {
+        let detached_main = match file {
+            Some(file) => TypstSource::new(file.id(), file.path(), text),
+            None => TypstSource::detached(text),
+        };
+        Self {
+            workspace: workspace.into(),
+            main: None,
+            detached_main: Some(detached_main),
+            missing_sources: Default::default(),
+        }
     }
 
-    pub fn get_workspace(&self) -> &OwnedRwLockReadGuard<Workspace> {
+    /// Gets a world sharing this one's workspace, with a different main source
+    pub fn with_main(&self, main: SourceId) -> Self {
+        Self {
+            workspace: Arc::clone(&self.workspace),
+            main: Some(main),
+            detached_main: None,
+            missing_sources: Default::default(),
+        }
+    }
+
+    pub fn get_workspace(&self) -> &Arc<OwnedRwLockReadGuard<Workspace>> {
         &self.workspace
     }
+
+    /// Gets the id of the main source, or `None` if it isn't in the workspace
+    pub fn main_id(&self) -> Option<SourceId> {
+        self.main
+    }
+
+    /// Gets the sources which were compiled as empty since they weren't loaded, and forgets them
+    pub fn take_missing_sources(&self) -> Vec<SourceId> {
+        self.missing_sources.lock().drain().collect()
+    }
+
+    /// Gets the URI the client knows the source by, or `None` for a detached main source, even if
+    /// it stands in for a file
+    pub fn source_uri(&self, typst_id: TypstSourceId) -> Option<Url> {
+        if self
+            .detached_main
+            .as_ref()
+            .map_or(false, |detached_main| detached_main.id() == typst_id)
+        {
+            return None;
+        }
+        let sources = &self.get_workspace().sources;
+        match sources.get_source_by_id(typst_id.into()) {
+            Some(source) => Some(source.uri().clone()),
+            None => sources.get_uri_by_id(typst_id.into()),
+        }
+    }
+
+    /// Gets a copy of the world holding the sources with the ids, along with the main source,
+    /// which doesn't lock the workspace. Sources which aren't loaded are left out.
+    pub fn snapshot(&self, source_ids: &HashSet<SourceId>) -> WorldSnapshot {
+        let workspace = self.get_workspace();
+        let mut sources = HashMap::new();
+        let mut ids = HashMap::new();
+        for &id in source_ids {
+            let Some(source) = workspace.sources.get_source_by_id(id) else {
+                continue;
+            };
+            if let Some(uri) = workspace.sources.get_uri_by_id(id) {
+                ids.insert(uri, id.into());
+            }
+            sources.insert(id.into(), source.as_ref().clone());
+        }
+        let main = self.main().clone();
+        WorldSnapshot {
+            library: Arc::clone(&workspace.typst_stdlib),
+            fonts: Arc::clone(&workspace.fonts),
+            resources: Arc::clone(&workspace.resources),
+            root: workspace.get_root(main.path()),
+            main,
+            main_id: self.main,
+            sources,
+            ids,
+            missing_sources: Arc::clone(&self.missing_sources),
+            unresolved: Default::default(),
+        }
+    }
+
+    fn get_root(&self) -> Option<PathBuf> {
+        self.get_workspace().get_root(self.main().path())
+    }
+}
+
+/// A copy of what compiling needs from a world, which doesn't hold a lock on the workspace.
+/// Typst can't be interrupted, so a compilation which is given up on runs until it ends, and
+/// compiling a snapshot keeps it from blocking changes to the workspace meanwhile. Sources Typst
+/// asks for which aren't in the snapshot are recorded, so that they can be loaded and compiled
+/// with in the next snapshot.
+pub struct WorldSnapshot {
+    library: Arc<Prehashed<Library>>,
+    fonts: Arc<FontManager>,
+    resources: Arc<RwLock<ResourceManager>>,
+    root: Option<PathBuf>,
+    main: TypstSource,
+    /// The id of the main source in the workspace, or `None` for a detached main source
+    main_id: Option<SourceId>,
+    sources: HashMap<TypstSourceId, TypstSource>,
+    /// Ids of the sources, by the URI they're stored under
+    ids: HashMap<Url, TypstSourceId>,
+    missing_sources: Arc<Mutex<HashSet<SourceId>>>,
+    /// URIs of the sources Typst asked for which weren't in the snapshot
+    unresolved: Arc<Mutex<Vec<Url>>>,
+}
+
+impl WorldSnapshot {
+    /// Gets the URIs of the sources Typst asked for which weren't in the snapshot, which are
+    /// shared with the snapshot while it's compiled
+    pub fn unresolved(&self) -> Arc<Mutex<Vec<Url>>> {
+        Arc::clone(&self.unresolved)
+    }
+}
+
+impl World for WorldSnapshot {
+    fn library(&self) -> &Prehashed<Library> {
+        &self.library
+    }
+
+    fn main(&self) -> &TypstSource {
+        &self.main
+    }
+
+    fn resolve(&self, typst_path: &TypstPath) -> FileResult<TypstSourceId> {
+        let typst_path = resolve_import(self.root.as_deref(), typst_path)?;
+        let lsp_uri = typst_to_lsp::path_to_uri(&typst_path)
+            .map_err(|_| FileError::NotFound(typst_path.clone()))?;
+        match self.ids.get(&lsp_uri) {
+            Some(&id) => Ok(id),
+            None => {
+                self.unresolved.lock().push(lsp_uri);
+                Err(FileError::NotFound(typst_path))
+            }
+        }
+    }
+
+    fn source(&self, typst_id: TypstSourceId) -> &TypstSource {
+        if self.main.id() == typst_id {
+            return &self.main;
+        }
+        match self.sources.get(&typst_id) {
+            Some(source) => source,
+            None => {
+                self.missing_sources.lock().insert(typst_id.into());
+                &EMPTY_SOURCE
+            }
+        }
+    }
+
+    fn book(&self) -> &Prehashed<FontBook> {
+        self.fonts.book()
+    }
+
+    fn font(&self, id: usize) -> Option<Font> {
+        self.fonts.font(id, &mut self.resources.write())
+    }
+
+    fn file(&self, typst_path: &TypstPath) -> FileResult<Buffer> {
+        load_file(
+            &self.resources,
+            self.root.as_deref(),
+            self.main_id,
+            typst_path,
+        )
+    }
+}
+
+/// Typst turns paths starting with `/` into absolute paths on disk, but they're meant to be
+/// relative to the project root, like with Typst's CLI. Relative paths are joined to the
+/// directory of the file using them, so they're already in the root. Other paths are looked up
+/// in the root, and only used as they are if they aren't found there.
+fn resolve_in_root(root: Option<&Path>, typst_path: &TypstPath) -> TypstPathOwned {
+    let Some(root) = root else {
+        return typst_path.to_owned();
+    };
+    if !typst_path.is_absolute() || typst_path.starts_with(root) {
+        return typst_path.to_owned();
+    }
+    let relative_path: TypstPathOwned = typst_path
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_) | Component::ParentDir))
+        .collect();
+    let root_path = root.join(relative_path);
+    if root_path.exists() {
+        root_path
+    } else {
+        typst_path.to_owned()
+    }
+}
+
+/// Gets the path of the file the path in an import, include or call loading a file in the source
+/// refers to, resolved like while compiling: relative to the source's directory, in the root for
+/// paths starting with `/`, and to the entrypoint for packages
+pub fn resolve_source_path(
+    workspace: &Workspace,
+    source: &TypstSource,
+    path: &str,
+) -> FileResult<TypstPathOwned> {
+    let source_path = source.path();
+    let dir = source_path
+        .parent()
+        .ok_or_else(|| FileError::NotFound(path.into()))?;
+    let mut joined_path = TypstPathOwned::new();
+    // Going through the components also drops any `.` in the path
+    for component in dir.join(path).components() {
+        match component {
+            Component::ParentDir => {
+                joined_path.pop();
+            }
+            component => joined_path.push(component),
+        }
+    }
+    resolve_import(workspace.get_root(source_path).as_deref(), &joined_path)
+}
+
+/// Gets the path of the file an import refers to, which is the entrypoint of a package for
+/// package imports
+fn resolve_import(root: Option<&Path>, typst_path: &TypstPath) -> FileResult<TypstPathOwned> {
+    match PackageSpec::from_import_path(typst_path) {
+        Some(package) => package.resolve_entrypoint(),
+        None => Ok(resolve_in_root(root, typst_path)),
+    }
+}
+
+/// Loads the file for Typst, recording that the main source depends on it, so that it's
+/// recompiled when the file changes
+fn load_file(
+    resources: &RwLock<ResourceManager>,
+    root: Option<&Path>,
+    main: Option<SourceId>,
+    typst_path: &TypstPath,
+) -> FileResult<Buffer> {
+    let typst_path = resolve_in_root(root, typst_path);
+    let lsp_uri = typst_to_lsp::path_to_uri(&typst_path)
+        .map_err(|_| FileError::NotFound(typst_path.clone()))?;
+    let mut resources = resources.write();
+    // Detached sources aren't in the workspace, so they're never recompiled when it changes
+    if let Some(main) = main {
+        resources.add_dependent(&lsp_uri, main);
+    }
+    let lsp_resource = resources.get_or_insert_resource(lsp_uri)?;
+    Ok(lsp_resource.into())
 }
 
 impl World for WorkspaceWorld {
@@ -33,20 +309,37 @@ impl World for WorkspaceWorld {
     }
 
     fn main(&self) -> &TypstSource {
-        self.source(self.main.into())
+        match (&self.detached_main, self.main) {
+            (Some(detached_main), _) => detached_main,
+            (None, Some(main)) => self.source(main.into()),
+            (None, None) => unreachable!("worlds should have a main source"),
+        }
     }
 
     fn resolve(&self, typst_path: &TypstPath) -> FileResult<TypstSourceId> {
-        let lsp_uri = typst_to_lsp::path_to_uri(typst_path).unwrap();
+        let typst_path = resolve_import(self.get_root().as_deref(), typst_path)?;
+        let lsp_uri = typst_to_lsp::path_to_uri(&typst_path)
+            .map_err(|_| FileError::NotFound(typst_path.clone()))?;
         self.get_workspace().sources.cache(lsp_uri).map(Into::into)
     }
 
     fn source(&self, typst_id: TypstSourceId) -> &TypstSource {
-        let lsp_source = self
+        if let Some(detached_main) = &self.detached_main {
+            if detached_main.id() == typst_id {
+                return detached_main;
+            }
+        }
+        match self
             .get_workspace()
             .sources
-            .get_open_source_by_id(typst_id.into());
-        lsp_source.as_ref()
+            .get_source_by_id(typst_id.into())
+        {
+            Some(lsp_source) => lsp_source.as_ref(),
+            None => {
+                self.missing_sources.lock().insert(typst_id.into());
+                &EMPTY_SOURCE
+            }
+        }
     }
 
     fn book(&self) -> &Prehashed<FontBook> {
@@ -59,9 +352,12 @@ impl World for WorkspaceWorld {
     }
 
     fn file(&self, typst_path: &TypstPath) -> FileResult<Buffer> {
-        let lsp_uri = typst_to_lsp::path_to_uri(typst_path).unwrap();
-        let mut resources = self.get_workspace().resources.write();
-        let lsp_resource = resources.get_or_insert_resource(lsp_uri)?;
-        Ok(lsp_resource.into())
+        let workspace = self.get_workspace();
+        load_file(
+            &workspace.resources,
+            self.get_root().as_deref(),
+            self.main,
+            typst_path,
+        )
     }
 }
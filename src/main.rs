@@ -6,6 +6,8 @@ mod config;
 mod ext;
 mod lsp_typst_boundary;
 mod server;
+#[cfg(test)]
+mod test_utils;
 mod workspace;
 
 #[tokio::main]
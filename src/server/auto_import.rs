@@ -0,0 +1,103 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::sync::Arc;
+
+use comemo::Prehashed;
+use tower_lsp::lsp_types::MessageType;
+use typst::eval::{Module, Value};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::typst_to_lsp;
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::workspace::package::PackageSpec;
+use crate::workspace::source::Source;
+use crate::workspace::source_manager::SourceId;
+use crate::workspace::Workspace;
+
+use super::TypstServer;
+
+impl TypstServer {
+    /// Rebuilds the library every source is compiled with from the inputs, then evaluates the
+    /// auto-imported files and packages and adds the names they define to it, as if each source
+    /// started with `#import ..: *`. Names in later auto-imports take precedence over earlier ones
+    /// and the standard library.
+    pub async fn update_library(&self, auto_imports: &[String], inputs: &BTreeMap<String, String>) {
+        let mut imported: Vec<(String, String, Value)> = Vec::new();
+        for spec in auto_imports {
+            match self.eval_auto_import(spec).await {
+                Ok(module) => imported.extend(
+                    module
+                        .scope()
+                        .iter()
+                        .map(|(name, value)| (spec.clone(), name.to_string(), value.clone())),
+                ),
+                Err(error) => {
+                    self.client
+                        .show_message(
+                            MessageType::WARNING,
+                            format!("Could not auto-import {spec}: {error}"),
+                        )
+                        .await
+                }
+            }
+        }
+
+        // Start over from the standard library, so removed auto-imports go away
+        let mut library = Workspace::build_library(inputs);
+        let mut global = library.global.scope().clone();
+        let mut origins = HashMap::new();
+        for (spec, name, value) in imported {
+            global.define(name.clone(), value);
+            origins.insert(name, spec);
+        }
+        library.global = Module::new(library.global.name().clone()).with_scope(global);
+
+        let mut workspace = self.workspace.write().await;
+        workspace.typst_stdlib = Arc::new(Prehashed::new(library));
+        workspace.sources.clear_documents();
+        workspace.set_auto_imports(origins);
+    }
+
+    /// Gets the auto-import the identifier's name comes from, if it isn't bound in the workspace
+    pub fn get_auto_import(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        ident: &LinkedNode,
+    ) -> Option<String> {
+        if !matches!(ident.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent) {
+            return None;
+        }
+        if self.resolve_definition(world, source, ident).is_some() {
+            return None;
+        }
+        world
+            .get_workspace()
+            .get_auto_import(ident.text())
+            .map(ToOwned::to_owned)
+    }
+
+    async fn eval_auto_import(&self, spec: &str) -> Result<Module, String> {
+        let path = match PackageSpec::from_import_path(Path::new(spec)) {
+            Some(package) => package
+                .resolve_entrypoint()
+                .map_err(|error| format!("{error:?}"))?,
+            None => self.resolve_workspace_path(Path::new(spec)),
+        };
+        let uri = typst_to_lsp::path_to_uri(&path)
+            .map_err(|_| format!("{} doesn't exist", path.display()))?;
+
+        let id: SourceId = self
+            .workspace
+            .read()
+            .await
+            .sources
+            .cache(uri)
+            .map_err(|error| format!("{error:?}"))?;
+
+        let world = self.get_world_with_main(id).await;
+        let source = world.get_workspace().sources.get_open_source_by_id(id);
+        let (module, _) = self.eval_source(&world, source);
+        module.ok_or_else(|| "it has errors".to_owned())
+    }
+}
@@ -0,0 +1,212 @@
+use tower_lsp::lsp_types::{
+    CallHierarchyIncomingCall, CallHierarchyItem, CallHierarchyOutgoingCall, SymbolKind,
+};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, LspRawRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    /// Gets the function defined by, or called through, the identifier at the position
+    pub fn prepare_call_hierarchy(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<Vec<CallHierarchyItem>> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        let leaf = self.get_leaf(source, typst_offset)?;
+        if !matches!(leaf.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent) {
+            return None;
+        }
+
+        let (definition_source, definition) = self.resolve_definition(world, source, &leaf)?;
+        let item = self.function_item(definition_source, &definition)?;
+        Some(vec![item])
+    }
+
+    /// Gets the calls to the item's function, grouped by the function they're made from. Calls
+    /// made outside of any function are grouped under their file.
+    pub fn get_incoming_calls(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        item: &CallHierarchyItem,
+    ) -> Option<Vec<CallHierarchyIncomingCall>> {
+        let definition = self.get_item_definition(source, item)?;
+        let references = self.find_references(world, source, &definition, false)?;
+
+        let mut incoming_calls: Vec<CallHierarchyIncomingCall> = Vec::new();
+        for (reference_source, reference) in references {
+            if !self.is_callee(&reference) {
+                continue;
+            }
+            let Some(caller) = self
+                .get_enclosing_function(&reference)
+                .and_then(|caller| self.function_item(reference_source, &caller))
+                .or_else(|| self.file_item(reference_source))
+            else {
+                continue;
+            };
+            let range = self.call_hierarchy_range(reference_source, &reference);
+
+            match incoming_calls
+                .iter_mut()
+                .find(|call| Self::is_same_item(&call.from, &caller))
+            {
+                Some(call) => call.from_ranges.push(range),
+                None => incoming_calls.push(CallHierarchyIncomingCall {
+                    from: caller,
+                    from_ranges: vec![range],
+                }),
+            }
+        }
+
+        Some(incoming_calls)
+    }
+
+    /// Gets the functions called from the body of the item's function, grouped by the function
+    /// being called. Recursive calls show up as calls to the function itself.
+    pub fn get_outgoing_calls(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        item: &CallHierarchyItem,
+    ) -> Option<Vec<CallHierarchyOutgoingCall>> {
+        let definition = self.get_item_definition(source, item)?;
+        let binding = self.get_function_binding(&definition)?;
+
+        let mut outgoing_calls: Vec<CallHierarchyOutgoingCall> = Vec::new();
+        for callee in self.get_descendants(&binding) {
+            if !matches!(callee.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent)
+                || !self.is_callee(&callee)
+            {
+                continue;
+            }
+            let Some(called) = self
+                .resolve_definition(world, source, &callee)
+                .and_then(|(called_source, called)| self.function_item(called_source, &called))
+            else {
+                continue;
+            };
+            let range = self.call_hierarchy_range(source, &callee);
+
+            match outgoing_calls
+                .iter_mut()
+                .find(|call| Self::is_same_item(&call.to, &called))
+            {
+                Some(call) => call.from_ranges.push(range),
+                None => outgoing_calls.push(CallHierarchyOutgoingCall {
+                    to: called,
+                    from_ranges: vec![range],
+                }),
+            }
+        }
+
+        Some(outgoing_calls)
+    }
+
+    /// Gets the identifier naming the item's function, which the item's selection range covers
+    fn get_item_definition<'a>(
+        &self,
+        source: &'a Source,
+        item: &CallHierarchyItem,
+    ) -> Option<LinkedNode<'a>> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            item.selection_range.start,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        self.get_leaf(source, typst_offset + 1)
+            .filter(|leaf| leaf.kind() == SyntaxKind::Ident)
+    }
+
+    /// Gets the `let` binding defining a function with the identifier as its name
+    pub fn get_function_binding<'a>(&self, definition: &LinkedNode<'a>) -> Option<LinkedNode<'a>> {
+        let closure = definition
+            .parent()
+            .filter(|parent| parent.kind() == SyntaxKind::Closure)?;
+        closure
+            .parent()
+            .filter(|parent| parent.kind() == SyntaxKind::LetBinding)
+            .cloned()
+    }
+
+    /// Gets the name of the innermost function definition containing the node
+    fn get_enclosing_function<'a>(&self, node: &LinkedNode<'a>) -> Option<LinkedNode<'a>> {
+        let mut ancestor = node.parent();
+        while let Some(parent) = ancestor {
+            if parent.kind() == SyntaxKind::Closure {
+                let name = parent
+                    .children()
+                    .take_while(|child| child.kind() != SyntaxKind::Params)
+                    .find(|child| child.kind() == SyntaxKind::Ident);
+                if let Some(name) = name {
+                    return Some(name);
+                }
+            }
+            ancestor = parent.parent();
+        }
+        None
+    }
+
+    fn is_callee(&self, ident: &LinkedNode) -> bool {
+        ident.prev_sibling().is_none()
+            && ident
+                .parent()
+                .map_or(false, |parent| parent.kind() == SyntaxKind::FuncCall)
+    }
+
+    fn function_item(&self, source: &Source, definition: &LinkedNode) -> Option<CallHierarchyItem> {
+        let binding = self.get_function_binding(definition)?;
+        let uri = source.uri().clone();
+
+        Some(CallHierarchyItem {
+            name: definition.text().to_string(),
+            kind: SymbolKind::FUNCTION,
+            tags: None,
+            detail: None,
+            uri,
+            range: self.call_hierarchy_range(source, &binding),
+            selection_range: self.call_hierarchy_range(source, definition),
+            data: None,
+        })
+    }
+
+    fn file_item(&self, source: &Source) -> Option<CallHierarchyItem> {
+        let path = source.as_ref().path();
+        let uri = source.uri().clone();
+        let range = self.call_hierarchy_range(source, &LinkedNode::new(source.as_ref().root()));
+
+        Some(CallHierarchyItem {
+            name: path.file_name()?.to_string_lossy().into_owned(),
+            kind: SymbolKind::FILE,
+            tags: None,
+            detail: None,
+            uri,
+            range,
+            selection_range: LspRawRange::default(),
+            data: None,
+        })
+    }
+
+    fn is_same_item(item: &CallHierarchyItem, other: &CallHierarchyItem) -> bool {
+        item.uri == other.uri && item.selection_range == other.selection_range
+    }
+
+    fn call_hierarchy_range(&self, source: &Source, node: &LinkedNode) -> LspRawRange {
+        typst_to_lsp::range(
+            node.range(),
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        )
+        .raw_range
+    }
+}
@@ -0,0 +1,56 @@
+use serde_json::Value as JsonValue;
+use tower_lsp::lsp_types::{CodeLens, Command, Url};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::LspRawRange;
+use crate::workspace::source::Source;
+
+use super::command::LspCommand;
+use super::TypstServer;
+
+impl TypstServer {
+    /// Gets the lenses shown above the first line of the source, if it looks like the root of a
+    /// document rather than a file meant to be imported
+    pub fn get_code_lenses(&self, source: &Source, uri: &Url) -> Vec<CodeLens> {
+        if !self.is_document_root(source) {
+            return Vec::new();
+        }
+
+        let export_pdf = Command {
+            title: "▶ Export PDF".to_owned(),
+            command: LspCommand::ExportPdf.into(),
+            arguments: Some(vec![JsonValue::String(uri.to_string())]),
+        };
+
+        vec![CodeLens {
+            range: LspRawRange::default(),
+            command: Some(export_pdf),
+            data: None,
+        }]
+    }
+
+    /// Whether the source sets up the page or has content at its top level, which files that
+    /// only define functions and variables for others to import don't
+    pub fn is_document_root(&self, source: &Source) -> bool {
+        let root = LinkedNode::new(source.as_ref().root());
+        root.children().any(|child| match child.kind() {
+            SyntaxKind::SetRule => Self::is_page_set_rule(&child),
+            SyntaxKind::Space
+            | SyntaxKind::Parbreak
+            | SyntaxKind::LineComment
+            | SyntaxKind::BlockComment
+            | SyntaxKind::Hash
+            | SyntaxKind::LetBinding
+            | SyntaxKind::ShowRule
+            | SyntaxKind::ModuleImport => false,
+            _ => true,
+        })
+    }
+
+    fn is_page_set_rule(set_rule: &LinkedNode) -> bool {
+        set_rule
+            .children()
+            .find(|child| child.kind() == SyntaxKind::Ident)
+            .map_or(false, |target| target.text() == "page")
+    }
+}
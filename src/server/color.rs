@@ -0,0 +1,273 @@
+use tower_lsp::lsp_types::{Color, ColorInformation, ColorPresentation, TextEdit};
+use typst::eval::Value;
+use typst::syntax::{LinkedNode, SyntaxKind};
+use typst::World;
+
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// How a color is written in the source, so that edits can keep the same style
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColorStyle {
+    /// `rgb("#aabbcc")`
+    Hex,
+    /// `rgb(170, 187, 204)`
+    Rgb,
+    /// `cmyk(10%, 20%, 30%, 40%)`
+    Cmyk,
+    /// `luma(170)`
+    Luma,
+    /// `red`
+    Named,
+}
+
+impl TypstServer {
+    pub fn get_document_colors(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+    ) -> Vec<ColorInformation> {
+        let root = LinkedNode::new(source.as_ref().root());
+        self.get_descendants(&root)
+            .iter()
+            .filter_map(|node| {
+                let (_, color) = Self::parse_color(world, node)?;
+                let lsp_range = typst_to_lsp::range(
+                    node.range(),
+                    source.as_ref(),
+                    self.get_const_config().position_encoding,
+                );
+                Some(ColorInformation {
+                    range: lsp_range.raw_range,
+                    color,
+                })
+            })
+            .collect()
+    }
+
+    /// Gets the ways to write `color` in place of the color at `range`, starting with the
+    /// constructor it's currently written with
+    pub fn get_color_presentations(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        range: LspRange,
+        color: Color,
+    ) -> Vec<ColorPresentation> {
+        let typst_range = lsp_to_typst::range(&range, source.as_ref());
+        let root = LinkedNode::new(source.as_ref().root());
+        let style = self
+            .get_descendants(&root)
+            .iter()
+            .filter(|node| node.range() == typst_range)
+            .find_map(|node| Self::parse_color(world, node))
+            .map_or(ColorStyle::Hex, |(style, _)| style);
+
+        let mut styles = vec![style, ColorStyle::Hex, ColorStyle::Rgb, ColorStyle::Cmyk];
+        styles.dedup();
+
+        let mut labels: Vec<String> = Vec::new();
+        for style in styles {
+            if let Some(label) = Self::format_color(color, style) {
+                if !labels.contains(&label) {
+                    labels.push(label);
+                }
+            }
+        }
+
+        labels
+            .into_iter()
+            .map(|label| ColorPresentation {
+                text_edit: Some(TextEdit {
+                    range: range.raw_range,
+                    new_text: label.clone(),
+                }),
+                label,
+                additional_text_edits: None,
+            })
+            .collect()
+    }
+
+    fn parse_color(world: &WorkspaceWorld, node: &LinkedNode) -> Option<(ColorStyle, Color)> {
+        match node.kind() {
+            SyntaxKind::FuncCall => Self::parse_color_constructor(node),
+            SyntaxKind::Ident => {
+                // Only identifiers used as values, not the names of named arguments
+                let is_name = node.parent().map_or(false, |parent| {
+                    parent.kind() == SyntaxKind::Named && node.prev_sibling().is_none()
+                });
+                if is_name {
+                    return None;
+                }
+                let Some(Value::Color(color)) = world.library().global.scope().get(node.text())
+                else {
+                    return None;
+                };
+                let rgba = color.to_rgba();
+                let color = Self::lsp_color(
+                    [rgba.r, rgba.g, rgba.b].map(|component| component as f32 / 255.0),
+                    rgba.a as f32 / 255.0,
+                );
+                Some((ColorStyle::Named, color))
+            }
+            _ => None,
+        }
+    }
+
+    fn parse_color_constructor(call: &LinkedNode) -> Option<(ColorStyle, Color)> {
+        let callee = call.children().next()?;
+        if callee.kind() != SyntaxKind::Ident {
+            return None;
+        }
+        let args = call
+            .children()
+            .find(|child| child.kind() == SyntaxKind::Args)?;
+        let args: Vec<_> = args
+            .children()
+            .filter(|arg| {
+                !arg.kind().is_trivia()
+                    && !matches!(
+                        arg.kind(),
+                        SyntaxKind::LeftParen | SyntaxKind::RightParen | SyntaxKind::Comma
+                    )
+            })
+            .collect();
+
+        match (callee.text().as_str(), args.as_slice()) {
+            ("rgb", [hex]) if hex.kind() == SyntaxKind::Str => {
+                let (rgb, alpha) = Self::parse_hex(hex.text().trim_matches('"'))?;
+                Some((ColorStyle::Hex, Self::lsp_color(rgb, alpha)))
+            }
+            ("rgb", [red, green, blue]) => {
+                let rgb = [red, green, blue].map(Self::parse_component);
+                let [Some(red), Some(green), Some(blue)] = rgb else {
+                    return None;
+                };
+                Some((ColorStyle::Rgb, Self::lsp_color([red, green, blue], 1.0)))
+            }
+            ("rgb", [red, green, blue, alpha]) => {
+                let rgba = [red, green, blue, alpha].map(Self::parse_component);
+                let [Some(red), Some(green), Some(blue), Some(alpha)] = rgba else {
+                    return None;
+                };
+                Some((ColorStyle::Rgb, Self::lsp_color([red, green, blue], alpha)))
+            }
+            ("cmyk", [cyan, magenta, yellow, key]) => {
+                let cmyk = [cyan, magenta, yellow, key].map(Self::parse_ratio);
+                let [Some(cyan), Some(magenta), Some(yellow), Some(key)] = cmyk else {
+                    return None;
+                };
+                let rgb = [cyan, magenta, yellow].map(|component| (1.0 - component) * (1.0 - key));
+                Some((ColorStyle::Cmyk, Self::lsp_color(rgb, 1.0)))
+            }
+            ("luma", [gray]) => {
+                let gray = Self::parse_component(gray)?;
+                Some((ColorStyle::Luma, Self::lsp_color([gray; 3], 1.0)))
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses hex notation like `#abc`, `#abcd`, `#aabbcc` or `#aabbccdd`, with an optional `#`
+    fn parse_hex(hex: &str) -> Option<([f32; 3], f32)> {
+        let hex = hex.strip_prefix('#').unwrap_or(hex);
+        if !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+
+        let digits: Vec<u8> = match hex.len() {
+            3 | 4 => hex
+                .chars()
+                .map(|c| u8::from_str_radix(&c.to_string().repeat(2), 16).ok())
+                .collect::<Option<_>>()?,
+            6 | 8 => (0..hex.len())
+                .step_by(2)
+                .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).ok())
+                .collect::<Option<_>>()?,
+            _ => return None,
+        };
+
+        let component = |index: usize| digits[index] as f32 / 255.0;
+        let alpha = digits.get(3).map_or(1.0, |alpha| *alpha as f32 / 255.0);
+        Some(([component(0), component(1), component(2)], alpha))
+    }
+
+    /// Parses an integer from 0 to 255 or a ratio into a value from 0 to 1
+    fn parse_component(node: &LinkedNode) -> Option<f32> {
+        match node.kind() {
+            SyntaxKind::Int => {
+                let value: u8 = node.text().parse().ok()?;
+                Some(value as f32 / 255.0)
+            }
+            _ => Self::parse_ratio(node),
+        }
+    }
+
+    fn parse_ratio(node: &LinkedNode) -> Option<f32> {
+        if node.kind() != SyntaxKind::Numeric {
+            return None;
+        }
+        let percent: f32 = node.text().strip_suffix('%')?.parse().ok()?;
+        Some((percent / 100.0).clamp(0.0, 1.0))
+    }
+
+    fn lsp_color([red, green, blue]: [f32; 3], alpha: f32) -> Color {
+        Color {
+            red,
+            green,
+            blue,
+            alpha,
+        }
+    }
+
+    /// Writes the color in the given style, or `None` if the style can't represent it
+    fn format_color(color: Color, style: ColorStyle) -> Option<String> {
+        let to_u8 = |component: f32| (component.clamp(0.0, 1.0) * 255.0).round() as u8;
+        let [red, green, blue, alpha] =
+            [color.red, color.green, color.blue, color.alpha].map(to_u8);
+        let is_opaque = alpha == u8::MAX;
+
+        match style {
+            ColorStyle::Hex | ColorStyle::Named => {
+                let mut hex = format!("#{red:02x}{green:02x}{blue:02x}");
+                if !is_opaque {
+                    hex.push_str(&format!("{alpha:02x}"));
+                }
+                Some(format!("rgb(\"{hex}\")"))
+            }
+            ColorStyle::Rgb if is_opaque => Some(format!("rgb({red}, {green}, {blue})")),
+            ColorStyle::Rgb => Some(format!("rgb({red}, {green}, {blue}, {alpha})")),
+            ColorStyle::Luma if is_opaque && red == green && green == blue => {
+                Some(format!("luma({red})"))
+            }
+            ColorStyle::Cmyk if is_opaque => {
+                let [red, green, blue] =
+                    [color.red, color.green, color.blue].map(|component| component.clamp(0.0, 1.0));
+                let key = 1.0 - red.max(green).max(blue);
+                let [cyan, magenta, yellow] = [red, green, blue].map(|component| {
+                    if key < 1.0 {
+                        (1.0 - component - key) / (1.0 - key)
+                    } else {
+                        0.0
+                    }
+                });
+                let percent = |ratio: f32| {
+                    let percent = format!("{:.1}", ratio * 100.0);
+                    let percent = percent.trim_end_matches(".0");
+                    format!("{percent}%")
+                };
+                Some(format!(
+                    "cmyk({}, {}, {}, {})",
+                    percent(cyan),
+                    percent(magenta),
+                    percent(yellow),
+                    percent(key)
+                ))
+            }
+            ColorStyle::Luma | ColorStyle::Cmyk => None,
+        }
+    }
+}
@@ -1,20 +1,72 @@
-use serde_json::Value;
+use std::ops::RangeInclusive;
+use std::sync::Arc;
+use std::time::Instant;
+
+use serde_json::{json, Value};
 use tower_lsp::{
-    jsonrpc::{Error, Result},
-    lsp_types::Url,
+    jsonrpc::{Error, ErrorCode, Result},
+    lsp_types::{DiagnosticSeverity, MessageType, Url},
 };
+use typst::geom::Color;
+
+use crate::config::PdfStandard;
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{lsp_to_typst, LspRange, LspRawRange, TypstSourceId};
+use crate::workspace::font_manager::FontOrigin;
+use crate::workspace::source_manager::{SourceId, SourceManager};
 
+use super::compile_selection::SelectionRender;
+use super::export::POINTS_PER_INCH;
 use super::TypstServer;
 
+/// Version of the `typst` crate typst-lsp is built with, which has to match the tag of the
+/// dependency in `Cargo.toml`
+const TYPST_VERSION: &str = "0.2.0";
+
+/// Formats documents can be exported to
+const EXPORT_FORMATS: [&str; 2] = ["pdf", "png"];
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LspCommand {
     ExportPdf,
+    ExportPdfa,
+    ExportPdfBytes,
+    ExportPng,
+    ExportPages,
+    ExportAll,
+    ListFonts,
+    Check,
+    DebugWorld,
+    DebugAst,
+    Version,
+    ReloadConfig,
+    SetMainFile,
+    ForgetSource,
+    IndexWorkspace,
+    CompileSelection,
+    CompileString,
 }
 
 impl From<LspCommand> for String {
     fn from(command: LspCommand) -> Self {
         match command {
             LspCommand::ExportPdf => "typst-lsp.doPdfExport".to_string(),
+            LspCommand::ExportPdfa => "typst-lsp.export.pdfa".to_string(),
+            LspCommand::ExportPdfBytes => "typst-lsp.export.pdfBytes".to_string(),
+            LspCommand::ExportPng => "typst-lsp.doPngExport".to_string(),
+            LspCommand::ExportPages => "typst-lsp.export.pages".to_string(),
+            LspCommand::ExportAll => "typst-lsp.export.all".to_string(),
+            LspCommand::ListFonts => "typst-lsp.listFonts".to_string(),
+            LspCommand::Check => "typst-lsp.check".to_string(),
+            LspCommand::DebugWorld => "typst-lsp.debug.world".to_string(),
+            LspCommand::DebugAst => "typst-lsp.debug.ast".to_string(),
+            LspCommand::Version => "typst-lsp.version".to_string(),
+            LspCommand::ReloadConfig => "typst-lsp.reloadConfig".to_string(),
+            LspCommand::SetMainFile => "typst-lsp.setMainFile".to_string(),
+            LspCommand::ForgetSource => "typst-lsp.forgetSource".to_string(),
+            LspCommand::IndexWorkspace => "typst-lsp.indexWorkspace".to_string(),
+            LspCommand::CompileSelection => "typst-lsp.compileSelection".to_string(),
+            LspCommand::CompileString => "typst-lsp.compileString".to_string(),
         }
     }
 }
@@ -23,12 +75,46 @@ impl LspCommand {
     pub fn parse(command: &str) -> Option<Self> {
         match command {
             "typst-lsp.doPdfExport" => Some(Self::ExportPdf),
+            "typst-lsp.export.pdfa" => Some(Self::ExportPdfa),
+            "typst-lsp.export.pdfBytes" => Some(Self::ExportPdfBytes),
+            "typst-lsp.doPngExport" => Some(Self::ExportPng),
+            "typst-lsp.export.pages" => Some(Self::ExportPages),
+            "typst-lsp.export.all" => Some(Self::ExportAll),
+            "typst-lsp.listFonts" => Some(Self::ListFonts),
+            "typst-lsp.check" => Some(Self::Check),
+            "typst-lsp.debug.world" => Some(Self::DebugWorld),
+            "typst-lsp.debug.ast" => Some(Self::DebugAst),
+            "typst-lsp.version" => Some(Self::Version),
+            "typst-lsp.reloadConfig" => Some(Self::ReloadConfig),
+            "typst-lsp.setMainFile" => Some(Self::SetMainFile),
+            "typst-lsp.forgetSource" => Some(Self::ForgetSource),
+            "typst-lsp.indexWorkspace" => Some(Self::IndexWorkspace),
+            "typst-lsp.compileSelection" => Some(Self::CompileSelection),
+            "typst-lsp.compileString" => Some(Self::CompileString),
             _ => None,
         }
     }
 
     pub fn all_as_string() -> Vec<String> {
-        vec![Self::ExportPdf.into()]
+        vec![
+            Self::ExportPdf.into(),
+            Self::ExportPdfa.into(),
+            Self::ExportPdfBytes.into(),
+            Self::ExportPng.into(),
+            Self::ExportPages.into(),
+            Self::ExportAll.into(),
+            Self::ListFonts.into(),
+            Self::Check.into(),
+            Self::DebugWorld.into(),
+            Self::DebugAst.into(),
+            Self::Version.into(),
+            Self::ReloadConfig.into(),
+            Self::SetMainFile.into(),
+            Self::ForgetSource.into(),
+            Self::IndexWorkspace.into(),
+            Self::CompileSelection.into(),
+            Self::CompileString.into(),
+        ]
     }
 }
 
@@ -36,6 +122,568 @@ impl LspCommand {
 impl TypstServer {
     /// Export the current document as a PDF file. The client is responsible for passing the correct file URI.
     pub async fn command_export_pdf(&self, arguments: Vec<Value>) -> Result<()> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+        let workspace = world.get_workspace();
+        let source = workspace.sources.get_open_source_by_id(source_id);
+
+        let progress = self.begin_progress("Exporting PDF").await;
+        self.run_export(&world, &config, source, progress).await;
+
+        Ok(())
+    }
+
+    /// Export the current document as a PDF/A-2b file, regardless of the configured standard. Fails
+    /// if PDF/A can't be written, rather than writing a plain PDF.
+    pub async fn command_export_pdfa(&self, arguments: Vec<Value>) -> Result<()> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+        Self::check_pdf_standard(PdfStandard::A2b).map_err(|message| Error {
+            code: ErrorCode::InternalError,
+            message: message.into(),
+            data: None,
+        })?;
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let mut config = self.config.read().await.clone();
+        config.pdf_standard = PdfStandard::A2b;
+        let workspace = world.get_workspace();
+        let source = workspace.sources.get_open_source_by_id(source_id);
+
+        let progress = self.begin_progress("Exporting PDF/A").await;
+        self.run_export(&world, &config, source, progress).await;
+
+        Ok(())
+    }
+
+    /// Compile the document and return it as a base64-encoded PDF, along with its page count and
+    /// size in bytes, instead of writing it to disk. For clients which don't share a file system
+    /// with the server, like web-based editors.
+    pub async fn command_export_pdf_bytes(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+        Self::check_pdf_standard(config.pdf_standard).map_err(|message| Error {
+            code: ErrorCode::InternalError,
+            message: message.into(),
+            data: None,
+        })?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let (document, _) = self.compile_source(&world);
+        let document = document.ok_or_else(|| Error {
+            code: ErrorCode::InternalError,
+            message: "The document has errors, so it can't be exported".into(),
+            data: None,
+        })?;
+        let start = Instant::now();
+        let buffer = Self::render_pdf(source, &document);
+        self.log_timing(
+            &config,
+            "export-pdf",
+            source.as_ref().path(),
+            start.elapsed(),
+        )
+        .await;
+
+        Ok(json!({
+            "pdf": base64::encode(&buffer),
+            "pageCount": document.pages.len(),
+            "byteSize": buffer.len(),
+        }))
+    }
+
+    /// Export the current document as PNG files, one per page. The client is responsible for passing the correct file URI.
+    pub async fn command_export_png(&self, arguments: Vec<Value>) -> Result<()> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+        let workspace = world.get_workspace();
+        let source = workspace.sources.get_open_source_by_id(source_id);
+
+        let progress = self.begin_progress("Exporting PNG").await;
+        self.run_png_export(&world, &config, source, progress).await;
+
+        Ok(())
+    }
+
+    /// Export some pages of the current document. Takes the file URI, the pages as a 1-based page
+    /// number or inclusive range like `3-5`, and optionally the format, `"pdf"` (the default) or
+    /// `"png"`.
+    pub async fn command_export_pages(&self, arguments: Vec<Value>) -> Result<()> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+        let pages = arguments
+            .get(1)
+            .and_then(Value::as_str)
+            .ok_or_else(|| Error::invalid_params("Missing page range as second argument"))?;
+        let pages = Self::parse_page_range(pages).map_err(|message| {
+            Error::invalid_params(format!("Invalid page range `{pages}`: {message}"))
+        })?;
+        let is_png = match arguments.get(2).and_then(Value::as_str) {
+            None | Some("pdf") => false,
+            Some("png") => true,
+            Some(_) => {
+                return Err(Error::invalid_params(
+                    "Export format must be `pdf` or `png`",
+                ))
+            }
+        };
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+        let workspace = world.get_workspace();
+        let source = workspace.sources.get_open_source_by_id(source_id);
+
+        let progress = self.begin_progress("Exporting pages").await;
+        self.report_progress(&progress, "Compiling…".to_owned(), 0)
+            .await;
+        let (Some(document), _) = self.compile_source(&world) else {
+            self.end_progress(progress, "Compilation failed").await;
+            self.client
+                .show_message(MessageType::ERROR, "The document could not be compiled")
+                .await;
+            return Ok(());
+        };
+
+        let page_count = document.pages.len();
+        if *pages.end() > page_count {
+            let message = format!(
+                "Cannot export pages {}-{}, since the document only has {page_count}",
+                pages.start(),
+                pages.end()
+            );
+            self.end_progress(progress, "Page range out of bounds")
+                .await;
+            self.client.show_message(MessageType::ERROR, message).await;
+            return Ok(());
+        }
+
+        let indices = pages.start() - 1..*pages.end();
+        if is_png {
+            self.export_png_pages(&config, source, &document, indices, &progress)
+                .await;
+        } else {
+            self.export_pdf_pages(&config, source, &document, indices, &progress)
+                .await;
+        }
+        self.end_progress(progress, "Done").await;
+
+        Ok(())
+    }
+
+    /// Export the current document in every format enabled by `exportFormats`, compiling it only
+    /// once. Returns the paths of the files written.
+    pub async fn command_export_all(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+        let workspace = world.get_workspace();
+        let source = workspace.sources.get_open_source_by_id(source_id);
+
+        let progress = self.begin_progress("Exporting").await;
+        self.report_progress(&progress, "Compiling…".to_owned(), 0)
+            .await;
+        let start = Instant::now();
+        let document = self.get_or_compile_document(&world);
+        self.log_timing(&config, "compile", source.as_ref().path(), start.elapsed())
+            .await;
+        let Some(document) = document else {
+            self.end_progress(progress, "Compilation failed").await;
+            return Err(Error {
+                code: ErrorCode::InternalError,
+                message: "The document has errors, so it can't be exported".into(),
+                data: None,
+            });
+        };
+
+        let files = self
+            .export_all_formats(&config, source, &document, &progress)
+            .await;
+        self.end_progress(progress, "Done").await;
+
+        Ok(json!({ "files": files }))
+    }
+
+    /// List the fonts available to Typst, with their family, style, weight, where they were found,
+    /// and the path of their file if they aren't embedded.
+    pub async fn command_list_fonts(&self) -> Result<Value> {
+        let workspace = self.workspace.read().await;
+
+        let fonts = workspace
+            .fonts
+            .fonts()
+            .map(|(info, origin, uri)| {
+                json!({
+                    "family": info.family,
+                    "style": format!("{:?}", info.variant.style).to_lowercase(),
+                    "weight": info.variant.weight.to_number(),
+                    "origin": origin.as_str(),
+                    "path": uri.and_then(|uri| uri.to_file_path().ok()),
+                })
+            })
+            .collect();
+
+        Ok(Value::Array(fonts))
+    }
+
+    /// Return the versions of typst-lsp and of the Typst compiler it's built with, and the formats
+    /// documents can be exported to, for editors to show and users to include in bug reports.
+    pub fn command_version(&self) -> Result<Value> {
+        let pdf_standards = match Self::check_pdf_standard(PdfStandard::A2b) {
+            Ok(()) => vec!["1.7", "a-2b"],
+            Err(_) => vec!["1.7"],
+        };
+
+        Ok(json!({
+            "typstLsp": env!("CARGO_PKG_VERSION"),
+            "typst": TYPST_VERSION,
+            "exportFormats": EXPORT_FORMATS,
+            "pdfStandards": pdf_standards,
+        }))
+    }
+
+    /// Ask the client for the current settings and apply them, for clients which don't send
+    /// changes to the settings on their own.
+    pub async fn command_reload_config(&self) -> Result<()> {
+        let settings = self.pull_settings().await.ok_or_else(|| Error {
+            code: ErrorCode::InternalError,
+            message: "The client sent no settings".into(),
+            data: None,
+        })?;
+        self.apply_settings(&settings).await;
+        Ok(())
+    }
+
+    /// Pin the file as the main file, compiled for diagnostics and exports whenever any file is
+    /// edited, or unpin it when no file URI is passed. Lasts until the settings change, so clients
+    /// which can should persist the pin in the `mainFile` setting instead.
+    pub async fn command_set_main_file(&self, arguments: Vec<Value>) -> Result<()> {
+        let main_file = match arguments.first() {
+            None | Some(Value::Null) => None,
+            Some(_) => {
+                let file_uri = Self::parse_file_uri_argument(&arguments)?;
+                let path = file_uri
+                    .to_file_path()
+                    .map_err(|_| Error::invalid_params("The main file must be a file"))?;
+                Some(path)
+            }
+        };
+
+        self.config.write().await.main_file = main_file.clone();
+        self.update_pinned_main(main_file.as_deref()).await;
+        self.refresh_open_diagnostics().await;
+        Ok(())
+    }
+
+    /// Drop a closed document from memory entirely, like a generated preview which won't be opened
+    /// again, rather than keeping it for when it's next used. Fails if the document is open.
+    pub async fn command_forget_source(&self, arguments: Vec<Value>) -> Result<()> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+
+        let mut workspace = self.workspace.write().await;
+        if let Some(id) = workspace.sources.get_id_by_uri(&file_uri) {
+            if workspace.sources.get_open_ids().contains(&id) {
+                return Err(Error::invalid_params("Open documents can't be forgotten"));
+            }
+            // The pinned main file is kept by id, which could otherwise go to another file
+            if *self.pinned_main.lock() == Some(id) {
+                return Err(Error::invalid_params("The main file can't be forgotten"));
+            }
+        }
+        if let Some(id) = workspace.sources.forget(&file_uri) {
+            workspace.resources.write().remove_dependent(id);
+        }
+        drop(workspace);
+
+        self.client
+            .publish_diagnostics(file_uri, Vec::new(), None)
+            .await;
+        Ok(())
+    }
+
+    /// Load and parse every Typst file in the workspace folders now, rather than when a
+    /// workspace-wide query first needs them, so that symbol, reference and definition queries
+    /// don't wait for it. Files excluded by `indexExclude` or ignore files are skipped. Returns how
+    /// many files were found and how many of them could be loaded.
+    pub async fn command_index_workspace(&self) -> Result<Value> {
+        let workspace = self.workspace.read().await;
+        let exclude = workspace.get_index_exclude();
+        let paths: Vec<_> = self
+            .get_const_config()
+            .root_paths
+            .iter()
+            .flat_map(|root_path| SourceManager::find_typst_files(root_path, exclude))
+            .collect();
+
+        let progress = self.begin_progress("Indexing workspace").await;
+        let mut loaded_count = 0;
+        let mut last_percentage = None;
+        for (index, path) in paths.iter().enumerate() {
+            if workspace.sources.cache_path(path).is_ok() {
+                loaded_count += 1;
+            }
+            // Reporting every file would flood the client in large workspaces
+            let percentage = (100 * (index + 1) / paths.len()) as u32;
+            if last_percentage != Some(percentage) {
+                let message = format!("{}/{} files", index + 1, paths.len());
+                self.report_progress(&progress, message, percentage).await;
+                last_percentage = Some(percentage);
+            }
+        }
+        self.end_progress(progress, "Done").await;
+
+        Ok(json!({
+            "fileCount": paths.len(),
+            "loadedCount": loaded_count,
+        }))
+    }
+
+    /// Compile the selected range of the file on its own, after the file's top-level `set` rules
+    /// and imports, and return its pages as base64-encoded PNGs. If it doesn't compile, return
+    /// the errors instead, placed in the file.
+    pub async fn command_compile_selection(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+        let range: LspRawRange = arguments
+            .get(1)
+            .and_then(|range| serde_json::from_value(range.clone()).ok())
+            .ok_or_else(|| Error::invalid_params("Missing range as second argument"))?;
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+        let range = LspRange::new(range, self.get_const_config().position_encoding);
+        let typst_range = lsp_to_typst::range(&range, source.as_ref());
+
+        match self.compile_selection(&world, &config, source, &file_uri, typst_range) {
+            SelectionRender::Pages(pages) => Ok(json!({
+                "success": true,
+                "png": pages.iter().map(base64::encode).collect::<Vec<_>>(),
+                "diagnostics": {},
+            })),
+            SelectionRender::Errors(diagnostics) => Ok(json!({
+                "success": false,
+                "png": [],
+                "diagnostics": diagnostics,
+            })),
+        }
+    }
+
+    /// Compile Typst source text which isn't a file, like that of a playground, without adding it to
+    /// the workspace. Takes the text and optionally whether to render the pages, and returns the
+    /// diagnostics and, if asked for, the pages as base64-encoded PNGs at the configured
+    /// resolution. The text can't access files, so relative imports fail.
+    pub async fn command_compile_string(&self, arguments: Vec<Value>) -> Result<Value> {
+        let text = arguments
+            .first()
+            .and_then(Value::as_str)
+            .ok_or_else(|| Error::invalid_params("Missing source text as first argument"))?;
+        let render = arguments.get(1).and_then(Value::as_bool).unwrap_or(false);
+
+        let world = self.get_detached_world(text.to_owned()).await;
+        let config = self.config.read().await;
+
+        let (document, diagnostics) = self.compile_source(&world);
+        let diagnostics: Vec<_> = diagnostics.into_values().flatten().collect();
+        let pixel_per_pt = config.png_dpi as f32 / POINTS_PER_INCH;
+        let pages: Vec<_> = match &document {
+            Some(document) if render => document
+                .pages
+                .iter()
+                .filter_map(|page| {
+                    typst::export::render(page, pixel_per_pt, Color::WHITE)
+                        .encode_png()
+                        .ok()
+                })
+                .map(base64::encode)
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        Ok(json!({
+            "success": document.is_some(),
+            "png": pages,
+            "diagnostics": diagnostics,
+        }))
+    }
+
+    /// Compile the document and return its diagnostics, without publishing or exporting anything.
+    /// The result says whether there were any errors, how many errors and warnings there were, and
+    /// how long compiling took, for one-off checks like those in CI. The document doesn't need to
+    /// be open.
+    pub async fn command_check(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+
+        let (world, _) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+
+        let start = Instant::now();
+        let (_, mut diagnostics) = self.compile_source(&world);
+        let duration = start.elapsed();
+
+        for (uri, file_diagnostics) in diagnostics.iter_mut() {
+            Self::suggest_missing_files(file_diagnostics);
+            self.apply_diagnostic_config(world.get_workspace(), &config, uri, file_diagnostics);
+        }
+        let count_severity = |severity| {
+            diagnostics
+                .values()
+                .flatten()
+                .filter(|diagnostic| diagnostic.severity == Some(severity))
+                .count()
+        };
+        let error_count = count_severity(DiagnosticSeverity::ERROR);
+        let warning_count = count_severity(DiagnosticSeverity::WARNING);
+
+        Ok(json!({
+            "success": error_count == 0,
+            "errorCount": error_count,
+            "warningCount": warning_count,
+            "durationMs": duration.as_millis() as u64,
+            "diagnostics": diagnostics,
+        }))
+    }
+
+    /// Describe the state Typst sees when compiling the document, to attach to bug reports: the
+    /// root absolute paths resolve against, the sources and other files the workspace knows about,
+    /// and where fonts are loaded from. Doesn't change anything.
+    pub async fn command_debug_world(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let config = self.config.read().await;
+        let workspace = world.get_workspace();
+        let sources = &workspace.sources;
+
+        let main_path = lsp_to_typst::uri_to_path(&file_uri);
+        let open_ids = sources.get_open_ids();
+        let mut source_entries: Vec<(u16, Value)> = sources
+            .get_uris()
+            .into_iter()
+            .filter_map(|uri| {
+                let id = sources.get_id_by_uri(&uri)?;
+                let state = if open_ids.contains(&id) {
+                    "open"
+                } else if sources.is_loaded(id) {
+                    "cached"
+                } else {
+                    "unloaded"
+                };
+                let number = TypstSourceId::from(id).into_u16();
+                Some((number, json!({ "id": number, "uri": uri, "state": state })))
+            })
+            .collect();
+        source_entries.sort_by_key(|(number, _)| *number);
+
+        let resources = workspace.resources.read();
+        let resource_entries: Vec<Value> = resources
+            .get_uris()
+            .into_iter()
+            .map(|uri| {
+                let dependents: Vec<u16> = resources
+                    .get_dependents(&uri)
+                    .into_iter()
+                    .map(|id| TypstSourceId::from(id).into_u16())
+                    .collect();
+                json!({ "uri": uri, "dependents": dependents })
+            })
+            .collect();
+        drop(resources);
+
+        let font_count = |origin: FontOrigin| {
+            workspace
+                .fonts
+                .fonts()
+                .filter(|(_, font_origin, _)| *font_origin == origin)
+                .count()
+        };
+
+        Ok(json!({
+            "main": {
+                "id": TypstSourceId::from(source_id).into_u16(),
+                "uri": file_uri,
+            },
+            "root": workspace.get_root(&main_path),
+            "sources": source_entries.into_iter().map(|(_, entry)| entry).collect::<Vec<_>>(),
+            "resources": resource_entries,
+            "fonts": {
+                "paths": self.resolve_font_paths(&config.font_paths),
+                "embedded": font_count(FontOrigin::Embedded),
+                "system": font_count(FontOrigin::System),
+                "custom": font_count(FontOrigin::Custom),
+            },
+        }))
+    }
+
+    /// Return the syntax tree the server parsed the file into, for building tooling against the
+    /// same parse. Takes the file URI and optionally the shape of the result, `"tree"` (the
+    /// default) for nested nodes or `"tokens"` for the leaves in order. Doesn't change anything.
+    pub async fn command_debug_ast(&self, arguments: Vec<Value>) -> Result<Value> {
+        let file_uri = Self::parse_file_uri_argument(&arguments)?;
+        let is_tokens = match arguments.get(1).and_then(Value::as_str) {
+            None | Some("tree") => false,
+            Some("tokens") => true,
+            Some(_) => {
+                return Err(Error::invalid_params(
+                    "Syntax tree shape must be `tree` or `tokens`",
+                ))
+            }
+        };
+
+        let (world, source_id) = self.get_world_with_file(&file_uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        if is_tokens {
+            Ok(self.get_syntax_tokens_json(source))
+        } else {
+            Ok(self.get_syntax_tree_json(source))
+        }
+    }
+
+    /// Parses a 1-based page number or inclusive range of pages, like `12` or `3-5`, or says which
+    /// part of it is wrong
+    fn parse_page_range(pages: &str) -> std::result::Result<RangeInclusive<usize>, String> {
+        let (start, end) = pages.split_once('-').unwrap_or((pages, pages));
+        let parse_page = |page: &str| {
+            let page = page.trim();
+            match page.parse::<usize>() {
+                _ if page.is_empty() => Err("a page number is missing".to_owned()),
+                Ok(0) => Err("pages are numbered from 1".to_owned()),
+                Ok(page) => Ok(page),
+                Err(_) => Err(format!("`{page}` is not a page number")),
+            }
+        };
+        let start = parse_page(start)?;
+        let end = parse_page(end)?;
+        if start > end {
+            return Err(format!("the first page, {start}, is after the last, {end}"));
+        }
+        Ok(start..=end)
+    }
+
+    /// Gets a world compiling the file, loading it first if it isn't open, like when a command
+    /// is run on a file in a file explorer. Fails if the file can't be loaded.
+    async fn get_world_with_file(&self, file_uri: &Url) -> Result<(WorkspaceWorld, SourceId)> {
+        let workspace = Arc::clone(&self.workspace).read_owned().await;
+        let source_id = workspace.sources.cache(file_uri.clone()).map_err(|error| {
+            Error::invalid_params(format!("Could not load {file_uri}: {error}"))
+        })?;
+        Ok((WorkspaceWorld::new(workspace, source_id), source_id))
+    }
+
+    fn parse_file_uri_argument(arguments: &[Value]) -> Result<Url> {
         if arguments.is_empty() {
             return Err(Error::invalid_params("Missing file URI argument"));
         }
@@ -44,15 +692,42 @@ impl TypstServer {
                 "Missing file URI as first argument",
             ));
         };
-        let file_uri = Url::parse(file_uri)
-            .map_err(|_| Error::invalid_params("Parameter is not a valid URI"))?;
+        Url::parse(file_uri).map_err(|_| Error::invalid_params("Parameter is not a valid URI"))
+    }
+}
 
-        let (world, source_id) = self.get_world_with_main_uri(&file_uri).await;
-        let workspace = world.get_workspace();
-        let source = workspace.sources.get_open_source_by_id(source_id);
+#[cfg(test)]
+mod test {
+    use super::*;
 
-        self.run_export(&world, source).await;
+    #[test]
+    fn page_range_is_parsed() {
+        assert_eq!(TypstServer::parse_page_range("12"), Ok(12..=12));
+        assert_eq!(TypstServer::parse_page_range("3-5"), Ok(3..=5));
+        assert_eq!(TypstServer::parse_page_range(" 3 - 3 "), Ok(3..=3));
+    }
 
-        Ok(())
+    #[test]
+    fn page_range_error_says_what_is_wrong() {
+        assert_eq!(
+            TypstServer::parse_page_range("0"),
+            Err("pages are numbered from 1".to_owned())
+        );
+        assert_eq!(
+            TypstServer::parse_page_range("0-2"),
+            Err("pages are numbered from 1".to_owned())
+        );
+        assert_eq!(
+            TypstServer::parse_page_range("5-3"),
+            Err("the first page, 5, is after the last, 3".to_owned())
+        );
+        assert_eq!(
+            TypstServer::parse_page_range("2-x"),
+            Err("`x` is not a page number".to_owned())
+        );
+        assert_eq!(
+            TypstServer::parse_page_range("3-"),
+            Err("a page number is missing".to_owned())
+        );
     }
 }
@@ -0,0 +1,238 @@
+use std::sync::Arc;
+
+use tower_lsp::lsp_types::{DiagnosticSeverity, Url};
+use typst::geom::Color;
+use typst::syntax::{LinkedNode, SyntaxKind};
+use typst::World;
+
+use crate::config::Config;
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{
+    typst_to_lsp, LspDiagnostic, LspDiagnostics, TypstOffset, TypstRange, TypstSpan,
+};
+use crate::workspace::source::Source;
+
+use super::export::POINTS_PER_INCH;
+use super::TypstServer;
+
+/// Sizes the page to the snippet, so the render shows just the selection
+const SNIPPET_PAGE: &str = "#set page(width: auto, height: auto, margin: 8pt)\n";
+
+/// The rendered pages of a compiled selection, or the errors compiling it
+pub enum SelectionRender {
+    Pages(Vec<Vec<u8>>),
+    Errors(LspDiagnostics),
+}
+
+/// Text compiled in place of a file, made of pieces of it. Remembers where each piece came from,
+/// so that errors can be shown in the file.
+struct Snippet {
+    text: String,
+    /// The offset in the snippet each piece starts at, along with the range it came from
+    pieces: Vec<(TypstOffset, TypstRange)>,
+}
+
+impl Snippet {
+    fn new() -> Self {
+        Self {
+            text: String::new(),
+            pieces: Vec::new(),
+        }
+    }
+
+    fn push_piece(&mut self, source: &Source, range: TypstRange) {
+        self.pieces.push((self.text.len(), range.clone()));
+        self.text.push_str(&source.as_ref().text()[range]);
+    }
+
+    /// Maps an offset in the snippet to the offset in the file it came from
+    fn original_offset(&self, offset: TypstOffset) -> Option<TypstOffset> {
+        self.pieces.iter().find_map(|(start, range)| {
+            let relative = offset.checked_sub(*start)?;
+            (relative <= range.len()).then_some(range.start + relative)
+        })
+    }
+}
+
+impl TypstServer {
+    /// Compiles the text in the range on its own, after the file's top-level `set` rules and
+    /// imports, and renders it to PNGs at the configured resolution. Errors in the selection or
+    /// the preamble are reported at their place in the file.
+    pub fn compile_selection(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        source: &Source,
+        uri: &Url,
+        range: TypstRange,
+    ) -> SelectionRender {
+        let snippet = Self::build_snippet(source, range);
+        let snippet_world = WorkspaceWorld::new_detached(
+            Arc::clone(world.get_workspace()),
+            snippet.text.clone(),
+            Some(source.as_ref()),
+        );
+
+        let errors = match typst::compile(&snippet_world) {
+            Ok(document) => {
+                let pixel_per_pt = config.png_dpi as f32 / POINTS_PER_INCH;
+                let pages = document
+                    .pages
+                    .iter()
+                    .filter_map(|page| {
+                        typst::export::render(page, pixel_per_pt, Color::WHITE)
+                            .encode_png()
+                            .ok()
+                    })
+                    .collect();
+                return SelectionRender::Pages(pages);
+            }
+            Err(errors) => errors,
+        };
+
+        let mut diagnostics = LspDiagnostics::new();
+        for error in errors.iter() {
+            // The snippet stands in for the file, so its errors are in the file's source
+            let (error_uri, diagnostic) = if error.span.source() == source.as_ref().id() {
+                let diagnostic = self.snippet_error_to_diagnostic(
+                    &snippet_world,
+                    &snippet,
+                    source,
+                    error.span,
+                    error.message.to_string(),
+                );
+                (uri.clone(), diagnostic)
+            } else {
+                typst_to_lsp::source_error_to_diagnostic(
+                    error,
+                    &snippet_world,
+                    self.get_const_config(),
+                )
+            };
+            diagnostics.entry(error_uri).or_default().push(diagnostic);
+        }
+        SelectionRender::Errors(diagnostics)
+    }
+
+    /// Puts the file's top-level `set` rules and imports before the selection, skipping those in
+    /// the selection itself
+    fn build_snippet(source: &Source, range: TypstRange) -> Snippet {
+        let mut snippet = Snippet::new();
+        let root = LinkedNode::new(source.as_ref().root());
+        for child in root.children() {
+            if !matches!(child.kind(), SyntaxKind::SetRule | SyntaxKind::ModuleImport)
+                || child.range().end > range.start
+            {
+                continue;
+            }
+            // The `#` before embedded code is a separate node
+            let start = match child.prev_leaf() {
+                Some(hash) if hash.kind() == SyntaxKind::Hash => hash.offset(),
+                _ => child.offset(),
+            };
+            snippet.push_piece(source, start..child.range().end);
+            snippet.text.push('\n');
+        }
+        snippet.text.push_str(SNIPPET_PAGE);
+        snippet.push_piece(source, range);
+        snippet
+    }
+
+    fn snippet_error_to_diagnostic(
+        &self,
+        snippet_world: &WorkspaceWorld,
+        snippet: &Snippet,
+        source: &Source,
+        span: TypstSpan,
+        message: String,
+    ) -> LspDiagnostic {
+        let snippet_range = snippet_world.main().range(span);
+        // Errors in text added around the pieces, like the page setup, are shown at the selection
+        let fallback = snippet.pieces.last().map_or(0, |(_, range)| range.start);
+        let start = snippet
+            .original_offset(snippet_range.start)
+            .unwrap_or(fallback);
+        let end = snippet
+            .original_offset(snippet_range.end)
+            .filter(|&end| end >= start)
+            .unwrap_or(start);
+        let lsp_range = typst_to_lsp::range(
+            start..end,
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+
+        LspDiagnostic {
+            range: lsp_range.raw_range,
+            severity: Some(DiagnosticSeverity::ERROR),
+            message,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::RwLock;
+    use tower_lsp::LspService;
+
+    use crate::config::{ConstConfig, PositionEncoding};
+    use crate::test_utils::TempDir;
+    use crate::workspace::Workspace;
+
+    use super::*;
+
+    /// A PNG of a single black pixel
+    const PIXEL_PNG: &[u8] = &[
+        0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A, 0x00, 0x00, 0x00, 0x0D, 0x49, 0x48, 0x44,
+        0x52, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x3A,
+        0x7E, 0x9B, 0x55, 0x00, 0x00, 0x00, 0x0A, 0x49, 0x44, 0x41, 0x54, 0x78, 0x9C, 0x63, 0x60,
+        0x00, 0x00, 0x00, 0x02, 0x00, 0x01, 0x48, 0xAF, 0xA4, 0x71, 0x00, 0x00, 0x00, 0x00, 0x49,
+        0x45, 0x4E, 0x44, 0xAE, 0x42, 0x60, 0x82,
+    ];
+
+    #[test]
+    fn selection_loads_files_relative_to_its_file() {
+        let dir = TempDir::new("selection-relative");
+        dir.write("chapters/figure.png", PIXEL_PNG);
+        let text = "= Chapter\n\n#image(\"figure.png\", width: 1cm)\n";
+        let path = dir.write("chapters/chapter.typ", text);
+        let uri = typst_to_lsp::path_to_uri(&path).unwrap();
+
+        let mut workspace = Workspace::default();
+        workspace
+            .sources
+            .insert_open(&uri, text.to_owned())
+            .unwrap();
+        let id = workspace.sources.get_id_by_uri(&uri).unwrap();
+        let world = WorkspaceWorld::new(
+            Arc::new(RwLock::new(workspace)).try_read_owned().unwrap(),
+            id,
+        );
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let server = service.inner();
+        server
+            .const_config
+            .set(ConstConfig {
+                position_encoding: PositionEncoding::Utf16,
+                root_paths: vec![dir.path()],
+                supports_work_done_progress: false,
+                supports_pull_diagnostics: false,
+                supports_diagnostic_refresh: false,
+            })
+            .unwrap();
+
+        let source = world.get_workspace().sources.get_open_source_by_id(id);
+        let start = text.find("#image").unwrap();
+        let render =
+            server.compile_selection(&world, &Config::default(), source, &uri, start..text.len());
+
+        match render {
+            SelectionRender::Pages(pages) => assert_eq!(pages.len(), 1),
+            SelectionRender::Errors(diagnostics) => {
+                panic!("selection should compile, but got {diagnostics:?}")
+            }
+        }
+    }
+}
@@ -0,0 +1,741 @@
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{
+    Command, CompletionItemKind, CompletionResponse, CompletionTextEdit, Documentation,
+    InsertTextFormat, MarkupContent, MarkupKind, TextEdit,
+};
+use typst::eval::{Scope, Value};
+use typst::ide::autocomplete;
+use typst::syntax::{LinkedNode, SyntaxKind};
+use typst::World;
+
+use crate::config::Snippet;
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{
+    lsp_to_typst, typst_to_lsp, LspCompletion, LspPosition, LspRawRange, TypstCompletion,
+    TypstCompletionKind, TypstOffset, TypstRange,
+};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+const IMAGE_EXTENSIONS: [&str; 5] = ["png", "jpg", "jpeg", "gif", "svg"];
+
+/// Snippets offered in markup unless the configuration replaces them, as name and body
+const BUILTIN_SNIPPETS: [(&str, &str); 4] = [
+    (
+        "fig",
+        "#figure(\n  image(\"${1:path}\"),\n  caption: [${2:caption}],\n) <${3:label}>",
+    ),
+    (
+        "table",
+        "#table(\n  columns: ${1:2},\n  [*${2:Header}*], [*${3:Header}*],\n  [${4}], [${5}],\n)",
+    ),
+    ("eq", "\\$ ${1:equation} \\$ <${2:label}>"),
+    (
+        "preamble",
+        "#set document(title: \"${1:Title}\", author: \"${2:Author}\")\n#set page(paper: \"${3:a4}\", numbering: \"1\")\n#set text(lang: \"${4:en}\")\n#set heading(numbering: \"1.\")\n\n$0",
+    ),
+];
+
+/// Functions which take a label as their first argument
+const LABEL_FUNCTIONS: [&str; 2] = ["ref", "link"];
+
+/// A path partway through being typed into a string literal
+#[derive(Debug)]
+pub enum PathCompletionContext {
+    File {
+        /// The directory the path typed so far leads to
+        dir: PathBuf,
+        /// Extensions of the files which can be loaded there, or `None` if any file can be
+        extensions: Option<&'static [&'static str]>,
+        /// The last component of the path typed so far, which completions replace
+        range: LspRawRange,
+        /// The file being edited, which can't import itself
+        source_path: PathBuf,
+    },
+    /// A package spec, like `@preview/example:0.1.0`
+    Package {
+        /// The spec typed so far, which completions replace
+        range: LspRawRange,
+    },
+}
+
+impl TypstServer {
+    pub fn get_completions(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+        explicit: bool,
+        snippets: &[Snippet],
+    ) -> Option<CompletionResponse> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+
+        if let Some(completions) = self.get_label_completions(world, source, typst_offset) {
+            return Some(CompletionResponse::Array(completions));
+        }
+
+        // Typst's autocompletion already decides between markup, math, and code mode by looking at
+        // the syntax around the cursor, and includes bindings in scope at that point
+        let global = world.library().global.scope();
+        let mut lsp_completions: Vec<LspCompletion> =
+            autocomplete(world, &[], source.as_ref(), typst_offset, explicit)
+                .map(|(_, typst_completions)| {
+                    typst_completions
+                        .iter()
+                        .map(|completion| self.completion_with_docs(completion, global))
+                        .collect()
+                })
+                .unwrap_or_default();
+
+        lsp_completions.extend(self.get_snippet_completions(
+            source,
+            typst_offset,
+            explicit,
+            snippets,
+        ));
+
+        if lsp_completions.is_empty() {
+            return None;
+        }
+        Some(CompletionResponse::Array(lsp_completions))
+    }
+
+    /// Gets the built-in and configured snippets, if the offset is at a word in markup. The
+    /// snippets replace the word, along with a `#` before it.
+    fn get_snippet_completions(
+        &self,
+        source: &Source,
+        typst_offset: TypstOffset,
+        explicit: bool,
+        snippets: &[Snippet],
+    ) -> Vec<LspCompletion> {
+        let Some(leaf) = self.get_leaf(source, typst_offset) else {
+            return Vec::new();
+        };
+        let in_markup = matches!(
+            leaf.kind(),
+            SyntaxKind::Text | SyntaxKind::Ident | SyntaxKind::Hash | SyntaxKind::Space
+        ) && leaf
+            .parent()
+            .map_or(false, |parent| parent.kind() == SyntaxKind::Markup);
+        if !in_markup {
+            return Vec::new();
+        }
+
+        let text = source.as_ref().text();
+        let word_start = text[..typst_offset]
+            .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_' || c == '-')
+            .len();
+        if word_start == typst_offset && !explicit {
+            return Vec::new();
+        }
+        let start = if text[..word_start].ends_with('#') {
+            word_start - 1
+        } else {
+            word_start
+        };
+        let replaced = &text[start..typst_offset];
+        let range = typst_to_lsp::range(
+            start..typst_offset,
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+
+        let builtin_snippets = BUILTIN_SNIPPETS
+            .iter()
+            .filter(|(name, _)| !snippets.iter().any(|snippet| snippet.name == *name))
+            .map(|(name, body)| (*name, *body));
+        let configured_snippets = snippets
+            .iter()
+            .map(|snippet| (snippet.name.as_str(), snippet.body.as_str()));
+
+        builtin_snippets
+            .chain(configured_snippets)
+            .map(|(name, body)| LspCompletion {
+                label: name.to_owned(),
+                kind: Some(CompletionItemKind::SNIPPET),
+                detail: Some("Snippet".to_owned()),
+                documentation: Some(Documentation::MarkupContent(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: format!("```typst\n{body}\n```"),
+                })),
+                // Match what's being replaced, so a `#` before the name doesn't filter it out
+                filter_text: replaced.starts_with('#').then(|| format!("#{name}")),
+                insert_text_format: Some(InsertTextFormat::SNIPPET),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range: range.raw_range,
+                    new_text: body.to_owned(),
+                })),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Finds out whether the position is in the path of an import, include or call to a function
+    /// loading a file, and if so, what can be completed there
+    pub fn get_path_completion_context(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<PathCompletionContext> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        let leaf = self.get_leaf(source, typst_offset)?;
+        if leaf.kind() != SyntaxKind::Str || typst_offset >= leaf.range().end {
+            return None;
+        }
+
+        // The path of a function call is in its arguments, rather than directly in the call
+        let parent = leaf.parent()?;
+        let user = match parent.kind() {
+            SyntaxKind::Args => parent.parent()?,
+            _ => parent,
+        };
+        let path_node = Self::get_path_node(user)?;
+        if path_node.range() != leaf.range() {
+            return None;
+        }
+
+        let typed = leaf.text().get(1..typst_offset - leaf.offset())?;
+        let range_from = |start: usize| {
+            typst_to_lsp::range(
+                start..typst_offset,
+                source.as_ref(),
+                self.get_const_config().position_encoding,
+            )
+            .raw_range
+        };
+
+        if typed.starts_with('@') && user.kind() == SyntaxKind::ModuleImport {
+            return Some(PathCompletionContext::Package {
+                range: range_from(leaf.offset() + 1),
+            });
+        }
+
+        let (typed_dir, typed_name) = typed.rsplit_once('/').unwrap_or(("", typed));
+        let source_path = source.as_ref().path().to_owned();
+        let dir = match typed_dir.strip_prefix('/') {
+            Some(typed_dir) => world
+                .get_workspace()
+                .get_root(&source_path)?
+                .join(typed_dir),
+            None if typed.starts_with('/') => world.get_workspace().get_root(&source_path)?,
+            None => source_path.parent()?.join(typed_dir),
+        };
+
+        Some(PathCompletionContext::File {
+            dir,
+            extensions: Self::get_path_extensions(user),
+            range: range_from(typst_offset - typed_name.len()),
+            source_path,
+        })
+    }
+
+    /// Lists the files and directories the path being typed could continue with, or the installed
+    /// packages the spec being typed could refer to
+    pub async fn get_path_completions(&self, context: PathCompletionContext) -> CompletionResponse {
+        let completions = match context {
+            PathCompletionContext::File {
+                dir,
+                extensions,
+                range,
+                source_path,
+            } => Self::get_file_completions(&dir, extensions, range, &source_path).await,
+            PathCompletionContext::Package { range } => Self::get_package_completions(range).await,
+        };
+        CompletionResponse::Array(completions)
+    }
+
+    async fn get_file_completions(
+        dir: &Path,
+        extensions: Option<&[&str]>,
+        range: LspRawRange,
+        source_path: &Path,
+    ) -> Vec<LspCompletion> {
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            return Vec::new();
+        };
+
+        let mut completions = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with('.') {
+                continue;
+            }
+            let Ok(file_type) = entry.file_type().await else {
+                continue;
+            };
+
+            if file_type.is_dir() {
+                let label = format!("{name}/");
+                completions.push(LspCompletion {
+                    kind: Some(CompletionItemKind::FOLDER),
+                    // Directories come before files
+                    sort_text: Some(format!("0{label}")),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range,
+                        new_text: label.clone(),
+                    })),
+                    // Carry on completing inside the directory once it's picked
+                    command: Some(Command {
+                        title: "Suggest".to_owned(),
+                        command: "editor.action.triggerSuggest".to_owned(),
+                        arguments: None,
+                    }),
+                    label,
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let path = entry.path();
+            let has_extension = extensions.map_or(true, |extensions| {
+                path.extension().map_or(false, |extension| {
+                    extensions.iter().any(|e| extension == *e)
+                })
+            });
+            if !has_extension || path == source_path {
+                continue;
+            }
+            completions.push(LspCompletion {
+                kind: Some(CompletionItemKind::FILE),
+                sort_text: Some(format!("1{name}")),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: name.clone(),
+                })),
+                label: name,
+                ..Default::default()
+            });
+        }
+
+        completions
+    }
+
+    /// Lists the packages in the directories Typst installs them to, laid out as
+    /// `{namespace}/{name}/{version}`
+    async fn get_package_completions(range: LspRawRange) -> Vec<LspCompletion> {
+        let package_dirs = [dirs::data_dir(), dirs::cache_dir()]
+            .into_iter()
+            .flatten()
+            .map(|dir| dir.join("typst").join("packages"));
+
+        let mut specs = Vec::new();
+        for package_dir in package_dirs {
+            for namespace in Self::list_dirs(&package_dir).await {
+                for name in Self::list_dirs(&package_dir.join(&namespace)).await {
+                    let versions_dir = package_dir.join(&namespace).join(&name);
+                    for version in Self::list_dirs(&versions_dir).await {
+                        specs.push(format!("@{namespace}/{name}:{version}"));
+                    }
+                }
+            }
+        }
+        specs.sort();
+        specs.dedup();
+
+        specs
+            .into_iter()
+            .map(|spec| LspCompletion {
+                kind: Some(CompletionItemKind::MODULE),
+                text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                    range,
+                    new_text: spec.clone(),
+                })),
+                label: spec,
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    async fn list_dirs(dir: &Path) -> Vec<String> {
+        let Ok(mut entries) = tokio::fs::read_dir(dir).await else {
+            return Vec::new();
+        };
+
+        let mut names = Vec::new();
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let is_dir = entry
+                .file_type()
+                .await
+                .map_or(false, |file_type| file_type.is_dir());
+            if is_dir {
+                names.push(entry.file_name().to_string_lossy().into_owned());
+            }
+        }
+        names
+    }
+
+    /// Gets the extensions of files which can be loaded by the import, include or function call
+    fn get_path_extensions(user: &LinkedNode) -> Option<&'static [&'static str]> {
+        if matches!(
+            user.kind(),
+            SyntaxKind::ModuleImport | SyntaxKind::ModuleInclude
+        ) {
+            return Some(&["typ"]);
+        }
+
+        let callee = user.children().next()?;
+        match callee.text().as_str() {
+            "image" => Some(&IMAGE_EXTENSIONS),
+            "csv" => Some(&["csv"]),
+            "json" => Some(&["json"]),
+            "yaml" => Some(&["yaml", "yml"]),
+            "xml" => Some(&["xml"]),
+            "bibliography" => Some(&["bib", "yaml", "yml"]),
+            _ => None,
+        }
+    }
+
+    /// Gets the labels declared anywhere in the workspace, if the offset is in a reference or in
+    /// the arguments of a function taking a label. Sources which were closed but are still cached
+    /// contribute their labels too.
+    fn get_label_completions(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        typst_offset: TypstOffset,
+    ) -> Option<Vec<LspCompletion>> {
+        let (range, needs_brackets) = self.get_label_completion_range(source, typst_offset)?;
+        let lsp_range = typst_to_lsp::range(
+            range,
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+
+        let mut completions: Vec<LspCompletion> = Vec::new();
+        for candidate_source in world.get_workspace().sources.get_sources() {
+            let root = LinkedNode::new(candidate_source.as_ref().root());
+            for label in self.get_descendants(&root) {
+                if !self.is_label_declaration(&label) {
+                    continue;
+                }
+                let Some(name) = self.get_label_name(&label) else {
+                    continue;
+                };
+                if completions
+                    .iter()
+                    .any(|completion| completion.label == name)
+                {
+                    continue;
+                }
+
+                let new_text = if needs_brackets {
+                    format!("<{name}>")
+                } else {
+                    name.clone()
+                };
+                completions.push(LspCompletion {
+                    kind: Some(CompletionItemKind::REFERENCE),
+                    detail: Self::get_label_detail(&label),
+                    text_edit: Some(CompletionTextEdit::Edit(TextEdit {
+                        range: lsp_range.raw_range,
+                        new_text,
+                    })),
+                    label: name,
+                    ..Default::default()
+                });
+            }
+        }
+
+        Some(completions)
+    }
+
+    /// Gets the range of the partial label name at the offset, which completions replace, and
+    /// whether the name must be wrapped in `<>` to make a label. That's the case in the arguments
+    /// of functions taking a label, but not after `@`.
+    fn get_label_completion_range(
+        &self,
+        source: &Source,
+        typst_offset: TypstOffset,
+    ) -> Option<(TypstRange, bool)> {
+        let text = source.as_ref().text();
+        let start = text
+            .get(..typst_offset)?
+            .trim_end_matches(is_label_char)
+            .len();
+        let after = text.get(typst_offset..)?;
+        let end = typst_offset + after.len() - after.trim_start_matches(is_label_char).len();
+
+        let leaf = self.get_leaf(source, typst_offset)?;
+        if matches!(
+            leaf.kind(),
+            SyntaxKind::Str | SyntaxKind::Raw | SyntaxKind::LineComment | SyntaxKind::BlockComment
+        ) {
+            return None;
+        }
+
+        match text[..start].chars().next_back() {
+            Some('@') => Some((start..end, false)),
+            before => {
+                // Right after the closing parenthesis is already outside of the arguments
+                let in_label_args = leaf.kind() != SyntaxKind::RightParen
+                    && std::iter::successors(Some(leaf.clone()), |node| node.parent().cloned())
+                        .any(|node| Self::is_label_function_args(&node));
+                if !in_label_args {
+                    return None;
+                }
+                Some((start..end, before != Some('<')))
+            }
+        }
+    }
+
+    fn is_label_function_args(node: &LinkedNode) -> bool {
+        node.kind() == SyntaxKind::Args
+            && node
+                .parent()
+                .and_then(|call| call.children().next())
+                .map_or(false, |callee| {
+                    callee.kind() == SyntaxKind::Ident
+                        && LABEL_FUNCTIONS.contains(&callee.text().as_str())
+                })
+    }
+
+    /// Describes what the label is attached to, using the text of headings and the captions of
+    /// figures
+    fn get_label_detail(label: &LinkedNode) -> Option<String> {
+        // A label at the end of a heading is part of the heading's body
+        let heading = label
+            .parent()
+            .and_then(|body| body.parent())
+            .filter(|parent| parent.kind() == SyntaxKind::Heading);
+        if let Some(heading) = heading {
+            let body = heading
+                .children()
+                .find(|child| child.kind() == SyntaxKind::Markup)?;
+            let title: String = body
+                .children()
+                .filter(|child| child.kind() != SyntaxKind::Label)
+                .map(|child| child.get().clone().into_text().to_string())
+                .collect();
+            return Some(title.trim().to_owned());
+        }
+
+        let target = label.prev_sibling()?;
+        match target.kind() {
+            SyntaxKind::FuncCall => {
+                let callee = target.children().next()?;
+                if callee.text() != "figure" {
+                    return None;
+                }
+                let caption = target
+                    .children()
+                    .find(|child| child.kind() == SyntaxKind::Args)?
+                    .children()
+                    .filter(|arg| arg.kind() == SyntaxKind::Named)
+                    .find(|named| {
+                        named
+                            .children()
+                            .next()
+                            .map_or(false, |name| name.text() == "caption")
+                    })
+                    .and_then(|named| named.children().last())
+                    .map(|caption| caption.get().clone().into_text());
+                let caption = caption.as_deref().map_or("", |caption| {
+                    caption.trim_start_matches('[').trim_end_matches(']').trim()
+                });
+                Some(format!("Figure: {caption}"))
+            }
+            SyntaxKind::Equation => Some("Equation".to_owned()),
+            _ => None,
+        }
+    }
+
+    /// Converts the completion, adding the signature and documentation of standard library
+    /// functions
+    fn completion_with_docs(&self, completion: &TypstCompletion, scope: &Scope) -> LspCompletion {
+        let mut lsp_completion = typst_to_lsp::completion(completion);
+
+        if matches!(completion.kind, TypstCompletionKind::Func) {
+            if let Some(Value::Func(func)) = scope.get(&completion.label) {
+                if let Some(info) = func.info() {
+                    let (signature, _) = self.get_param_information(info);
+                    lsp_completion.detail = Some(signature);
+                    lsp_completion.documentation = Some(self.markdown_docs(info.docs));
+                }
+            }
+        }
+
+        lsp_completion
+    }
+}
+
+/// Whether the character can be part of a label's name
+fn is_label_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.' | ':')
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::sync::RwLock;
+    use tower_lsp::LspService;
+
+    use crate::config::{ConstConfig, PositionEncoding};
+    use crate::lsp_typst_boundary::LspRange;
+    use crate::test_utils::TempDir;
+    use crate::workspace::Workspace;
+
+    use super::*;
+
+    /// Gets the text of the label completion range at the end of the first occurrence of `at` in
+    /// the text, along with whether completions need `<>` around them
+    fn label_completion_range(text: &str, at: &str) -> Option<(String, bool)> {
+        let mut source = Source::new_detached();
+        source.replace(text.to_owned());
+        let (service, _) = LspService::new(TypstServer::with_client);
+
+        let offset = text.find(at).unwrap() + at.len();
+        let (range, needs_brackets) = service
+            .inner()
+            .get_label_completion_range(&source, offset)?;
+        Some((text[range].to_owned(), needs_brackets))
+    }
+
+    /// Opens the text as the file at `path` in the directory, then gets the path completion
+    /// context at the end of the first occurrence of `at` in it, along with the text its range
+    /// covers, which is the part of the path typed before the position
+    fn path_completion_context(
+        dir: &TempDir,
+        path: &str,
+        text: &str,
+        at: &str,
+    ) -> Option<(PathCompletionContext, String)> {
+        let uri = typst_to_lsp::path_to_uri(&dir.write(path, text)).unwrap();
+        let mut workspace = Workspace::default();
+        workspace
+            .sources
+            .insert_open(&uri, text.to_owned())
+            .unwrap();
+        let id = workspace.sources.get_id_by_uri(&uri).unwrap();
+        let world = WorkspaceWorld::new(
+            Arc::new(RwLock::new(workspace)).try_read_owned().unwrap(),
+            id,
+        );
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let server = service.inner();
+        server
+            .const_config
+            .set(ConstConfig {
+                position_encoding: PositionEncoding::Utf16,
+                root_paths: Vec::new(),
+                supports_work_done_progress: false,
+                supports_pull_diagnostics: false,
+                supports_diagnostic_refresh: false,
+            })
+            .unwrap();
+
+        let source = world.get_workspace().sources.get_open_source_by_id(id);
+        let offset = text.find(at).unwrap() + at.len();
+        let position =
+            typst_to_lsp::offset_to_position(offset, PositionEncoding::Utf16, source.as_ref());
+        let context = server.get_path_completion_context(&world, source, position)?;
+        let range = match &context {
+            PathCompletionContext::File { range, .. } => *range,
+            PathCompletionContext::Package { range } => *range,
+        };
+        let range = lsp_to_typst::range(
+            &LspRange::new(range, PositionEncoding::Utf16),
+            source.as_ref(),
+        );
+        Some((context, text[range].to_owned()))
+    }
+
+    #[test]
+    fn label_completion_range_in_ref() {
+        let range = label_completion_range("= Intro <intro>\nSee @in", "@in");
+        assert_eq!(range, Some(("in".to_owned(), false)));
+    }
+
+    #[test]
+    fn label_completion_range_covers_whole_name() {
+        let range = label_completion_range("See @intro here", "@in");
+        assert_eq!(range, Some(("intro".to_owned(), false)));
+    }
+
+    #[test]
+    fn label_completion_range_in_ref_function() {
+        let range = label_completion_range("#ref(<intro>)", "<in");
+        assert_eq!(range, Some(("intro".to_owned(), false)));
+    }
+
+    #[test]
+    fn label_completion_range_outside_ref() {
+        assert_eq!(label_completion_range("See intro here", "in"), None);
+        assert_eq!(label_completion_range("#let s = \"@intro\"", "@in"), None);
+    }
+
+    #[test]
+    fn path_completion_context_in_import() {
+        let dir = TempDir::new("completion-import");
+        let text = "#import \"chapters/int\": value";
+        let context = path_completion_context(&dir, "main.typ", text, "chapters/in");
+
+        let Some((
+            PathCompletionContext::File {
+                dir: completion_dir,
+                extensions,
+                ..
+            },
+            typed,
+        )) = context
+        else {
+            panic!("import path should complete files");
+        };
+        assert_eq!(completion_dir, dir.path().join("chapters"));
+        assert_eq!(extensions, Some(&["typ"][..]));
+        assert_eq!(typed, "in");
+    }
+
+    #[test]
+    fn path_completion_context_from_root() {
+        let dir = TempDir::new("completion-root");
+        dir.write("typst.toml", "");
+        let text = "#import \"/lib/util\": value";
+        let context = path_completion_context(&dir, "chapters/main.typ", text, "/lib/ut");
+
+        let Some((
+            PathCompletionContext::File {
+                dir: completion_dir,
+                ..
+            },
+            typed,
+        )) = context
+        else {
+            panic!("import path should complete files");
+        };
+        assert_eq!(completion_dir, dir.path().join("lib"));
+        assert_eq!(typed, "ut");
+    }
+
+    #[test]
+    fn path_completion_context_in_package_import() {
+        let dir = TempDir::new("completion-package");
+        let text = "#import \"@preview/example\": value";
+        let context = path_completion_context(&dir, "main.typ", text, "@preview/ex");
+
+        let Some((PathCompletionContext::Package { .. }, typed)) = context else {
+            panic!("package import should complete packages");
+        };
+        assert_eq!(typed, "@preview/ex");
+    }
+
+    #[test]
+    fn path_completion_context_outside_path() {
+        let dir = TempDir::new("completion-outside-path");
+        let text = "#let s = \"chapters/int\"";
+        assert!(path_completion_context(&dir, "main.typ", text, "chapters/in").is_none());
+    }
+}
@@ -0,0 +1,483 @@
+use tower_lsp::lsp_types::request::GotoTypeDefinitionResponse;
+use tower_lsp::lsp_types::{GotoDefinitionResponse, Location};
+use typst::eval::Value;
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::world::{resolve_source_path, WorkspaceWorld};
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, TypstRange, TypstSource};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// Most bindings followed when tracing a value through bindings like `#let y = x`
+const MAX_TYPE_DEFINITION_DEPTH: usize = 16;
+
+impl TypstServer {
+    pub fn get_definition(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<GotoDefinitionResponse> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        let leaf = self.get_label_node(self.get_leaf(source, typst_offset)?);
+
+        let location = match leaf.kind() {
+            SyntaxKind::Ident | SyntaxKind::MathIdent => {
+                self.find_ident_definition(world, source, &leaf)
+            }
+            SyntaxKind::Ref | SyntaxKind::Label => self.find_label_definition(world, &leaf),
+            SyntaxKind::Str => self.find_import_definition(world, source, &leaf),
+            _ => None,
+        }?;
+
+        Some(GotoDefinitionResponse::Scalar(location))
+    }
+
+    /// Gets where the function producing the identifier's value is defined. For a binding like
+    /// `#let x = constructor(..)`, that's the `let` of `constructor`, and bindings to other
+    /// bindings are followed. Values from the standard library have no definition to go to.
+    pub fn get_type_definition(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<GotoTypeDefinitionResponse> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        let leaf = self.get_leaf(source, typst_offset)?;
+        if !matches!(leaf.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent) {
+            return None;
+        }
+
+        let location =
+            self.find_type_definition(world, source, &leaf, MAX_TYPE_DEFINITION_DEPTH)?;
+        Some(GotoTypeDefinitionResponse::Scalar(location))
+    }
+
+    fn find_type_definition(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        ident: &LinkedNode,
+        depth: usize,
+    ) -> Option<Location> {
+        let (definition_source, definition) = self.resolve_definition(world, source, ident)?;
+        let binding = definition
+            .parent()
+            .filter(|parent| parent.kind() == SyntaxKind::LetBinding)?;
+        let init = binding
+            .children()
+            .skip_while(|child| child.kind() != SyntaxKind::Eq)
+            .skip(1)
+            .find(|child| !child.kind().is_trivia());
+
+        let traced = init.and_then(|init| match init.kind() {
+            SyntaxKind::FuncCall => {
+                let callee = init
+                    .children()
+                    .next()
+                    .filter(|callee| callee.kind() == SyntaxKind::Ident)?;
+                self.find_ident_definition(world, definition_source, &callee)
+            }
+            SyntaxKind::Ident if depth > 0 => {
+                self.find_type_definition(world, definition_source, &init, depth - 1)
+            }
+            _ => None,
+        });
+        traced
+            .or_else(|| self.find_value_function_definition(world, definition_source, &definition))
+    }
+
+    /// Finds the definition of the named function a top-level binding's evaluated value is, for
+    /// values the syntax can't be traced through, like functions returned by other functions
+    fn find_value_function_definition(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        definition: &LinkedNode,
+    ) -> Option<Location> {
+        let (Some(module), _) = self.eval_source(world, source) else {
+            return None;
+        };
+        let Some(Value::Func(func)) = module.scope().get(definition.text()) else {
+            return None;
+        };
+        let name = func.name()?;
+        if name == definition.text().as_str() {
+            return None;
+        }
+        let (function_source, function) = self.find_top_level_binding(source, name)?;
+        self.location(function_source.as_ref(), function.range())
+    }
+
+    fn find_ident_definition(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        ident: &LinkedNode,
+    ) -> Option<Location> {
+        let (definition_source, binding) = self.resolve_definition(world, source, ident)?;
+        self.location(definition_source.as_ref(), binding.range())
+    }
+
+    /// Finds the source and identifier where the name used by `ident` is ultimately defined,
+    /// following imports into the files they import from
+    pub fn resolve_definition<'a>(
+        &self,
+        world: &'a WorkspaceWorld,
+        source: &'a Source,
+        ident: &LinkedNode<'a>,
+    ) -> Option<(&'a Source, LinkedNode<'a>)> {
+        let Some(binding) = self.find_binding(ident) else {
+            return self.find_wildcard_import_binding(world, source, ident.text());
+        };
+
+        let import = binding
+            .parent()
+            .filter(|parent| parent.kind() == SyntaxKind::ImportItems)
+            .and_then(|items| items.parent());
+        let imported_binding = import.and_then(|import| {
+            let path_node = import
+                .children()
+                .find(|child| child.kind() == SyntaxKind::Str)?;
+            let imported = self.get_imported_source(world, source, &path_node)?;
+            self.find_top_level_binding(imported, binding.text())
+        });
+
+        Some(imported_binding.unwrap_or((source, binding)))
+    }
+
+    fn find_label_definition(&self, world: &WorkspaceWorld, node: &LinkedNode) -> Option<Location> {
+        let name = self.get_label_name(node)?;
+
+        // Labels are global to the document, so they may be declared in any file
+        let workspace = world.get_workspace();
+        workspace
+            .sources
+            .get_sources()
+            .into_iter()
+            .find_map(|source| {
+                let root = LinkedNode::new(source.as_ref().root());
+                self.get_descendants(&root)
+                    .into_iter()
+                    .find(|node| {
+                        self.is_label_declaration(node)
+                            && self.get_label_name(node).as_deref() == Some(name.as_str())
+                    })
+                    .and_then(|declaration| self.location(source.as_ref(), declaration.range()))
+            })
+    }
+
+    fn find_import_definition(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        path_node: &LinkedNode,
+    ) -> Option<Location> {
+        let parent = path_node.parent()?;
+        if !matches!(
+            parent.kind(),
+            SyntaxKind::ModuleImport | SyntaxKind::ModuleInclude
+        ) {
+            return None;
+        }
+
+        let imported = self.get_imported_source(world, source, path_node)?;
+        self.location(imported.as_ref(), 0..0)
+    }
+
+    fn find_wildcard_import_binding<'a>(
+        &self,
+        world: &'a WorkspaceWorld,
+        source: &'a Source,
+        name: &str,
+    ) -> Option<(&'a Source, LinkedNode<'a>)> {
+        let root = LinkedNode::new(source.as_ref().root());
+        self.get_descendants(&root)
+            .into_iter()
+            .filter(|node| node.kind() == SyntaxKind::ModuleImport)
+            .filter(|import| {
+                import
+                    .children()
+                    .any(|child| child.kind() == SyntaxKind::Star)
+            })
+            .filter_map(|import| {
+                let path_node = import
+                    .children()
+                    .find(|child| child.kind() == SyntaxKind::Str)?;
+                self.get_imported_source(world, source, &path_node)
+            })
+            .find_map(|imported| self.find_top_level_binding(imported, name))
+    }
+
+    fn find_top_level_binding<'a>(
+        &self,
+        source: &'a Source,
+        name: &str,
+    ) -> Option<(&'a Source, LinkedNode<'a>)> {
+        let root = LinkedNode::new(source.as_ref().root());
+        self.get_top_level_bindings(&root)
+            .into_iter()
+            .find(|binding| binding.text().as_str() == name)
+            .map(|binding| (source, binding))
+    }
+
+    /// Loads the source that the string literal in an `import` or `include` refers to
+    pub fn get_imported_source<'a>(
+        &self,
+        world: &'a WorkspaceWorld,
+        source: &Source,
+        path_node: &LinkedNode,
+    ) -> Option<&'a Source> {
+        let workspace = world.get_workspace();
+        let path = path_node.text().trim_matches('"');
+        let path = resolve_source_path(workspace, source.as_ref(), path).ok()?;
+        let uri = typst_to_lsp::path_to_uri(&path).ok()?;
+
+        let sources = &workspace.sources;
+        let id = sources.cache(uri).ok()?;
+        Some(sources.get_open_source_by_id(id))
+    }
+
+    /// Finds the identifier which binds the name used by `ident`, taking scoping and shadowing
+    /// into account. Returns `None` if the name isn't bound in this file, e.g. because it comes from
+    /// the standard library or a wildcard import.
+    pub fn find_binding<'a>(&self, ident: &LinkedNode<'a>) -> Option<LinkedNode<'a>> {
+        if self.is_binding_site(ident) {
+            return Some(ident.clone());
+        }
+
+        let name = ident.text();
+        let find_in = |bindings: Vec<LinkedNode<'a>>| {
+            bindings
+                .into_iter()
+                .rev()
+                .find(|binding| binding.text() == name)
+        };
+
+        let mut child = ident.clone();
+        while let Some(parent) = child.parent().cloned() {
+            let is_body =
+                !matches!(child.kind(), SyntaxKind::Params) && child.offset() != parent.offset();
+            match parent.kind() {
+                SyntaxKind::Closure if is_body => {
+                    let params = parent
+                        .children()
+                        .find(|node| node.kind() == SyntaxKind::Params);
+                    if let Some(binding) =
+                        params.and_then(|params| find_in(self.get_bound_idents(&params)))
+                    {
+                        return Some(binding);
+                    }
+                    // Named closures may call themselves
+                    if let Some(binding) = find_in(self.get_bound_idents(&parent)) {
+                        return Some(binding);
+                    }
+                }
+                SyntaxKind::ForLoop => {
+                    if let Some(binding) = find_in(self.get_bound_idents(&parent)) {
+                        if binding.offset() < child.offset() {
+                            return Some(binding);
+                        }
+                    }
+                }
+                _ => {}
+            }
+
+            let mut sibling = child.prev_sibling();
+            while let Some(node) = sibling {
+                if matches!(
+                    node.kind(),
+                    SyntaxKind::LetBinding | SyntaxKind::ModuleImport
+                ) {
+                    if let Some(binding) = find_in(self.get_bound_idents(&node)) {
+                        return Some(binding);
+                    }
+                }
+                sibling = node.prev_sibling();
+            }
+
+            child = parent;
+        }
+
+        None
+    }
+
+    /// Whether this identifier is the one being bound by a `let`, `import`, parameter list, or
+    /// `for` loop
+    pub fn is_binding_site(&self, ident: &LinkedNode) -> bool {
+        let mut ancestor = ident.parent();
+        while let Some(node) = ancestor {
+            if matches!(
+                node.kind(),
+                SyntaxKind::LetBinding
+                    | SyntaxKind::Closure
+                    | SyntaxKind::Params
+                    | SyntaxKind::ForLoop
+                    | SyntaxKind::ModuleImport
+            ) {
+                return self
+                    .get_bound_idents(node)
+                    .iter()
+                    .any(|binding| binding.offset() == ident.offset());
+            }
+            ancestor = node.parent();
+        }
+        false
+    }
+
+    /// Gets the identifiers bound by a binding construct
+    pub fn get_bound_idents<'a>(&self, node: &LinkedNode<'a>) -> Vec<LinkedNode<'a>> {
+        match node.kind() {
+            SyntaxKind::LetBinding => node
+                .children()
+                .take_while(|child| child.kind() != SyntaxKind::Eq)
+                .flat_map(|child| match child.kind() {
+                    SyntaxKind::Closure => self.get_bound_idents(&child),
+                    _ => self.get_idents(&child),
+                })
+                .collect(),
+            SyntaxKind::Closure => node
+                .children()
+                .take_while(|child| child.kind() != SyntaxKind::Params)
+                .filter(|child| child.kind() == SyntaxKind::Ident)
+                .collect(),
+            SyntaxKind::Params => node
+                .children()
+                .flat_map(|child| match child.kind() {
+                    SyntaxKind::Named => child
+                        .children()
+                        .find(|name| name.kind() == SyntaxKind::Ident)
+                        .into_iter()
+                        .collect(),
+                    _ => self.get_idents(&child),
+                })
+                .collect(),
+            SyntaxKind::ForLoop => node
+                .children()
+                .take_while(|child| child.kind() != SyntaxKind::In)
+                .flat_map(|child| self.get_idents(&child))
+                .collect(),
+            SyntaxKind::ModuleImport => node
+                .children()
+                .filter(|child| child.kind() == SyntaxKind::ImportItems)
+                .flat_map(|items| self.get_idents(&items))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Gets the identifiers bound at the top level of a file, which are the ones it exports
+    pub fn get_top_level_bindings<'a>(&self, root: &LinkedNode<'a>) -> Vec<LinkedNode<'a>> {
+        root.children()
+            .filter(|child| child.kind() == SyntaxKind::LetBinding)
+            .flat_map(|binding| self.get_bound_idents(&binding))
+            .collect()
+    }
+
+    fn get_idents<'a>(&self, node: &LinkedNode<'a>) -> Vec<LinkedNode<'a>> {
+        self.get_descendants(node)
+            .into_iter()
+            .filter(|node| node.kind() == SyntaxKind::Ident)
+            .collect()
+    }
+
+    /// Gets the node and all of its descendants in pre-order
+    pub fn get_descendants<'a>(&self, node: &LinkedNode<'a>) -> Vec<LinkedNode<'a>> {
+        let mut descendants = vec![node.clone()];
+        for child in node.children() {
+            descendants.extend(self.get_descendants(&child));
+        }
+        descendants
+    }
+
+    /// Gets the reference a leaf is the marker of, like the `@intro` in `@intro[Section]`, since
+    /// that's the node holding its name. Other leaves are returned as they are.
+    pub fn get_label_node<'a>(&self, leaf: LinkedNode<'a>) -> LinkedNode<'a> {
+        match leaf.kind() {
+            SyntaxKind::RefMarker => leaf.parent().cloned().unwrap_or(leaf),
+            _ => leaf,
+        }
+    }
+
+    /// Gets the name of a label or reference, without the surrounding `<>` or leading `@`
+    pub fn get_label_name(&self, node: &LinkedNode) -> Option<String> {
+        match node.kind() {
+            SyntaxKind::Label => {
+                let text = node.text();
+                Some(
+                    text.trim_start_matches('<')
+                        .trim_end_matches('>')
+                        .to_owned(),
+                )
+            }
+            SyntaxKind::Ref => {
+                let marker = node.children().next();
+                let text = marker.as_ref().map_or(node.text(), |marker| marker.text());
+                Some(text.trim_start_matches('@').to_owned())
+            }
+            _ => None,
+        }
+    }
+
+    /// Gets the range of the name of a label or reference, without the `<`, `>` or `@` around it
+    pub fn get_label_name_range(&self, node: &LinkedNode) -> Option<TypstRange> {
+        let name = self.get_label_name(node)?;
+        // The `<` or `@` before the name is one byte long
+        let start = node.offset() + 1;
+        Some(start..start + name.len())
+    }
+
+    /// Whether the label is attached to content, rather than used as a value (like in
+    /// `ref(<label>)`)
+    pub fn is_label_declaration(&self, node: &LinkedNode) -> bool {
+        node.kind() == SyntaxKind::Label
+            && node
+                .parent()
+                .map_or(false, |parent| parent.kind() == SyntaxKind::Markup)
+    }
+
+    pub fn location(&self, source: &TypstSource, typst_range: TypstRange) -> Option<Location> {
+        let uri = typst_to_lsp::path_to_uri(source.path()).ok()?;
+        let lsp_range = typst_to_lsp::range(
+            typst_range,
+            source,
+            self.get_const_config().position_encoding,
+        );
+        Some(Location {
+            uri,
+            range: lsp_range.raw_range,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::LspService;
+
+    use super::*;
+
+    #[test]
+    fn ref_marker_is_label_node() {
+        let source = TypstSource::detached("= Intro <intro>\nSee @intro[Section].");
+        let offset = source.text().find('@').unwrap() + 1;
+        let leaf = LinkedNode::new(source.root()).leaf_at(offset).unwrap();
+        assert_eq!(leaf.kind(), SyntaxKind::RefMarker);
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let node = service.inner().get_label_node(leaf);
+        assert_eq!(node.kind(), SyntaxKind::Ref);
+        assert_eq!(
+            service.inner().get_label_name(&node).as_deref(),
+            Some("intro")
+        );
+    }
+}
@@ -1,22 +1,48 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 use futures::future::join_all;
-use tower_lsp::lsp_types::Url;
+use tower_lsp::lsp_types::request::WorkspaceDiagnosticRefresh;
+use tower_lsp::lsp_types::{
+    DiagnosticSeverity, DocumentDiagnosticReport, DocumentDiagnosticReportKind,
+    DocumentDiagnosticReportResult, FullDocumentDiagnosticReport,
+    RelatedFullDocumentDiagnosticReport, Url, WorkspaceDiagnosticReport,
+    WorkspaceDiagnosticReportResult, WorkspaceDocumentDiagnosticReport,
+    WorkspaceFullDocumentDiagnosticReport,
+};
 
+use crate::config::{Config, DiagnosticLevel};
 use crate::lsp_typst_boundary::LspDiagnostic;
+use crate::workspace::source_manager::SourceId;
 use crate::workspace::Workspace;
 
 use super::TypstServer;
 
+/// Comment which suppresses diagnostics on the next line whose message contains one of the
+/// comma-separated strings in parentheses after it, like `// typst-lsp: allow(unused)`
+pub const ALLOW_COMMENT: &str = "// typst-lsp: allow(";
+
 impl TypstServer {
+    /// Publishes the diagnostics, or keeps them for clients which pull diagnostics and tells
+    /// those clients to pull them again. Clients which pull diagnostics but can't be told to pull
+    /// again get them published too.
     pub async fn update_all_diagnostics(
         &self,
         workspace: &Workspace,
-        mut diagnostics: HashMap<Url, Vec<LspDiagnostic>>,
+        config: &Config,
+        diagnostics: HashMap<Url, Vec<LspDiagnostic>>,
     ) {
-        // Clear the previous diagnostics (could be done with the refresh notification when implemented by tower-lsp)
-        for uri in workspace.sources.get_uris() {
-            diagnostics.entry(uri.clone()).or_insert_with(Vec::new);
+        let diagnostics = self.finish_diagnostics(workspace, config, diagnostics);
+
+        let const_config = self.get_const_config();
+        if const_config.supports_pull_diagnostics {
+            *self.pulled_diagnostics.lock() = Some(diagnostics.clone());
+            if const_config.supports_diagnostic_refresh {
+                let _ = self
+                    .client
+                    .send_request::<WorkspaceDiagnosticRefresh>(())
+                    .await;
+                return;
+            }
         }
 
         let diagnostic_futures = diagnostics.into_iter().map(|(url, file_diagnostics)| {
@@ -24,4 +50,260 @@ impl TypstServer {
         });
         join_all(diagnostic_futures).await;
     }
+
+    /// Gets the diagnostics of the document for clients which ask for them, as they were last
+    /// computed. Diagnostics of the other files compiled along with it are reported as related
+    /// documents.
+    pub async fn get_document_diagnostics(&self, uri: &Url) -> DocumentDiagnosticReportResult {
+        let source_id = self.workspace.read().await.sources.get_id_by_uri(uri);
+        let mut diagnostics = self.get_pulled_diagnostics(source_id).await;
+
+        let items = diagnostics.remove(uri).unwrap_or_default();
+        let related_documents: HashMap<_, _> = diagnostics
+            .into_iter()
+            .map(|(uri, items)| {
+                let report = FullDocumentDiagnosticReport {
+                    result_id: None,
+                    items,
+                };
+                (uri, DocumentDiagnosticReportKind::Full(report))
+            })
+            .collect();
+
+        DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(
+            RelatedFullDocumentDiagnosticReport {
+                related_documents: (!related_documents.is_empty()).then_some(related_documents),
+                full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                    result_id: None,
+                    items,
+                },
+            },
+        ))
+    }
+
+    /// Gets the diagnostics of all known files for clients which ask for them, as they were last
+    /// computed
+    pub async fn get_workspace_diagnostics(&self) -> WorkspaceDiagnosticReportResult {
+        let diagnostics = self.get_pulled_diagnostics(None).await;
+
+        let items = diagnostics
+            .into_iter()
+            .map(|(uri, items)| {
+                WorkspaceDocumentDiagnosticReport::Full(WorkspaceFullDocumentDiagnosticReport {
+                    uri,
+                    version: None,
+                    full_document_diagnostic_report: FullDocumentDiagnosticReport {
+                        result_id: None,
+                        items,
+                    },
+                })
+            })
+            .collect();
+
+        WorkspaceDiagnosticReportResult::Report(WorkspaceDiagnosticReport { items })
+    }
+
+    /// Gets the diagnostics last computed. Before any were, the open documents are compiled,
+    /// along with the source if given, or else the pinned main file or the first open one.
+    async fn get_pulled_diagnostics(
+        &self,
+        source_id: Option<SourceId>,
+    ) -> HashMap<Url, Vec<LspDiagnostic>> {
+        if let Some(diagnostics) = self.pulled_diagnostics.lock().clone() {
+            return diagnostics;
+        }
+
+        let source_id = match source_id {
+            Some(source_id) => Some(source_id),
+            None => {
+                let workspace = self.workspace.read().await;
+                self.get_pinned_main(&workspace.sources)
+                    .or_else(|| workspace.sources.get_open_ids().into_iter().next())
+            }
+        };
+        let Some(source_id) = source_id else {
+            return HashMap::new();
+        };
+
+        let world = self.get_world_with_main(source_id).await;
+        let config = self.config.read().await;
+        let main_id = self.get_main_id(world.get_workspace(), source_id);
+        let (diagnostics, _) = self.compile_open_roots(&world.with_main(main_id), main_id);
+        let diagnostics = self.finish_diagnostics(world.get_workspace(), &config, diagnostics);
+
+        *self.pulled_diagnostics.lock() = Some(diagnostics.clone());
+        diagnostics
+    }
+
+    /// Adds lints to the compiler's diagnostics and applies the diagnostic settings, and adds
+    /// empty diagnostics for the other files, which clears their previous ones
+    fn finish_diagnostics(
+        &self,
+        workspace: &Workspace,
+        config: &Config,
+        mut diagnostics: HashMap<Url, Vec<LspDiagnostic>>,
+    ) -> HashMap<Url, Vec<LspDiagnostic>> {
+        for id in workspace.sources.get_open_ids() {
+            let source = workspace.sources.get_open_source_by_id(id);
+            let lints = self.lint_unused(source);
+            if !lints.is_empty() {
+                diagnostics
+                    .entry(source.uri().clone())
+                    .or_default()
+                    .extend(lints);
+            }
+        }
+
+        for (uri, file_diagnostics) in diagnostics.iter_mut() {
+            Self::dedup_diagnostics(file_diagnostics);
+            Self::suggest_missing_files(file_diagnostics);
+            self.apply_diagnostic_config(workspace, config, uri, file_diagnostics);
+        }
+
+        // Clear the previous diagnostics (could be done with the refresh notification when implemented by tower-lsp)
+        for uri in workspace.sources.get_uris() {
+            diagnostics.entry(uri.clone()).or_insert_with(Vec::new);
+        }
+
+        diagnostics
+    }
+
+    /// Drops diagnostics with the same range and message as an earlier one, like an error in a
+    /// file reported by each of the documents importing it. The first of them is kept, so the
+    /// order stays the same from one update to the next.
+    fn dedup_diagnostics(diagnostics: &mut Vec<LspDiagnostic>) {
+        let mut seen = HashSet::new();
+        diagnostics.retain(|diagnostic| {
+            let range = diagnostic.range;
+            let key = (
+                (range.start.line, range.start.character),
+                (range.end.line, range.end.character),
+                diagnostic.message.clone(),
+            );
+            seen.insert(key)
+        });
+    }
+
+    /// Changes the severity of the diagnostics according to the configured rules, and drops those
+    /// which are turned off or suppressed by a comment on the line before them
+    pub fn apply_diagnostic_config(
+        &self,
+        workspace: &Workspace,
+        config: &Config,
+        uri: &Url,
+        diagnostics: &mut Vec<LspDiagnostic>,
+    ) {
+        // Sources which couldn't be loaded, like evicted ones, have no comments to suppress with
+        let text = workspace
+            .sources
+            .get_id_by_uri(uri)
+            .and_then(|id| workspace.sources.get_source_by_id(id))
+            .map(|source| source.as_ref().text());
+        let lines: Vec<&str> = text.map_or_else(Vec::new, |text| text.lines().collect());
+
+        diagnostics.retain_mut(|diagnostic| {
+            let line = diagnostic.range.start.line as usize;
+            let is_allowed = line
+                .checked_sub(1)
+                .and_then(|previous_line| lines.get(previous_line))
+                .map_or(false, |previous_line| {
+                    Self::is_allowed_by_comment(previous_line, &diagnostic.message)
+                });
+            if is_allowed {
+                return false;
+            }
+
+            let rule = config
+                .diagnostic_rules
+                .iter()
+                .find(|rule| diagnostic.message.contains(&rule.message));
+            let severity = match rule.map(|rule| rule.level) {
+                None => return true,
+                Some(DiagnosticLevel::Off) => return false,
+                Some(DiagnosticLevel::Error) => DiagnosticSeverity::ERROR,
+                Some(DiagnosticLevel::Warning) => DiagnosticSeverity::WARNING,
+                Some(DiagnosticLevel::Information) => DiagnosticSeverity::INFORMATION,
+                Some(DiagnosticLevel::Hint) => DiagnosticSeverity::HINT,
+            };
+            diagnostic.severity = Some(severity);
+            true
+        });
+    }
+
+    fn is_allowed_by_comment(line: &str, message: &str) -> bool {
+        let Some((_, allowed)) = line.split_once(ALLOW_COMMENT) else {
+            return false;
+        };
+        let Some((allowed, _)) = allowed.split_once(')') else {
+            return false;
+        };
+        allowed
+            .split(',')
+            .map(str::trim)
+            .any(|allowed| !allowed.is_empty() && message.contains(allowed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use tower_lsp::LspService;
+
+    use crate::lsp_typst_boundary::{typst_to_lsp, LspPosition, LspRawRange};
+    use crate::test_utils::TempDir;
+
+    use super::*;
+
+    fn diagnostic(message: &str) -> LspDiagnostic {
+        LspDiagnostic {
+            message: message.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn diagnostics_of_evicted_source_are_kept() {
+        let dir = TempDir::new("evicted-diagnostics");
+        let uri = typst_to_lsp::path_to_uri(&dir.write("chapter.typ", "= Chapter")).unwrap();
+        let mut workspace = Workspace::default();
+        workspace.sources.cache(uri.clone()).unwrap();
+        workspace.sources.evict_idle(Duration::ZERO);
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let mut diagnostics = vec![diagnostic(
+            "file could not be loaded, so it was compiled as empty",
+        )];
+        service.inner().apply_diagnostic_config(
+            &workspace,
+            &Config::default(),
+            &uri,
+            &mut diagnostics,
+        );
+
+        assert_eq!(diagnostics.len(), 1);
+    }
+
+    #[test]
+    fn diagnostics_of_loaded_source_can_be_allowed() {
+        let dir = TempDir::new("allowed-diagnostics");
+        let text = format!("{ALLOW_COMMENT}unknown variable)\n#x");
+        let uri = typst_to_lsp::path_to_uri(&dir.write("chapter.typ", &text)).unwrap();
+        let workspace = Workspace::default();
+        workspace.sources.cache(uri.clone()).unwrap();
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let mut diagnostics = vec![LspDiagnostic {
+            range: LspRawRange::new(LspPosition::new(1, 1), LspPosition::new(1, 2)),
+            ..diagnostic("unknown variable")
+        }];
+        service.inner().apply_diagnostic_config(
+            &workspace,
+            &Config::default(),
+            &uri,
+            &mut diagnostics,
+        );
+
+        assert!(diagnostics.is_empty());
+    }
 }
@@ -1,64 +1,415 @@
+use std::path::Path;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep;
 use tower_lsp::lsp_types::TextDocumentContentChangeEvent;
+use typst::syntax::SyntaxKind;
 
 use crate::config::{Config, ExportPdfMode};
 use crate::lsp_typst_boundary::world::WorkspaceWorld;
-use crate::lsp_typst_boundary::LspRange;
+use crate::lsp_typst_boundary::{lsp_to_typst, LspDiagnostics, LspRange, TypstRange};
 use crate::workspace::source::Source;
+use crate::workspace::source_manager::{SourceId, SourceManager};
+use crate::workspace::Workspace;
 
+use super::diagnostics::ALLOW_COMMENT;
+use super::progress::Progress;
 use super::TypstServer;
 
 impl TypstServer {
-    /// Apply a single change event to a document
+    /// Apply a single change event to a document, returning whether it may affect diagnostics.
+    /// Changes inside a comment or raw block which leave it intact don't, unless they add or
+    /// remove lines, or edit a comment suppressing diagnostics.
     pub fn apply_single_document_change(
         &self,
         source: &mut Source,
         change: TextDocumentContentChangeEvent,
-    ) {
+    ) -> bool {
         let replacement = change.text;
 
         match change.range {
             Some(range) => {
                 let range = LspRange::new(range, self.get_const_config().position_encoding);
+                let typst_range = lsp_to_typst::range(&range, source.as_ref());
+                // Adding or removing lines moves the diagnostics after them
+                let changes_lines = replacement.contains('\n')
+                    || source.as_ref().text()[typst_range.clone()].contains('\n');
+                let enclosing = if changes_lines {
+                    None
+                } else {
+                    self.get_enclosing_inert_leaf(source, &typst_range)
+                };
+
                 source.edit(&range, &replacement);
+
+                let Some((kind, leaf_range)) = enclosing else {
+                    return true;
+                };
+                let edited_len = leaf_range.len() - typst_range.len() + replacement.len();
+                let is_intact = self
+                    .get_leaf(source, leaf_range.start + 1)
+                    .map_or(false, |leaf| {
+                        leaf.kind() == kind
+                            && leaf.range() == (leaf_range.start..leaf_range.start + edited_len)
+                            && !leaf.text().contains(ALLOW_COMMENT)
+                    });
+                !is_intact
             }
-            None => source.replace(replacement),
+            None => {
+                source.replace(replacement);
+                true
+            }
+        }
+    }
+
+    /// Gets the comment or raw block containing the range, if the range doesn't touch its
+    /// delimiters and it isn't a comment suppressing diagnostics
+    fn get_enclosing_inert_leaf(
+        &self,
+        source: &Source,
+        typst_range: &TypstRange,
+    ) -> Option<(SyntaxKind, TypstRange)> {
+        let leaf = self.get_leaf(source, typst_range.start)?;
+        let text = leaf.text();
+        if text.contains(ALLOW_COMMENT) {
+            return None;
         }
+        let (open_len, close_len) = match leaf.kind() {
+            SyntaxKind::LineComment => (2, 0),
+            SyntaxKind::BlockComment => (2, 2),
+            SyntaxKind::Raw => {
+                let backticks = text.len() - text.trim_start_matches('`').len();
+                (backticks, backticks)
+            }
+            _ => return None,
+        };
+
+        let leaf_range = leaf.range();
+        let is_inside = leaf_range.start + open_len <= typst_range.start
+            && typst_range.end + close_len <= leaf_range.end;
+        is_inside.then_some((leaf.kind(), leaf_range))
+    }
+
+    /// Marks diagnostics as needing to be updated after a change which may affect them
+    pub fn mark_diagnostics_outdated(&self) {
+        self.diagnostics_outdated.store(true, Ordering::SeqCst);
     }
 
+    pub fn are_diagnostics_outdated(&self) -> bool {
+        self.diagnostics_outdated.load(Ordering::SeqCst)
+    }
+
+    /// Marks the diagnostics being computed for earlier changes as stale
+    pub fn start_edit(&self) {
+        self.edit_generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn get_edit_generation(&self) -> u64 {
+        self.edit_generation.load(Ordering::SeqCst)
+    }
+
+    /// Waits for the configured debounce interval after an edit to the source, returning whether
+    /// no other edit was made in the meantime. Only the last of several quick edits should be
+    /// followed by diagnostics.
+    pub async fn debounce_edit(&self, source_id: SourceId) -> bool {
+        let edit_count = {
+            let mut edit_counts = self.edit_counts.lock();
+            let edit_count = edit_counts.entry(source_id).or_default();
+            *edit_count += 1;
+            *edit_count
+        };
+
+        let debounce_ms = self.config.read().await.debounce_ms;
+        if debounce_ms == 0 {
+            return true;
+        }
+        sleep(Duration::from_millis(debounce_ms)).await;
+
+        self.edit_counts.lock().get(&source_id) == Some(&edit_count)
+    }
+
+    /// Updates diagnostics after an edit, exporting too in `OnType` mode. Documents which aren't
+    /// files, like `untitled:` ones, have nowhere to be exported next to, so they're only checked.
+    ///
+    /// When a main file is pinned and includes or imports the edited file, it's checked in place
+    /// of the edited file, so that errors in the edited file are those it has as part of the whole
+    /// document.
     pub async fn on_source_changed(
         &self,
         world: &WorkspaceWorld,
         config: &Config,
         source: &Source,
     ) {
+        let source_id = source.as_ref().id().into();
+        let sources = &world.get_workspace().sources;
+        match self.get_main_id(world.get_workspace(), source_id) {
+            main_id if main_id != source_id => {
+                let main_world = world.with_main(main_id);
+                let main_source = sources.get_open_source_by_id(main_id);
+                self.check_changed_source(&main_world, config, main_source)
+                    .await;
+            }
+            _ => self.check_changed_source(world, config, source).await,
+        }
+    }
+
+    async fn check_changed_source(&self, world: &WorkspaceWorld, config: &Config, source: &Source) {
+        let is_file = source.uri().scheme() == "file";
         match config.export_pdf {
-            ExportPdfMode::OnType => self.run_diagnostics_and_export(world, source).await,
-            _ => self.run_diagnostics(world, source).await,
+            ExportPdfMode::OnType if is_file => {
+                self.run_diagnostics_and_export(world, config, source).await
+            }
+            _ => self.run_diagnostics(world, config, source).await,
+        }
+    }
+
+    /// Updates diagnostics of the open documents, which may depend on the saved file, then
+    /// exports the saved source in `OnSave` mode, reusing the document compiled for diagnostics.
+    /// `OnType` mode has already exported the saved content, since saving doesn't change it. The
+    /// pinned main file, if it includes or imports the saved source, is checked and exported in its
+    /// place.
+    pub async fn on_source_saved(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        source_id: SourceId,
+    ) {
+        let source_id = self.get_main_id(world.get_workspace(), source_id);
+        let world = &world.with_main(source_id);
+        self.run_open_roots_diagnostics(world, config, source_id)
+            .await;
+        if config.export_pdf == ExportPdfMode::OnSave {
+            let source = world
+                .get_workspace()
+                .sources
+                .get_open_source_by_id(source_id);
+            if let Some(document) = self.get_or_compile_document(world) {
+                self.export_document(config, source, &document, &Progress::quiet())
+                    .await;
+            }
         }
     }
 
-    pub async fn run_export(&self, world: &WorkspaceWorld, source: &Source) {
-        let (document, _) = self.compile_source(world);
+    /// Starts compiling the document the just opened source belongs to, unless that was done to
+    /// check it, so that the first edit reuses its layout from Typst's cache rather than starting
+    /// from scratch. That cache is kept on the compile worker, so opening the source doesn't wait
+    /// for the compilation, and since it compiles a snapshot, neither does an edit made meanwhile.
+    /// Files which don't look like the root of a document wouldn't compile on their own, and
+    /// nothing is compiled in the background while idle.
+    pub fn warm_compile(&self, world: &WorkspaceWorld, config: &Config, source_id: SourceId) {
+        if self.idle.is_idle() || config.export_pdf == ExportPdfMode::OnType {
+            return;
+        }
 
-        if let Some(document) = document {
-            self.export_pdf(source, &document).await;
+        let sources = &world.get_workspace().sources;
+        let main_id = self.get_main_id(world.get_workspace(), source_id);
+        if !self.is_document_root(sources.get_open_source_by_id(main_id)) {
+            return;
+        }
+        let world = world.with_main(main_id);
+        let snapshot = world.snapshot(&self.load_dependencies(&world, main_id));
+        drop(
+            self.compile_worker
+                .submit(move || typst::compile(&snapshot).is_ok()),
+        );
+    }
+
+    /// Loads the configured main file and pins it, so that edits don't have to look it up again.
+    /// A main file which can't be loaded isn't pinned, until the setting is applied again.
+    pub async fn update_pinned_main(&self, main_file: Option<&Path>) {
+        let pinned_main = match main_file {
+            Some(main_file) => {
+                let path = self.resolve_workspace_path(main_file);
+                let workspace = self.workspace.read().await;
+                workspace.sources.cache_path(&path).ok()
+            }
+            None => None,
+        };
+        *self.pinned_main.lock() = pinned_main;
+    }
+
+    /// Gets the file pinned as the main file, loading it again if it was evicted, or `None` if no
+    /// file is pinned or it can't be loaded
+    pub fn get_pinned_main(&self, sources: &SourceManager) -> Option<SourceId> {
+        let main_id = (*self.pinned_main.lock())?;
+        if !sources.is_loaded(main_id) {
+            sources.cache(sources.get_uri_by_id(main_id)?).ok()?;
+        }
+        Some(main_id)
+    }
+
+    /// Gets the source to compile for the source: the pinned main file if it includes or imports
+    /// the source, or else the source itself, so that its errors aren't lost when it isn't part of
+    /// the pinned document
+    pub fn get_main_id(&self, workspace: &Workspace, source_id: SourceId) -> SourceId {
+        match self.get_pinned_main(&workspace.sources) {
+            Some(main_id) if self.is_dependency_of(workspace, &[main_id], source_id) => main_id,
+            _ => source_id,
+        }
+    }
+
+    /// Compiles the source and exports it as a PDF, then ends the progress
+    pub async fn run_export(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        source: &Source,
+        progress: Progress,
+    ) {
+        self.report_progress(&progress, "Compiling…".to_owned(), 0)
+            .await;
+        let start = Instant::now();
+        let document = self.get_or_compile_document(world);
+        self.log_timing(config, "compile", source.as_ref().path(), start.elapsed())
+            .await;
+
+        match document {
+            Some(document) => {
+                self.export_pdf(config, source, &document, &progress).await;
+                self.end_progress(progress, "Done").await;
+            }
+            None => self.end_progress(progress, "Compilation failed").await,
         }
     }
 
-    pub async fn run_diagnostics_and_export(&self, world: &WorkspaceWorld, source: &Source) {
+    /// Compiles the source and exports its pages as PNGs, then ends the progress
+    pub async fn run_png_export(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        source: &Source,
+        progress: Progress,
+    ) {
+        self.report_progress(&progress, "Compiling…".to_owned(), 0)
+            .await;
+        let start = Instant::now();
+        let document = self.get_or_compile_document(world);
+        self.log_timing(config, "compile", source.as_ref().path(), start.elapsed())
+            .await;
+
+        match document {
+            Some(document) => {
+                self.export_png(config, source, &document, &progress).await;
+                self.end_progress(progress, "Done").await;
+            }
+            None => self.end_progress(progress, "Compilation failed").await,
+        }
+    }
+
+    pub async fn run_diagnostics_and_export(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        source: &Source,
+    ) {
+        let edit_generation = self.get_edit_generation();
+        self.diagnostics_outdated.store(false, Ordering::SeqCst);
+        let start = Instant::now();
         let (document, diagnostics) = self.compile_source(world);
+        self.log_timing(config, "compile", source.as_ref().path(), start.elapsed())
+            .await;
 
-        self.update_all_diagnostics(world.get_workspace(), diagnostics)
+        if self.get_edit_generation() == edit_generation {
+            self.send_compile_status(world, config, source, document.as_ref(), &diagnostics)
+                .await;
+        }
+        self.publish_diagnostics_if_latest(world, config, diagnostics, edit_generation)
             .await;
+        // Exporting on type happens in the background, so it's quiet
         if let Some(document) = document {
-            self.export_pdf(source, &document).await;
+            self.export_document(config, source, &document, &Progress::quiet())
+                .await;
         }
     }
 
-    pub async fn run_diagnostics(&self, world: &WorkspaceWorld, source: &Source) {
+    pub async fn run_diagnostics(&self, world: &WorkspaceWorld, config: &Config, source: &Source) {
+        let edit_generation = self.get_edit_generation();
+        self.diagnostics_outdated.store(false, Ordering::SeqCst);
+        let start = Instant::now();
         let (_, diagnostics) = self.eval_source(world, source);
+        self.log_timing(config, "eval", source.as_ref().path(), start.elapsed())
+            .await;
+
+        if self.get_edit_generation() == edit_generation {
+            self.send_compile_status(world, config, source, None, &diagnostics)
+                .await;
+        }
+
+        self.publish_diagnostics_if_latest(world, config, diagnostics, edit_generation)
+            .await;
+    }
+
+    /// Compiles the source along with the other open sources which are roots of documents, and
+    /// publishes all of their diagnostics together. Documents split into chapters may have
+    /// several roots open at once. The source itself is compiled even if it isn't open, like a
+    /// pinned main file.
+    pub async fn run_open_roots_diagnostics(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        source_id: SourceId,
+    ) {
+        let edit_generation = self.get_edit_generation();
+        self.diagnostics_outdated.store(false, Ordering::SeqCst);
+        let start = Instant::now();
 
-        self.update_all_diagnostics(world.get_workspace(), diagnostics)
+        let (diagnostics, root_count) = self.compile_open_roots(world, source_id);
+        let sources = &world.get_workspace().sources;
+        let main_path = sources.get_open_source_by_id(source_id).as_ref().path();
+        let phase = format!("compile-{root_count}-roots");
+        self.log_timing(config, &phase, main_path, start.elapsed())
             .await;
+
+        self.publish_diagnostics_if_latest(world, config, diagnostics, edit_generation)
+            .await;
+    }
+
+    /// Compiles the source along with the other open sources which are roots of documents, like
+    /// `run_open_roots_diagnostics`, and gets their diagnostics along with how many roots were
+    /// compiled. The roots are compiled one after the other, on the compile worker holding
+    /// Typst's cache, since compiling them on other threads would start each from scratch.
+    pub fn compile_open_roots(
+        &self,
+        world: &WorkspaceWorld,
+        source_id: SourceId,
+    ) -> (LspDiagnostics, usize) {
+        let sources = &world.get_workspace().sources;
+        let mut root_ids: Vec<SourceId> = sources
+            .get_open_ids()
+            .into_iter()
+            .filter(|&id| id != source_id)
+            .filter(|&id| self.is_document_root(sources.get_open_source_by_id(id)))
+            .collect();
+        root_ids.insert(0, source_id);
+
+        let mut diagnostics = LspDiagnostics::new();
+        for &root_id in &root_ids {
+            let (_, root_diagnostics) = self.compile_source(&world.with_main(root_id));
+            for (uri, file_diagnostics) in root_diagnostics {
+                // Roots sharing a file each report its errors, which are deduplicated when
+                // published
+                diagnostics.entry(uri).or_default().extend(file_diagnostics);
+            }
+        }
+        (diagnostics, root_ids.len())
+    }
+
+    /// Publishes the diagnostics unless a change was made since they started being computed. The
+    /// newer change will publish its own, so these are outdated.
+    async fn publish_diagnostics_if_latest(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        diagnostics: LspDiagnostics,
+        edit_generation: u64,
+    ) {
+        if self.get_edit_generation() == edit_generation {
+            self.update_all_diagnostics(world.get_workspace(), config, diagnostics)
+                .await;
+            self.update_resource_watcher(world.get_workspace()).await;
+        } else {
+            self.mark_diagnostics_outdated();
+        }
     }
 }
@@ -1,35 +1,519 @@
-use std::fs;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use std::{fs, io, process};
 
+use chrono::Local;
+use tokio::process::Command;
+use tokio::time::sleep;
 use tower_lsp::lsp_types::MessageType;
 use typst::doc::Document;
+use typst::geom::Color;
 
+use crate::config::{Config, ExportFormat, PdfStandard};
 use crate::workspace::source::Source;
 
+use super::export_directive::ExportDirective;
 use super::log::LogMessage;
+use super::progress::Progress;
 use super::TypstServer;
 
+/// Typst measures in points, of which there are 72 per inch
+pub const POINTS_PER_INCH: f32 = 72.0;
+
+/// The Typst version this is built with only writes plain PDFs, without the embedded color
+/// profile and metadata PDF/A requires
+const PDFA_UNSUPPORTED: &str =
+    "PDF/A export isn't supported by the Typst version this server is built with (0.2), so no PDF \
+     was written. Set `typst-lsp.pdfStandard` to \"1.7\" to export plain PDFs.";
+
+/// How many times moving an export into place is tried, in case the destination is locked
+const RENAME_ATTEMPTS: u32 = 5;
+
+/// Time to wait after the first failed attempt to move an export into place, which grows with
+/// each attempt
+const RENAME_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// Number of temporary files written so far, which tells apart those written at the same time to
+/// the same destination
+static TEMP_FILE_COUNT: AtomicU64 = AtomicU64::new(0);
+
+/// Values of the placeholders in the export directory and file name templates
+struct ExportPlaceholders {
+    /// Name of the source file, without its extension
+    name: String,
+    /// Directory of the source file
+    dir: String,
+    date: String,
+    time: String,
+    /// Title of the document, made safe to use in a file name
+    title: String,
+    /// Abbreviated hash of the checked out commit
+    git: String,
+}
+
+impl ExportPlaceholders {
+    fn fill(&self, template: &str) -> String {
+        template
+            .replace("{name}", &self.name)
+            .replace("{dir}", &self.dir)
+            .replace("{date}", &self.date)
+            .replace("{time}", &self.time)
+            .replace("{title}", &self.title)
+            .replace("{git}", &self.git)
+    }
+}
+
 impl TypstServer {
-    pub async fn export_pdf(&self, source: &Source, document: &Document) {
-        let buffer = typst::export::pdf(document);
-        let output_path = source.as_ref().path().with_extension("pdf");
-
-        let result = fs::write(&output_path, buffer);
-
-        match result {
-            Ok(_) => {
-                let message = LogMessage {
-                    message_type: MessageType::INFO,
-                    message: format!("File written to {}", output_path.to_string_lossy()),
-                };
-                self.log_to_client(message).await;
+    /// Exports the document in the format the source's export comment asks for, or as a PDF if
+    /// it doesn't, for exports which happen on their own rather than by command
+    pub async fn export_document(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        progress: &Progress,
+    ) {
+        let format = ExportDirective::from_source(source).format;
+        self.export_format(
+            config,
+            source,
+            document,
+            format.unwrap_or(ExportFormat::Pdf),
+            progress,
+        )
+        .await;
+    }
+
+    /// Exports the document in each of the configured formats, returning the paths of the files
+    /// written
+    pub async fn export_all_formats(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        progress: &Progress,
+    ) -> Vec<PathBuf> {
+        let mut written = Vec::new();
+        for &format in &config.export_formats {
+            written.extend(
+                self.export_format(config, source, document, format, progress)
+                    .await,
+            );
+        }
+        written
+    }
+
+    /// Exports the document in the format, returning the paths of the files written
+    pub async fn export_format(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        format: ExportFormat,
+        progress: &Progress,
+    ) -> Vec<PathBuf> {
+        match format {
+            ExportFormat::Pdf => self
+                .export_pdf(config, source, document, progress)
+                .await
+                .into_iter()
+                .collect(),
+            ExportFormat::Png => self.export_png(config, source, document, progress).await,
+        }
+    }
+
+    pub async fn export_pdf(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        progress: &Progress,
+    ) -> Option<PathBuf> {
+        self.write_pdf(config, source, document, "", progress).await
+    }
+
+    /// Exports the pages with the given 0-based indices to a PDF suffixed with the page numbers
+    pub async fn export_pdf_pages(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        indices: Range<usize>,
+        progress: &Progress,
+    ) {
+        let suffix = if indices.len() == 1 {
+            format!("-{}", indices.end)
+        } else {
+            format!("-{}-{}", indices.start + 1, indices.end)
+        };
+        let pages = Document {
+            pages: document.pages[indices].to_vec(),
+            ..document.clone()
+        };
+        self.write_pdf(config, source, &pages, &suffix, progress)
+            .await;
+    }
+
+    async fn write_pdf(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        suffix: &str,
+        progress: &Progress,
+    ) -> Option<PathBuf> {
+        let config = &ExportDirective::from_source(source).apply(config);
+        let output_path = match self
+            .get_export_path(config, source, document, suffix, "pdf")
+            .await
+        {
+            Ok(output_path) => output_path,
+            Err(error) => {
+                self.show_export_error(error).await;
+                return None;
             }
-            Err(e) => {
-                let message = LogMessage {
-                    message_type: MessageType::ERROR,
-                    message: e.to_string(),
-                };
-                self.log_to_client(message).await;
+        };
+        if let Err(error) = Self::check_pdf_standard(config.pdf_standard) {
+            self.show_export_error(error).await;
+            return None;
+        }
+        let _pending = self.pending_exports.start();
+
+        // Typst exports all pages at once, so there's no progress to report within the export
+        let message = format!("Exporting {} pages…", document.pages.len());
+        self.report_progress(progress, message, 50).await;
+
+        let start = Instant::now();
+        let buffer = Self::render_pdf(source, document);
+        self.log_timing(
+            config,
+            "export-pdf",
+            source.as_ref().path(),
+            start.elapsed(),
+        )
+        .await;
+        let result = Self::write_atomically(&output_path, &buffer).await;
+        let is_written = result.is_ok();
+        self.log_export_result(&output_path, result).await;
+        is_written.then_some(output_path)
+    }
+
+    /// Writes the document as a PDF in memory. Typst's exporter takes no options: it always embeds
+    /// subsets of the fonts with just the glyphs used, and compresses page contents and images,
+    /// like the CLI built with the same Typst version.
+    pub fn render_pdf(source: &Source, document: &Document) -> Vec<u8> {
+        let document = Self::with_fallback_title(source, document);
+        typst::export::pdf(&document)
+    }
+
+    /// Typst writes the title and authors set with `#set document(..)` into the PDF's metadata.
+    /// Documents without a title get the name of their file instead, so they can still be told
+    /// apart in reference managers.
+    fn with_fallback_title<'a>(source: &Source, document: &'a Document) -> Cow<'a, Document> {
+        if document.title.is_some() {
+            return Cow::Borrowed(document);
+        }
+        let Some(stem) = source.as_ref().path().file_stem() else {
+            return Cow::Borrowed(document);
+        };
+        Cow::Owned(Document {
+            title: Some(stem.to_string_lossy().as_ref().into()),
+            ..document.clone()
+        })
+    }
+
+    pub async fn export_png(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        progress: &Progress,
+    ) -> Vec<PathBuf> {
+        self.export_png_pages(config, source, document, 0..document.pages.len(), progress)
+            .await
+    }
+
+    /// Renders the pages with the given 0-based indices to PNGs at the configured resolution,
+    /// returning the paths of the files written. Pages are suffixed with `-{page}`, unless the
+    /// document only has one.
+    pub async fn export_png_pages(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        indices: Range<usize>,
+        progress: &Progress,
+    ) -> Vec<PathBuf> {
+        let config = &ExportDirective::from_source(source).apply(config);
+        let is_single_page = document.pages.len() == 1;
+        let page_count = indices.len();
+        let _pending = self.pending_exports.start();
+
+        let mut written = Vec::new();
+        for (exported, index) in indices.enumerate() {
+            let message = format!("Exporting page {}/{page_count}…", exported + 1);
+            let percentage = 50 + 50 * exported / page_count;
+            self.report_progress(progress, message, percentage as u32)
+                .await;
+
+            let suffix = if is_single_page {
+                String::new()
+            } else {
+                format!("-{}", index + 1)
+            };
+            let output_path = match self
+                .get_export_path(config, source, document, &suffix, "png")
+                .await
+            {
+                Ok(output_path) => output_path,
+                Err(error) => {
+                    self.show_export_error(error).await;
+                    return written;
+                }
+            };
+
+            let pixel_per_pt = config.png_dpi as f32 / POINTS_PER_INCH;
+            let start = Instant::now();
+            let pixmap = typst::export::render(&document.pages[index], pixel_per_pt, Color::WHITE);
+            let phase = format!("export-png-page-{}", index + 1);
+            self.log_timing(config, &phase, source.as_ref().path(), start.elapsed())
+                .await;
+            let result = match pixmap.encode_png() {
+                Ok(buffer) => Self::write_atomically(&output_path, &buffer).await,
+                Err(e) => Err(e.to_string()),
+            };
+            let is_written = result.is_ok();
+            self.log_export_result(&output_path, result).await;
+            if is_written {
+                written.push(output_path);
             }
+        }
+        written
+    }
+
+    /// Gets the path to export the source to from the configured directory and file name
+    /// templates, creating the directory if needed. Relative directories are resolved against the
+    /// workspace root.
+    async fn get_export_path(
+        &self,
+        config: &Config,
+        source: &Source,
+        document: &Document,
+        suffix: &str,
+        extension: &str,
+    ) -> Result<PathBuf, String> {
+        let path = source.as_ref().path();
+        let source_dir = path.parent().unwrap_or(Path::new(""));
+        let now = Local::now();
+        // Only ask Git when needed, since it runs a process
+        let uses_git = [&config.export_dir, &config.export_file_name]
+            .iter()
+            .any(|template| template.contains("{git}"));
+        let git = if uses_git {
+            Self::get_git_short_hash(source_dir)
+                .await
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+        let placeholders = ExportPlaceholders {
+            name: path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into(),
+            dir: source_dir.to_string_lossy().into(),
+            date: now.format("%Y-%m-%d").to_string(),
+            // Colons aren't allowed in file names on Windows
+            time: now.format("%H-%M-%S").to_string(),
+            title: document
+                .title
+                .as_ref()
+                .map(|title| Self::sanitize_file_name(title))
+                .unwrap_or_default(),
+            git,
+        };
+
+        let dir = PathBuf::from(placeholders.fill(&config.export_dir));
+        let dir = if dir.is_absolute() {
+            dir
+        } else {
+            let root = self
+                .get_const_config()
+                .root_paths
+                .first()
+                .map_or(source_dir, PathBuf::as_path);
+            root.join(dir)
+        };
+
+        fs::create_dir_all(&dir).map_err(|e| {
+            format!(
+                "Could not create export directory {}: {e}",
+                dir.to_string_lossy()
+            )
+        })?;
+
+        let file_name = format!(
+            "{}{suffix}.{extension}",
+            placeholders.fill(&config.export_file_name)
+        );
+        Ok(dir.join(file_name))
+    }
+
+    /// Gets the abbreviated hash of the commit checked out in the Git repository containing the
+    /// directory, or `None` if it isn't in one or Git isn't installed
+    async fn get_git_short_hash(dir: &Path) -> Option<String> {
+        let output = Command::new("git")
+            .args(["rev-parse", "--short", "HEAD"])
+            .current_dir(dir)
+            .output()
+            .await
+            .ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let hash = String::from_utf8(output.stdout).ok()?;
+        Some(hash.trim().to_owned())
+    }
+
+    /// Replaces the characters which aren't allowed in file names on some platforms, like path
+    /// separators, so that a document's title can be used in one. Names made only of dots, like
+    /// `..`, would name a directory instead, so their dots are replaced too.
+    fn sanitize_file_name(name: &str) -> String {
+        let name: String = name
+            .trim()
+            .chars()
+            .map(|c| match c {
+                '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+                c if c.is_control() => '_',
+                c => c,
+            })
+            .collect();
+        if !name.is_empty() && name.chars().all(|c| c == '.') {
+            return "_".repeat(name.len());
+        }
+        name
+    }
+
+    /// Writes the file next to the destination, then moves it into place, so that the
+    /// destination never holds a partially written file, even if writing is interrupted. On
+    /// Windows, programs like PDF viewers can lock the destination while reading it, so moving is
+    /// retried for a while before giving up and leaving the destination as it was.
+    async fn write_atomically(path: &Path, contents: &[u8]) -> Result<(), String> {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp_number = TEMP_FILE_COUNT.fetch_add(1, Ordering::Relaxed);
+        let temp_path =
+            path.with_file_name(format!(".{file_name}.{}.{temp_number}.tmp", process::id()));
+        fs::write(&temp_path, contents)
+            .map_err(|e| format!("Could not write {}: {e}", temp_path.to_string_lossy()))?;
+
+        let mut attempt = 1;
+        loop {
+            let error = match fs::rename(&temp_path, path) {
+                Ok(()) => return Ok(()),
+                Err(error) => error,
+            };
+            if attempt == RENAME_ATTEMPTS || !Self::may_be_locked(&error) {
+                let _ = fs::remove_file(&temp_path);
+                return Err(format!(
+                    "Could not replace {}, which may be open in another program: {error}",
+                    path.to_string_lossy()
+                ));
+            }
+            sleep(RENAME_RETRY_DELAY * attempt).await;
+            attempt += 1;
+        }
+    }
+
+    /// Whether the error may be caused by another program having the file open, which stops it
+    /// from being replaced on Windows until that program lets go of it
+    fn may_be_locked(error: &io::Error) -> bool {
+        // Elsewhere, a denied permission won't go away by waiting. `ERROR_SHARING_VIOLATION` and
+        // `ERROR_LOCK_VIOLATION` have no stable error kind.
+        cfg!(windows)
+            && (error.kind() == io::ErrorKind::PermissionDenied
+                || matches!(error.raw_os_error(), Some(32 | 33)))
+    }
+
+    async fn log_export_result<E: ToString>(&self, output_path: &Path, result: Result<(), E>) {
+        let message = match result {
+            Ok(_) => LogMessage {
+                message_type: MessageType::INFO,
+                message: format!("File written to {}", output_path.to_string_lossy()),
+            },
+            Err(e) => LogMessage {
+                message_type: MessageType::ERROR,
+                message: e.to_string(),
+            },
         };
+        self.log_to_client(message).await;
+    }
+
+    /// Fails if PDFs conforming to the standard can't be written, so that a plain PDF isn't passed
+    /// off as conforming
+    pub fn check_pdf_standard(standard: PdfStandard) -> Result<(), String> {
+        match standard {
+            PdfStandard::V1_7 => Ok(()),
+            PdfStandard::A2b => Err(PDFA_UNSUPPORTED.to_owned()),
+        }
+    }
+
+    async fn show_export_error(&self, error: String) {
+        self.client.show_message(MessageType::ERROR, error).await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn placeholders() -> ExportPlaceholders {
+        ExportPlaceholders {
+            name: "main".to_owned(),
+            dir: "/home/thesis".to_owned(),
+            date: "2023-04-01".to_owned(),
+            time: "12-30-00".to_owned(),
+            title: "My Thesis".to_owned(),
+            git: "1a2b3c4".to_owned(),
+        }
+    }
+
+    #[test]
+    fn placeholders_are_filled() {
+        let placeholders = placeholders();
+
+        assert_eq!(
+            placeholders.fill("{dir}/out/{date}"),
+            "/home/thesis/out/2023-04-01"
+        );
+        assert_eq!(
+            placeholders.fill("{name}-{title}-{git}-{time}"),
+            "main-My Thesis-1a2b3c4-12-30-00"
+        );
+        assert_eq!(placeholders.fill("{name}{name}"), "mainmain");
+        assert_eq!(placeholders.fill("{unknown}"), "{unknown}");
+    }
+
+    #[test]
+    fn title_is_sanitized() {
+        assert_eq!(TypstServer::sanitize_file_name(" Notes "), "Notes");
+        assert_eq!(TypstServer::sanitize_file_name("A/B: C?"), "A_B_ C_");
+        assert_eq!(TypstServer::sanitize_file_name("..\\secret"), ".._secret");
+        assert_eq!(TypstServer::sanitize_file_name("v1.2"), "v1.2");
+        assert_eq!(TypstServer::sanitize_file_name(""), "");
+    }
+
+    #[test]
+    fn dot_only_title_is_replaced() {
+        assert_eq!(TypstServer::sanitize_file_name("."), "_");
+        assert_eq!(TypstServer::sanitize_file_name(".."), "__");
+        assert_eq!(TypstServer::sanitize_file_name(" ... "), "___");
+
+        let mut placeholders = placeholders();
+        placeholders.title = TypstServer::sanitize_file_name("..");
+        assert_eq!(placeholders.fill("out/{title}"), "out/__");
     }
 }
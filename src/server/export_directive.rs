@@ -0,0 +1,69 @@
+use crate::config::{Config, ExportFormat};
+use crate::workspace::source::Source;
+
+/// Comment in the leading comments of a file which overrides the export settings for it, with
+/// comma-separated `key: value` pairs in parentheses after it, like
+/// `// typst-lsp: export(format: png, dpi: 300, dir: slides)`
+const EXPORT_COMMENT: &str = "// typst-lsp: export(";
+
+/// Export settings a file sets for itself, which take precedence over the config
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ExportDirective {
+    /// Format exports happening on their own, like on save, are written in. Export commands
+    /// always use their own format.
+    pub format: Option<ExportFormat>,
+    pub png_dpi: Option<u32>,
+    /// Template of the directory to export to, like `export_dir` in the config
+    pub dir: Option<String>,
+    /// Template of the exported file's name, like `export_file_name` in the config
+    pub name: Option<String>,
+}
+
+impl ExportDirective {
+    /// Reads the export comment among the comments at the top of the source. Unknown keys and
+    /// invalid values are ignored.
+    pub fn from_source(source: &Source) -> Self {
+        let mut directive = Self::default();
+        let leading_comments = source
+            .as_ref()
+            .text()
+            .lines()
+            .map(str::trim)
+            .take_while(|line| line.is_empty() || line.starts_with("//"));
+        for line in leading_comments {
+            let Some(arguments) = line
+                .strip_prefix(EXPORT_COMMENT)
+                .and_then(|rest| rest.rsplit_once(')'))
+                .map(|(arguments, _)| arguments)
+            else {
+                continue;
+            };
+            for (key, value) in arguments.split(',').filter_map(|pair| pair.split_once(':')) {
+                let value = value.trim().trim_matches('"');
+                match key.trim() {
+                    "format" => directive.format = ExportFormat::from_name(value),
+                    "dpi" => directive.png_dpi = value.parse().ok(),
+                    "dir" => directive.dir = Some(value.to_owned()),
+                    "name" => directive.name = Some(value.to_owned()),
+                    _ => {}
+                }
+            }
+        }
+        directive
+    }
+
+    /// Gets the config with the settings the directive overrides replaced
+    pub fn apply(&self, config: &Config) -> Config {
+        let mut config = config.clone();
+        if let Some(png_dpi) = self.png_dpi {
+            config.png_dpi = png_dpi;
+        }
+        if let Some(dir) = &self.dir {
+            config.export_dir = dir.clone();
+        }
+        if let Some(name) = &self.name {
+            config.export_file_name = name.clone();
+        }
+        config
+    }
+}
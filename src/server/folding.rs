@@ -0,0 +1,120 @@
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::{TypstOffset, TypstRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    pub fn get_folding_ranges(&self, source: &Source) -> Vec<FoldingRange> {
+        let root = LinkedNode::new(source.as_ref().root());
+
+        let mut folding_ranges = Vec::new();
+        let mut headings = Vec::new();
+        let mut comment_lines: Option<(usize, usize)> = None;
+
+        for node in self.get_descendants(&root) {
+            match node.kind() {
+                SyntaxKind::Heading => {
+                    if let Some(level) = self.get_heading_level(&node) {
+                        headings.push((level, node.offset()));
+                    }
+                }
+                SyntaxKind::CodeBlock
+                | SyntaxKind::ContentBlock
+                | SyntaxKind::Args
+                | SyntaxKind::Array
+                | SyntaxKind::Dict => {
+                    // Keep the closing delimiter visible when folded
+                    let (start_line, end_line) = self.get_lines(source, node.range());
+                    self.push_folding_range(
+                        &mut folding_ranges,
+                        start_line,
+                        end_line.saturating_sub(1),
+                        None,
+                    );
+                }
+                SyntaxKind::BlockComment => {
+                    let (start_line, end_line) = self.get_lines(source, node.range());
+                    let kind = Some(FoldingRangeKind::Comment);
+                    self.push_folding_range(&mut folding_ranges, start_line, end_line, kind);
+                }
+                SyntaxKind::LineComment => {
+                    // Consecutive line comments fold together
+                    let (line, _) = self.get_lines(source, node.range());
+                    comment_lines = match comment_lines {
+                        Some((start_line, end_line)) if end_line + 1 == line => {
+                            Some((start_line, line))
+                        }
+                        Some((start_line, end_line)) => {
+                            let kind = Some(FoldingRangeKind::Comment);
+                            self.push_folding_range(
+                                &mut folding_ranges,
+                                start_line,
+                                end_line,
+                                kind,
+                            );
+                            Some((line, line))
+                        }
+                        None => Some((line, line)),
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        if let Some((start_line, end_line)) = comment_lines {
+            let kind = Some(FoldingRangeKind::Comment);
+            self.push_folding_range(&mut folding_ranges, start_line, end_line, kind);
+        }
+
+        for (index, (level, start)) in headings.iter().enumerate() {
+            let end = headings[index + 1..]
+                .iter()
+                .find(|(next_level, _)| next_level <= level)
+                .map_or(source.as_ref().text().len(), |(_, next_start)| *next_start);
+            let (start_line, end_line) = self.get_section_lines(source, *start, end);
+            let kind = Some(FoldingRangeKind::Region);
+            self.push_folding_range(&mut folding_ranges, start_line, end_line, kind);
+        }
+
+        folding_ranges
+    }
+
+    fn get_lines(&self, source: &Source, typst_range: TypstRange) -> (usize, usize) {
+        let source = source.as_ref();
+        let start_line = source.byte_to_line(typst_range.start).unwrap_or_default();
+        let end_line = source.byte_to_line(typst_range.end).unwrap_or(start_line);
+        (start_line, end_line)
+    }
+
+    /// Gets the lines of a heading's section, leaving out blank lines before the next heading
+    fn get_section_lines(
+        &self,
+        source: &Source,
+        start: TypstOffset,
+        end: TypstOffset,
+    ) -> (usize, usize) {
+        let text = &source.as_ref().text()[start..end];
+        let trimmed_end = start + text.trim_end().len();
+        self.get_lines(source, start..trimmed_end)
+    }
+
+    fn push_folding_range(
+        &self,
+        folding_ranges: &mut Vec<FoldingRange>,
+        start_line: usize,
+        end_line: usize,
+        kind: Option<FoldingRangeKind>,
+    ) {
+        if end_line > start_line {
+            folding_ranges.push(FoldingRange {
+                start_line: start_line as u32,
+                end_line: end_line as u32,
+                kind,
+                ..Default::default()
+            });
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use tower_lsp::lsp_types::TextEdit;
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::config::FormatConfig;
+use crate::lsp_typst_boundary::{typst_to_lsp, LspRawRange, TypstOffset, TypstRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    /// Formats the whole source, returning an edit replacing it if anything changed
+    pub fn format_document(&self, source: &Source, format_config: &FormatConfig) -> Vec<TextEdit> {
+        let text = source.as_ref().text();
+        let mut formatted = self.format_lines(source, 0..text.len(), format_config);
+        Self::format_final_newlines(&mut formatted, format_config);
+
+        if formatted == text {
+            return Vec::new();
+        }
+
+        let encoding = self.get_const_config().position_encoding;
+        let start = typst_to_lsp::offset_to_position(0, encoding, source.as_ref());
+        let end = typst_to_lsp::offset_to_position(text.len(), encoding, source.as_ref());
+
+        vec![TextEdit {
+            range: LspRawRange::new(start, end),
+            new_text: formatted,
+        }]
+    }
+
+    /// Formats the lines touched by `typst_range`, expanding it to whole lines and to cover any raw
+    /// block, string or comment it cuts through, so that their content is never changed
+    pub fn format_range(
+        &self,
+        source: &Source,
+        typst_range: TypstRange,
+        format_config: &FormatConfig,
+    ) -> Vec<TextEdit> {
+        let root = LinkedNode::new(source.as_ref().root());
+        let verbatim_ranges = self.get_verbatim_ranges(&root);
+        let text = source.as_ref().text();
+
+        let mut start = typst_range.start.min(text.len());
+        let mut end = typst_range.end.clamp(start, text.len());
+        loop {
+            let line_start = text[..start].rfind('\n').map_or(0, |index| index + 1);
+            let line_end = text[end..]
+                .find('\n')
+                .map_or(text.len(), |index| end + index);
+            let expanded_start = verbatim_ranges
+                .iter()
+                .filter(|range| range.start < line_start && line_start < range.end)
+                .map(|range| range.start)
+                .min()
+                .unwrap_or(line_start);
+            let expanded_end = verbatim_ranges
+                .iter()
+                .filter(|range| range.start < line_end && line_end < range.end)
+                .map(|range| range.end)
+                .max()
+                .unwrap_or(line_end);
+
+            if (expanded_start, expanded_end) == (start, end) {
+                break;
+            }
+            start = expanded_start;
+            end = expanded_end;
+        }
+
+        let formatted = self.format_lines(source, start..end, format_config);
+        if formatted == text[start..end] {
+            return Vec::new();
+        }
+
+        let lsp_range = typst_to_lsp::range(
+            start..end,
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+        vec![TextEdit {
+            range: lsp_range.raw_range,
+            new_text: formatted,
+        }]
+    }
+
+    /// Formats the lines in the range, which must start at the beginning of a line and end at the
+    /// end of one.
+    ///
+    /// Only whitespace which can't affect the output is touched: lines starting in code are
+    /// reindented according to their nesting, and trailing whitespace is removed if configured.
+    /// Markup indentation is kept, since it matters for lists, and raw blocks and strings are kept
+    /// verbatim.
+    pub fn format_lines(
+        &self,
+        source: &Source,
+        typst_range: TypstRange,
+        format_config: &FormatConfig,
+    ) -> String {
+        let root = LinkedNode::new(source.as_ref().root());
+        let verbatim_ranges = self.get_verbatim_ranges(&root);
+        let is_verbatim = |offset: TypstOffset| {
+            verbatim_ranges
+                .iter()
+                .any(|range| range.start < offset && offset < range.end)
+        };
+
+        let text = &source.as_ref().text()[typst_range.clone()];
+        let mut formatted = String::with_capacity(text.len());
+        let mut line_start = typst_range.start;
+
+        for line in text.split_inclusive('\n') {
+            // Each line keeps its own line break, so CRLF line breaks stay as they are
+            let content_len = line.trim_end_matches(['\r', '\n']).len();
+            let (line, line_break) = line.split_at(content_len);
+            let line_end = line_start + line.len();
+
+            if is_verbatim(line_start) {
+                formatted.push_str(line);
+            } else {
+                let content = if is_verbatim(line_end) || !format_config.trim_trailing_whitespace {
+                    line.trim_start()
+                } else {
+                    line.trim()
+                };
+                let first = line_end - line.trim_start().len();
+                let indent = match self.get_code_indent_level(&root, first) {
+                    Some(level) if !content.is_empty() => Some(level),
+                    _ => None,
+                };
+
+                match indent {
+                    Some(level) => {
+                        formatted.push_str(&Self::indentation(level, format_config));
+                        formatted.push_str(content);
+                    }
+                    None => {
+                        let indentation = &line[..line.len() - line.trim_start().len()];
+                        if !content.is_empty() || !format_config.trim_trailing_whitespace {
+                            formatted.push_str(indentation);
+                        }
+                        formatted.push_str(content);
+                    }
+                }
+            }
+
+            formatted.push_str(line_break);
+            line_start = line_end + line_break.len();
+        }
+
+        formatted
+    }
+
+    /// Removes blank lines at the end of the text and makes sure it ends with a newline, as
+    /// configured. Empty text is left empty.
+    fn format_final_newlines(text: &mut String, format_config: &FormatConfig) {
+        if format_config.trim_final_newlines {
+            let content_len = text.trim_end_matches(['\n', '\r']).len();
+            let newline_len = text[content_len..].find('\n').map_or(0, |index| index + 1);
+            text.truncate(content_len + newline_len);
+        }
+        if format_config.insert_final_newline && !text.is_empty() && !text.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+
+    /// Gets the ranges of the nodes whose content must be kept as is
+    fn get_verbatim_ranges(&self, root: &LinkedNode) -> Vec<TypstRange> {
+        self.get_descendants(root)
+            .into_iter()
+            .filter(|node| {
+                matches!(
+                    node.kind(),
+                    SyntaxKind::Raw | SyntaxKind::Str | SyntaxKind::BlockComment
+                )
+            })
+            .map(|node| node.range())
+            .collect()
+    }
+
+    /// Gets the indentation level of a line whose first non-whitespace character is at `first`, or
+    /// `None` if the line starts in markup or math, where indentation shouldn't be changed
+    fn get_code_indent_level(&self, root: &LinkedNode, first: TypstOffset) -> Option<usize> {
+        let leaf = root.leaf_at(first + 1)?;
+        if leaf.offset() != first {
+            return None;
+        }
+
+        let is_delimited = |kind: SyntaxKind| {
+            matches!(
+                kind,
+                SyntaxKind::CodeBlock
+                    | SyntaxKind::ContentBlock
+                    | SyntaxKind::Args
+                    | SyntaxKind::Params
+                    | SyntaxKind::Array
+                    | SyntaxKind::Dict
+                    | SyntaxKind::Parenthesized
+            )
+        };
+
+        let mut level = 0;
+        let mut is_code = false;
+        let mut ancestor = leaf.parent();
+        while let Some(node) = ancestor {
+            if !is_code {
+                match node.kind() {
+                    SyntaxKind::Markup | SyntaxKind::Math | SyntaxKind::Equation => return None,
+                    SyntaxKind::Code => is_code = true,
+                    kind => is_code = is_delimited(kind),
+                }
+            }
+            if is_delimited(node.kind()) && node.offset() < first {
+                level += 1;
+            }
+            ancestor = node.parent();
+        }
+        if !is_code {
+            return None;
+        }
+
+        // Closing delimiters line up with the line that opened them
+        let is_closing = matches!(
+            leaf.kind(),
+            SyntaxKind::RightBrace | SyntaxKind::RightBracket | SyntaxKind::RightParen
+        );
+        if is_closing {
+            level = level.saturating_sub(1);
+        }
+
+        Some(level)
+    }
+
+    fn indentation(level: usize, format_config: &FormatConfig) -> String {
+        if format_config.use_tabs {
+            "\t".repeat(level)
+        } else {
+            " ".repeat(level * format_config.indent_width)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::lsp_types::FormattingOptions;
+    use tower_lsp::LspService;
+
+    use super::*;
+
+    fn format_config(options: FormattingOptions) -> FormatConfig {
+        FormatConfig::from_options(&FormattingOptions {
+            tab_size: 2,
+            insert_spaces: true,
+            ..options
+        })
+    }
+
+    fn format_final_newlines(text: &str, format_config: &FormatConfig) -> String {
+        let mut text = text.to_owned();
+        TypstServer::format_final_newlines(&mut text, format_config);
+        text
+    }
+
+    #[test]
+    fn final_newlines_kept_by_default() {
+        let format_config = format_config(FormattingOptions::default());
+        assert_eq!(format_final_newlines("a\n\n\n", &format_config), "a\n\n\n");
+        assert_eq!(format_final_newlines("a", &format_config), "a");
+    }
+
+    #[test]
+    fn final_newlines_trimmed() {
+        let format_config = format_config(FormattingOptions {
+            trim_final_newlines: Some(true),
+            ..Default::default()
+        });
+        assert_eq!(format_final_newlines("a\n\n\n", &format_config), "a\n");
+        assert_eq!(format_final_newlines("a\r\n\r\n", &format_config), "a\r\n");
+        assert_eq!(format_final_newlines("a", &format_config), "a");
+    }
+
+    #[test]
+    fn final_newline_inserted() {
+        let format_config = format_config(FormattingOptions {
+            insert_final_newline: Some(true),
+            trim_final_newlines: Some(true),
+            ..Default::default()
+        });
+        assert_eq!(format_final_newlines("a", &format_config), "a\n");
+        assert_eq!(format_final_newlines("a\n\n", &format_config), "a\n");
+        assert_eq!(format_final_newlines("", &format_config), "");
+    }
+
+    #[test]
+    fn trailing_whitespace_kept_when_not_trimmed() {
+        let mut source = Source::new_detached();
+        source.replace("Text  \n  \n#let x = 1 \n".to_owned());
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let text_len = source.as_ref().text().len();
+
+        let trimmed = service.inner().format_lines(
+            &source,
+            0..text_len,
+            &format_config(FormattingOptions::default()),
+        );
+        let kept = service.inner().format_lines(
+            &source,
+            0..text_len,
+            &format_config(FormattingOptions {
+                trim_trailing_whitespace: Some(false),
+                ..Default::default()
+            }),
+        );
+
+        assert_eq!(trimmed, "Text\n\n#let x = 1\n");
+        assert_eq!(kept, "Text  \n  \n#let x = 1 \n");
+    }
+
+    #[test]
+    fn crlf_line_breaks_kept() {
+        let mut source = Source::new_detached();
+        source.replace("Text  \r\n  \r\n#{\r\nlet x = 1 \r\n}\r\n".to_owned());
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let text_len = source.as_ref().text().len();
+
+        let formatted = service.inner().format_lines(
+            &source,
+            0..text_len,
+            &format_config(FormattingOptions::default()),
+        );
+
+        assert_eq!(formatted, "Text\r\n\r\n#{\r\n  let x = 1\r\n}\r\n");
+    }
+}
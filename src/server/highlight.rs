@@ -0,0 +1,54 @@
+use tower_lsp::lsp_types::{DocumentHighlight, DocumentHighlightKind};
+use typst::syntax::LinkedNode;
+
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    /// Gets the occurrences in the source of the binding or label at the position. Bindings and
+    /// label declarations are writes, and other uses are reads.
+    pub fn get_document_highlights(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<Vec<DocumentHighlight>> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        let leaf = self.get_leaf(source, typst_offset)?;
+
+        let source_id = source.as_ref().id();
+        let highlights = self
+            .find_references(world, source, &leaf, true)?
+            .into_iter()
+            .filter(|(reference_source, _)| reference_source.as_ref().id() == source_id)
+            .map(|(_, node)| self.document_highlight(source, &node))
+            .collect();
+
+        Some(highlights)
+    }
+
+    fn document_highlight(&self, source: &Source, node: &LinkedNode) -> DocumentHighlight {
+        let kind = if self.is_binding_site(node) || self.is_label_declaration(node) {
+            DocumentHighlightKind::WRITE
+        } else {
+            DocumentHighlightKind::READ
+        };
+        let lsp_range = typst_to_lsp::range(
+            node.range(),
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+
+        DocumentHighlight {
+            range: lsp_range.raw_range,
+            kind: Some(kind),
+        }
+    }
+}
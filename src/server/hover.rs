@@ -1,6 +1,11 @@
-use tower_lsp::lsp_types::Hover;
+use std::sync::Arc;
+
+use tower_lsp::lsp_types::{Hover, HoverContents, MarkedString, MarkupContent, MarkupKind};
+use typst::eval::{FuncInfo, Value};
+use typst::geom::Color;
 use typst::ide::tooltip;
-use typst::syntax::LinkedNode;
+use typst::syntax::{LinkedNode, SyntaxKind};
+use typst::World;
 
 use crate::lsp_typst_boundary::world::WorkspaceWorld;
 use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition};
@@ -8,6 +13,12 @@ use crate::workspace::source::Source;
 
 use super::TypstServer;
 
+/// Longest equation, in bytes, that is rendered in hovers, to keep hovering cheap
+const MAX_EQUATION_LEN: usize = 1000;
+
+/// Resolution of rendered equations, in pixels per point
+const EQUATION_PIXEL_PER_PT: f32 = 2.0;
+
 impl TypstServer {
     pub fn get_hover(
         &self,
@@ -21,19 +32,207 @@ impl TypstServer {
             source.as_ref(),
         );
 
-        let typst_tooltip = tooltip(world, &[], source.as_ref(), typst_offset)?;
-        let lsp_tooltip = typst_to_lsp::tooltip(&typst_tooltip);
+        let typst_tooltip = tooltip(world, &[], source.as_ref(), typst_offset);
+        let lsp_tooltip = typst_tooltip.as_ref().map(typst_to_lsp::tooltip);
 
         let typst_hovered_node = LinkedNode::new(source.as_ref().root()).leaf_at(typst_offset)?;
+
+        if let Some(equation) = Self::get_equation(&typst_hovered_node) {
+            if let Some(image) = self.render_equation(world, &equation) {
+                let mut contents = vec![MarkedString::String(image)];
+                if let Some(HoverContents::Scalar(tooltip)) = lsp_tooltip {
+                    contents.push(tooltip);
+                }
+                let lsp_equation_range = typst_to_lsp::range(
+                    equation.range(),
+                    source.as_ref(),
+                    self.get_const_config().position_encoding,
+                );
+
+                return Some(Hover {
+                    contents: HoverContents::Array(contents),
+                    range: Some(lsp_equation_range.raw_range),
+                });
+            }
+        }
+
         let lsp_hovered_range = typst_to_lsp::range(
             typst_hovered_node.range(),
             source.as_ref(),
             self.get_const_config().position_encoding,
         );
 
+        let auto_import_note = self
+            .get_auto_import(world, source, &typst_hovered_node)
+            .map(|spec| format!("*Auto-imported from `{spec}`*"));
+
+        if let Some(docs) = self.get_function_docs(world, source, &typst_hovered_node) {
+            let docs = match &auto_import_note {
+                Some(note) => format!("{note}\n\n{docs}"),
+                None => docs,
+            };
+            return Some(Hover {
+                contents: HoverContents::Markup(MarkupContent {
+                    kind: MarkupKind::Markdown,
+                    value: docs,
+                }),
+                range: Some(lsp_hovered_range.raw_range),
+            });
+        }
+
+        let contents = match (auto_import_note, lsp_tooltip) {
+            (Some(note), Some(HoverContents::Scalar(tooltip))) => {
+                HoverContents::Array(vec![MarkedString::String(note), tooltip])
+            }
+            (Some(note), None) => HoverContents::Scalar(MarkedString::String(note)),
+            (_, tooltip) => tooltip?,
+        };
+
         Some(Hover {
-            contents: lsp_tooltip,
+            contents,
             range: Some(lsp_hovered_range.raw_range),
         })
     }
+
+    /// Documents the function the identifier refers to. Functions defined in the workspace are
+    /// documented by their `let` signature and the comments right above it, and standard library
+    /// functions by their signature, parameters and documentation.
+    fn get_function_docs(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        ident: &LinkedNode,
+    ) -> Option<String> {
+        if !matches!(ident.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent) {
+            return None;
+        }
+
+        // Bindings in the workspace shadow the standard library
+        if let Some((_, definition)) = self.resolve_definition(world, source, ident) {
+            return self.user_function_docs(&definition);
+        }
+
+        let library = world.library();
+        let value = match ident.kind() {
+            SyntaxKind::MathIdent => library
+                .math
+                .scope()
+                .get(ident.text())
+                .or_else(|| library.global.scope().get(ident.text())),
+            _ => library.global.scope().get(ident.text()),
+        };
+        let Some(Value::Func(func)) = value else {
+            return None;
+        };
+        Some(self.library_function_docs(func.info()?))
+    }
+
+    fn library_function_docs(&self, info: &FuncInfo) -> String {
+        let (signature, _) = self.get_param_information(info);
+        let mut docs = format!("```typst\n{signature}\n```\n\n");
+
+        // The library's docs fence their examples as `example`, which clients don't highlight
+        docs.push_str(&info.docs.replace("```example", "```typst"));
+
+        if !info.params.is_empty() {
+            docs.push_str("\n\n**Parameters**\n");
+            for param in &info.params {
+                let summary = param.docs.lines().next().unwrap_or_default();
+                docs.push_str(&format!(
+                    "\n- `{}: {}` — {summary}",
+                    param.name,
+                    Self::format_cast_info(&param.cast)
+                ));
+            }
+        }
+
+        docs
+    }
+
+    fn user_function_docs(&self, definition: &LinkedNode) -> Option<String> {
+        let binding = self.get_function_binding(definition)?;
+        let closure = definition.parent()?;
+        let params = closure
+            .children()
+            .find(|child| child.kind() == SyntaxKind::Params)?;
+        let signature = format!(
+            "#let {}{}",
+            definition.text(),
+            params.get().clone().into_text()
+        );
+
+        let mut docs = format!("```typst\n{signature}\n```");
+        let comment = Self::get_preceding_comment(&binding);
+        if !comment.is_empty() {
+            docs.push_str("\n\n");
+            docs.push_str(&comment);
+        }
+        Some(docs)
+    }
+
+    /// Gets the text of the comments directly above the binding, without their delimiters. A blank
+    /// line between a comment and the binding detaches the comment from it.
+    fn get_preceding_comment(binding: &LinkedNode) -> String {
+        let Some(parent) = binding.parent() else {
+            return String::new();
+        };
+
+        let mut lines = Vec::new();
+        for sibling in parent.children().take(binding.index()).rev() {
+            match sibling.kind() {
+                // The `#` before a binding in markup
+                SyntaxKind::Hash => {}
+                SyntaxKind::Space if sibling.text().matches('\n').count() <= 1 => {}
+                SyntaxKind::LineComment => {
+                    let text = sibling.text();
+                    let text = text.trim_start_matches('/');
+                    lines.push(text.strip_prefix(' ').unwrap_or(text).to_owned());
+                }
+                SyntaxKind::BlockComment => {
+                    let text = sibling.text();
+                    let text = text.trim_start_matches("/*").trim_end_matches("*/");
+                    for line in text.lines().rev() {
+                        let line = line.trim().trim_start_matches('*').trim_start();
+                        lines.push(line.to_owned());
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        lines.reverse();
+        lines.join("\n").trim().to_owned()
+    }
+
+    fn get_equation<'a>(leaf: &LinkedNode<'a>) -> Option<LinkedNode<'a>> {
+        let mut ancestor = leaf.parent();
+        while let Some(node) = ancestor {
+            if node.kind() == SyntaxKind::Equation {
+                return Some(node.clone());
+            }
+            ancestor = node.parent();
+        }
+        None
+    }
+
+    /// Renders the equation on its own, returning it as a Markdown image
+    fn render_equation(&self, world: &WorkspaceWorld, equation: &LinkedNode) -> Option<String> {
+        let text = equation.get().clone().into_text();
+        if text.len() > MAX_EQUATION_LEN {
+            return None;
+        }
+
+        let fragment = format!("#set page(width: auto, height: auto, margin: 4pt)\n{text}");
+        let fragment_world =
+            WorkspaceWorld::new_detached(Arc::clone(world.get_workspace()), fragment, None);
+        let document = typst::compile(&fragment_world).ok()?;
+        let page = document.pages.first()?;
+
+        let pixmap = typst::export::render(page, EQUATION_PIXEL_PER_PT, Color::WHITE);
+        let png = pixmap.encode_png().ok()?;
+        Some(format!(
+            "![equation](data:image/png;base64,{})",
+            base64::encode(png)
+        ))
+    }
 }
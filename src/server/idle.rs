@@ -0,0 +1,79 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use tokio::time::sleep;
+use tower_lsp::lsp_types::MessageType;
+use tower_lsp::Client;
+
+use crate::config::Config;
+
+use super::typst_compiler::CompileWorker;
+
+/// Longest time between checks for idleness, so that changes to the timeout are picked up
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Tracks the time since the last edit, so that background work can pause while the user is away
+#[derive(Debug)]
+pub struct IdleDetector {
+    last_edit: Mutex<Instant>,
+    /// The configured `idle_timeout_secs`, kept here so the detector doesn't need to lock the
+    /// config
+    timeout_secs: AtomicU64,
+    is_idle: AtomicBool,
+}
+
+impl Default for IdleDetector {
+    fn default() -> Self {
+        Self {
+            last_edit: Mutex::new(Instant::now()),
+            timeout_secs: AtomicU64::new(Config::default().idle_timeout_secs),
+            is_idle: AtomicBool::new(false),
+        }
+    }
+}
+
+impl IdleDetector {
+    /// Records an edit, returning whether it ends a period of idleness
+    pub fn record_edit(&self) -> bool {
+        *self.last_edit.lock() = Instant::now();
+        self.is_idle.swap(false, Ordering::SeqCst)
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.is_idle.load(Ordering::SeqCst)
+    }
+
+    /// Sets how long after the last edit to go idle, or 0 to never go idle
+    pub fn set_timeout(&self, timeout_secs: u64) {
+        self.timeout_secs.store(timeout_secs, Ordering::Relaxed);
+    }
+
+    /// Waits for the timeout to pass without edits, then evicts Typst's incremental cache to free
+    /// memory, over and over. Runs until the server exits.
+    pub async fn run(&self, client: Client, compile_worker: &CompileWorker) {
+        loop {
+            let timeout_secs = self.timeout_secs.load(Ordering::Relaxed);
+            if timeout_secs == 0 || self.is_idle() {
+                sleep(IDLE_CHECK_INTERVAL).await;
+                continue;
+            }
+
+            let timeout = Duration::from_secs(timeout_secs);
+            let since_edit = self.last_edit.lock().elapsed();
+            if since_edit < timeout {
+                sleep((timeout - since_edit).min(IDLE_CHECK_INTERVAL)).await;
+                continue;
+            }
+
+            self.is_idle.store(true, Ordering::SeqCst);
+            compile_worker.evict(0);
+            client
+                .log_message(
+                    MessageType::INFO,
+                    format!("No edits for {timeout_secs}s, pausing background work"),
+                )
+                .await;
+        }
+    }
+}
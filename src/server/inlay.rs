@@ -0,0 +1,121 @@
+use serde_json::{json, Value as JsonValue};
+use tower_lsp::lsp_types::{InlayHint, InlayHintKind, InlayHintLabel, InlayHintTooltip, Url};
+use typst::eval::{Module, Value};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{typst_to_lsp, TypstRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// Longest value representation shown in a hint's tooltip
+const MAX_TOOLTIP_LEN: usize = 200;
+
+/// Data attached to a hint, so its tooltip can be computed when resolved
+#[derive(Debug)]
+pub struct InlayHintData {
+    pub uri: Url,
+    pub name: String,
+}
+
+impl InlayHintData {
+    fn to_json(&self) -> JsonValue {
+        json!({ "uri": self.uri, "name": self.name })
+    }
+
+    pub fn from_json(data: &JsonValue) -> Option<Self> {
+        let uri = data.get("uri")?.as_str()?;
+        let name = data.get("name")?.as_str()?;
+        Some(Self {
+            uri: Url::parse(uri).ok()?,
+            name: name.to_owned(),
+        })
+    }
+}
+
+impl TypstServer {
+    /// Gets hints with the types of the top-level `let` bindings in the range. Only top-level
+    /// bindings end up in the evaluated module, so those are the only ones with a known type.
+    pub fn get_inlay_hints(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        uri: &Url,
+        typst_range: TypstRange,
+    ) -> Vec<InlayHint> {
+        let (Some(module), _) = self.eval_source(world, source) else {
+            return Vec::new();
+        };
+
+        let root = LinkedNode::new(source.as_ref().root());
+        root.children()
+            .filter(|child| child.kind() == SyntaxKind::LetBinding)
+            // The name of a function binding already shows that it's a function
+            .filter(|binding| {
+                !binding
+                    .children()
+                    .any(|child| child.kind() == SyntaxKind::Closure)
+            })
+            .flat_map(|binding| self.get_bound_idents(&binding))
+            .filter(|ident| {
+                typst_range.start <= ident.offset() && ident.range().end <= typst_range.end
+            })
+            .filter_map(|ident| {
+                let value = Self::get_module_value(&module, ident.text())?;
+                let position = typst_to_lsp::offset_to_position(
+                    ident.range().end,
+                    self.get_const_config().position_encoding,
+                    source.as_ref(),
+                );
+                let data = InlayHintData {
+                    uri: uri.clone(),
+                    name: ident.text().to_string(),
+                };
+
+                Some(InlayHint {
+                    position,
+                    label: InlayHintLabel::String(format!(": {}", value.type_name())),
+                    kind: Some(InlayHintKind::TYPE),
+                    text_edits: None,
+                    tooltip: None,
+                    padding_left: None,
+                    padding_right: None,
+                    data: Some(data.to_json()),
+                })
+            })
+            .collect()
+    }
+
+    /// Adds the value of the binding as the hint's tooltip
+    pub fn resolve_inlay_hint(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        data: &InlayHintData,
+        mut hint: InlayHint,
+    ) -> InlayHint {
+        let (Some(module), _) = self.eval_source(world, source) else {
+            return hint;
+        };
+
+        if let Some(value) = Self::get_module_value(&module, &data.name) {
+            let mut repr = value.repr().to_string();
+            if repr.len() > MAX_TOOLTIP_LEN {
+                let end = (0..=MAX_TOOLTIP_LEN)
+                    .rev()
+                    .find(|&index| repr.is_char_boundary(index))
+                    .unwrap_or_default();
+                repr.truncate(end);
+                repr.push('…');
+            }
+            hint.tooltip = Some(InlayHintTooltip::String(repr));
+        }
+
+        hint
+    }
+
+    fn get_module_value<'a>(module: &'a Module, name: &str) -> Option<&'a Value> {
+        module.scope().get(name)
+    }
+}
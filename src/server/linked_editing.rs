@@ -0,0 +1,56 @@
+use tower_lsp::lsp_types::LinkedEditingRanges;
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, LspRawRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// Characters which label names can be made of, as a JavaScript regular expression
+const LABEL_NAME_PATTERN: &str = r"[\p{L}\p{N}_\-.:]+";
+
+impl TypstServer {
+    /// Gets the names of the label declarations and references in the source which share the
+    /// name at the position, so renaming one renames them all
+    pub fn get_linked_editing_ranges(
+        &self,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<LinkedEditingRanges> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        let node = self.get_label_node(self.get_leaf(source, typst_offset)?);
+        let name = self.get_label_name(&node)?;
+
+        let root = LinkedNode::new(source.as_ref().root());
+        let ranges: Vec<LspRawRange> = self
+            .get_descendants(&root)
+            .into_iter()
+            .filter(|node| matches!(node.kind(), SyntaxKind::Ref | SyntaxKind::Label))
+            .filter(|node| self.get_label_name(node).as_deref() == Some(name.as_str()))
+            .map(|node| {
+                // Skip the `<` or `@` before the name
+                let start = node.offset() + 1;
+                let lsp_range = typst_to_lsp::range(
+                    start..start + name.len(),
+                    source.as_ref(),
+                    self.get_const_config().position_encoding,
+                );
+                lsp_range.raw_range
+            })
+            .collect();
+
+        // A name used only once has nothing to edit along with it
+        if ranges.len() < 2 {
+            return None;
+        }
+
+        Some(LinkedEditingRanges {
+            ranges,
+            word_pattern: Some(LABEL_NAME_PATTERN.to_owned()),
+        })
+    }
+}
@@ -0,0 +1,269 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+use tower_lsp::lsp_types::{DocumentLink, Url};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::typst_to_lsp;
+use crate::lsp_typst_boundary::world::resolve_source_path;
+use crate::workspace::source::Source;
+use crate::workspace::source_manager::SourceId;
+use crate::workspace::Workspace;
+
+use super::TypstServer;
+
+/// Functions whose first argument is the path of a file they load
+const FILE_FUNCTIONS: [&str; 7] = [
+    "image",
+    "read",
+    "csv",
+    "json",
+    "yaml",
+    "xml",
+    "bibliography",
+];
+
+impl TypstServer {
+    /// Gets links for the paths in imports, includes and calls to functions loading files. Paths
+    /// to files which don't exist get a link without a target.
+    pub fn get_document_links(&self, workspace: &Workspace, source: &Source) -> Vec<DocumentLink> {
+        let root = LinkedNode::new(source.as_ref().root());
+        self.get_descendants(&root)
+            .iter()
+            .filter_map(Self::get_path_node)
+            .map(|path_node| self.document_link(workspace, source, &path_node))
+            .collect()
+    }
+
+    /// Gets the paths of the files the source imports, includes or loads
+    pub fn get_dependencies(&self, workspace: &Workspace, source: &Source) -> Vec<PathBuf> {
+        let root = LinkedNode::new(source.as_ref().root());
+        self.get_descendants(&root)
+            .iter()
+            .filter_map(Self::get_path_node)
+            .filter_map(|path_node| Self::resolve_path(workspace, source, &path_node))
+            .collect()
+    }
+
+    /// Whether an open source imports or includes the source, directly or through other files.
+    /// Compiling the open source reports the file's diagnostics, so they stay relevant. Only the
+    /// sources already known are looked at, without loading any.
+    pub fn is_dependency_of_open_source(&self, workspace: &Workspace, uri: &Url) -> bool {
+        let sources = &workspace.sources;
+        sources.get_id_by_uri(uri).map_or(false, |id| {
+            self.is_dependency_of(workspace, &sources.get_open_ids(), id)
+        })
+    }
+
+    /// Whether any of the roots imports or includes the source, directly or through other files
+    pub fn is_dependency_of(
+        &self,
+        workspace: &Workspace,
+        root_ids: &[SourceId],
+        target_id: SourceId,
+    ) -> bool {
+        let sources = &workspace.sources;
+        sources.get_uri_by_id(target_id).map_or(false, |target| {
+            self.get_all_dependencies(workspace, root_ids)
+                .contains(&target)
+        })
+    }
+
+    /// Gets the URIs of the files the roots import, include or load, directly or through other
+    /// files, normalized like the URIs sources are stored under. Only sources which are loaded are
+    /// followed, which those compiled since they were last evicted are.
+    pub fn get_all_dependencies(
+        &self,
+        workspace: &Workspace,
+        root_ids: &[SourceId],
+    ) -> HashSet<Url> {
+        let sources = &workspace.sources;
+        let mut dependencies = HashSet::new();
+        let mut pending = root_ids.to_vec();
+        let mut visited = HashSet::new();
+        while let Some(id) = pending.pop() {
+            if !visited.insert(id) {
+                continue;
+            }
+            let Some(source) = sources.get_source_by_id(id) else {
+                continue;
+            };
+            for path in self.get_dependencies(workspace, source) {
+                let Ok(uri) = Url::from_file_path(&path) else {
+                    continue;
+                };
+                let uri = sources.normalize_uri(&uri);
+                if let Some(dependency_id) = sources.get_id_by_uri(&uri) {
+                    pending.push(dependency_id);
+                }
+                dependencies.insert(uri);
+            }
+        }
+        dependencies
+    }
+
+    /// Gets the string literal holding the path used by the node, if any
+    pub fn get_path_node<'a>(node: &LinkedNode<'a>) -> Option<LinkedNode<'a>> {
+        match node.kind() {
+            SyntaxKind::ModuleImport | SyntaxKind::ModuleInclude => node
+                .children()
+                .find(|child| child.kind() == SyntaxKind::Str),
+            SyntaxKind::FuncCall => {
+                let callee = node.children().next()?;
+                if callee.kind() != SyntaxKind::Ident
+                    || !FILE_FUNCTIONS.contains(&callee.text().as_str())
+                {
+                    return None;
+                }
+                let args = node
+                    .children()
+                    .find(|child| child.kind() == SyntaxKind::Args)?;
+                args.children()
+                    .find(|arg| !arg.kind().is_trivia() && arg.kind() != SyntaxKind::LeftParen)
+                    .filter(|arg| arg.kind() == SyntaxKind::Str)
+            }
+            _ => None,
+        }
+    }
+
+    /// Resolves the path in the string literal like Typst does when compiling the source. Each `..`
+    /// removes the directory before it, so that files which no longer exist still get the path
+    /// they were known by.
+    fn resolve_path(
+        workspace: &Workspace,
+        source: &Source,
+        path_node: &LinkedNode,
+    ) -> Option<PathBuf> {
+        let path = path_node.text().trim_matches('"');
+        resolve_source_path(workspace, source.as_ref(), path).ok()
+    }
+
+    fn document_link(
+        &self,
+        workspace: &Workspace,
+        source: &Source,
+        path_node: &LinkedNode,
+    ) -> DocumentLink {
+        let target = Self::resolve_path(workspace, source, path_node)
+            .filter(|path| path.exists())
+            .and_then(|path| typst_to_lsp::path_to_uri(&path).ok());
+        let tooltip = target.is_none().then(|| "File not found".to_owned());
+
+        let lsp_range = typst_to_lsp::range(
+            path_node.range(),
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+
+        DocumentLink {
+            range: lsp_range.raw_range,
+            target,
+            tooltip,
+            data: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tower_lsp::LspService;
+
+    use crate::test_utils::TempDir;
+
+    use super::*;
+
+    /// Writes the files to a new directory and loads them, like compiling would, opens those
+    /// listed in `open`, then closes `closed` and checks whether it's still a dependency of an open
+    /// source
+    fn is_dependency_after_close(
+        test_name: &str,
+        files: &[(&str, &str)],
+        open: &[&str],
+        closed: &str,
+    ) -> bool {
+        let dir = TempDir::new(test_name);
+        for (name, text) in files {
+            dir.write(name, text);
+        }
+        let uri = |name: &str| typst_to_lsp::path_to_uri(&dir.path().join(name)).unwrap();
+
+        let mut workspace = Workspace::default();
+        for (name, _) in files {
+            workspace.sources.cache(uri(name)).unwrap();
+        }
+        for name in open {
+            let text = fs::read_to_string(dir.path().join(name)).unwrap();
+            workspace.sources.insert_open(&uri(name), text).unwrap();
+        }
+        workspace.sources.close(&uri(closed));
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        service
+            .inner()
+            .is_dependency_of_open_source(&workspace, &uri(closed))
+    }
+
+    #[test]
+    fn closed_standalone_file_is_not_dependency() {
+        let is_dependency = is_dependency_after_close(
+            "closed-standalone",
+            &[
+                ("main.typ", "#include \"chapter.typ\""),
+                ("chapter.typ", "= Chapter"),
+                ("notes.typ", "= Notes"),
+            ],
+            &["main.typ", "notes.typ"],
+            "notes.typ",
+        );
+        assert!(!is_dependency);
+    }
+
+    #[test]
+    fn closed_imported_file_is_dependency() {
+        let is_dependency = is_dependency_after_close(
+            "closed-imported",
+            &[
+                ("main.typ", "#import \"chapter.typ\": value\n#value"),
+                ("chapter.typ", "#let value = 1"),
+            ],
+            &["main.typ", "chapter.typ"],
+            "chapter.typ",
+        );
+        assert!(is_dependency);
+    }
+
+    #[test]
+    fn closed_indirectly_included_file_is_dependency() {
+        let is_dependency = is_dependency_after_close(
+            "closed-indirect",
+            &[
+                ("main.typ", "#include \"part.typ\""),
+                ("part.typ", "#include \"./chapter.typ\""),
+                ("chapter.typ", "= Chapter"),
+            ],
+            &["main.typ", "chapter.typ"],
+            "chapter.typ",
+        );
+        assert!(is_dependency);
+    }
+
+    #[test]
+    fn closed_file_imported_from_root_is_dependency() {
+        let is_dependency = is_dependency_after_close(
+            "closed-root-relative",
+            &[
+                ("typst.toml", ""),
+                (
+                    "chapters/main.typ",
+                    "#import \"/common.typ\": value\n#value",
+                ),
+                ("common.typ", "#let value = 1"),
+            ],
+            &["chapters/main.typ", "common.typ"],
+            "common.typ",
+        );
+        assert!(is_dependency);
+    }
+}
@@ -0,0 +1,75 @@
+use std::collections::HashSet;
+
+use tower_lsp::lsp_types::{DiagnosticSeverity, DiagnosticTag};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::{typst_to_lsp, LspDiagnostic};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    /// Finds `let` bindings and imported items which are never used in the source. Bindings at the
+    /// top level of files which aren't document roots are skipped, since other files may import
+    /// them, as are names starting with `_`, which are meant to be unused.
+    pub fn lint_unused(&self, source: &Source) -> Vec<LspDiagnostic> {
+        let root = LinkedNode::new(source.as_ref().root());
+        let descendants = self.get_descendants(&root);
+
+        let used_offsets: HashSet<usize> = descendants
+            .iter()
+            .filter(|node| matches!(node.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent))
+            .filter(|ident| !self.is_binding_site(ident))
+            .filter_map(|ident| self.find_binding(ident))
+            .map(|binding| binding.offset())
+            .collect();
+
+        let include_top_level = self.is_document_root(source);
+        descendants
+            .iter()
+            .filter(|node| {
+                matches!(
+                    node.kind(),
+                    SyntaxKind::LetBinding | SyntaxKind::ModuleImport
+                )
+            })
+            // Bindings at the top level are children of the root
+            .filter(|node| {
+                include_top_level
+                    || node
+                        .parent()
+                        .map_or(false, |parent| parent.parent().is_some())
+            })
+            .flat_map(|node| {
+                let kind = match node.kind() {
+                    SyntaxKind::ModuleImport => "import",
+                    _ => "binding",
+                };
+                self.get_bound_idents(node)
+                    .into_iter()
+                    .map(move |ident| (kind, ident))
+            })
+            .filter(|(_, ident)| {
+                !ident.text().starts_with('_') && !used_offsets.contains(&ident.offset())
+            })
+            .map(|(kind, ident)| self.unused_diagnostic(source, kind, &ident))
+            .collect()
+    }
+
+    fn unused_diagnostic(&self, source: &Source, kind: &str, ident: &LinkedNode) -> LspDiagnostic {
+        let lsp_range = typst_to_lsp::range(
+            ident.range(),
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+
+        LspDiagnostic {
+            range: lsp_range.raw_range,
+            severity: Some(DiagnosticSeverity::HINT),
+            message: format!("unused {kind} `{}`", ident.text()),
+            // Lets clients fade the name out
+            tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+            ..Default::default()
+        }
+    }
+}
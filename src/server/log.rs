@@ -1,6 +1,10 @@
 use std::fmt::Display;
+use std::path::Path;
+use std::time::Duration;
 
-use tower_lsp::lsp_types::MessageType;
+use tower_lsp::lsp_types::{MessageType, Url};
+
+use crate::config::Config;
 
 use super::TypstServer;
 
@@ -17,4 +21,23 @@ impl TypstServer {
             .log_message(message.message_type, message.message)
             .await;
     }
+
+    /// Logs how long a phase of handling the file took, if enabled in the config, to help find
+    /// out what slows the server down
+    pub async fn log_timing(&self, config: &Config, phase: &str, path: &Path, duration: Duration) {
+        let Some(message_type) = config.log_timings else {
+            return;
+        };
+        let uri = Url::from_file_path(path)
+            .map_or_else(|_| path.to_string_lossy().into_owned(), String::from);
+
+        self.log_to_client(LogMessage {
+            message_type,
+            message: format!(
+                "[timing] phase={phase} uri={uri} elapsed_ms={}",
+                duration.as_millis()
+            ),
+        })
+        .await;
+    }
 }
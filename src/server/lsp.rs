@@ -1,15 +1,24 @@
-use serde_json::Value as JsonValue;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value as JsonValue};
+use tower_lsp::lsp_types::request::{GotoTypeDefinitionParams, GotoTypeDefinitionResponse};
 use tower_lsp::lsp_types::*;
 use tower_lsp::{jsonrpc, LanguageServer};
-use typst::ide::autocomplete;
 
-use crate::config::{ConstConfig, ExportPdfMode, PositionEncoding};
+use crate::config::{ConstConfig, ExportPdfMode, FormatConfig, PositionEncoding};
 use crate::ext::InitializeParamsExt;
-use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp};
+use crate::lsp_typst_boundary::{lsp_to_typst, LspRange};
 
 use super::command::LspCommand;
+use super::inlay::InlayHintData;
+use super::semantic_tokens::get_semantic_tokens_legend;
 use super::TypstServer;
 
+/// Longest time shutting down waits for pending exports to finish
+const SHUTDOWN_EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
 #[tower_lsp::async_trait]
 impl LanguageServer for TypstServer {
     async fn initialize(&self, params: InitializeParams) -> jsonrpc::Result<InitializeResult> {
@@ -22,31 +31,111 @@ impl LanguageServer for TypstServer {
             PositionEncoding::Utf16
         };
 
+        let root_paths = params.root_paths();
+        // Imports in documents which aren't files resolve relative to the first workspace folder
+        self.workspace
+            .write()
+            .await
+            .sources
+            .set_untitled_dir(root_paths.first().cloned());
+
         self.const_config
-            .set(ConstConfig { position_encoding })
+            .set(ConstConfig {
+                position_encoding,
+                root_paths,
+                supports_work_done_progress: params.supports_work_done_progress(),
+                supports_pull_diagnostics: params.supports_pull_diagnostics(),
+                supports_diagnostic_refresh: params.supports_diagnostic_refresh(),
+            })
             .expect("const config should not yet be initialized");
 
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 signature_help_provider: Some(SignatureHelpOptions {
                     trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
-                    retrigger_characters: None,
+                    retrigger_characters: Some(vec![",".to_string(), ":".to_string()]),
                     work_done_progress_options: WorkDoneProgressOptions {
                         work_done_progress: None,
                     },
                 }),
                 hover_provider: Some(HoverProviderCapability::Simple(true)),
+                document_symbol_provider: Some(OneOf::Left(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                document_range_formatting_provider: Some(OneOf::Left(true)),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(false),
+                }),
+                call_hierarchy_provider: Some(CallHierarchyServerCapability::Simple(true)),
+                color_provider: Some(ColorProviderCapability::Simple(true)),
+                document_link_provider: Some(DocumentLinkOptions {
+                    resolve_provider: Some(false),
+                    work_done_progress_options: Default::default(),
+                }),
+                inlay_hint_provider: Some(OneOf::Right(InlayHintServerCapabilities::Options(
+                    InlayHintOptions {
+                        resolve_provider: Some(true),
+                        work_done_progress_options: Default::default(),
+                    },
+                ))),
+                semantic_tokens_provider: Some(
+                    SemanticTokensServerCapabilities::SemanticTokensOptions(
+                        SemanticTokensOptions {
+                            work_done_progress_options: WorkDoneProgressOptions {
+                                work_done_progress: None,
+                            },
+                            legend: get_semantic_tokens_legend(),
+                            range: Some(true),
+                            full: Some(SemanticTokensFullOptions::Bool(true)),
+                        },
+                    ),
+                ),
+                definition_provider: Some(OneOf::Left(true)),
+                type_definition_provider: Some(TypeDefinitionProviderCapability::Simple(true)),
+                references_provider: Some(OneOf::Left(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
+                linked_editing_range_provider: Some(LinkedEditingRangeServerCapabilities::Simple(
+                    true,
+                )),
+                rename_provider: Some(OneOf::Right(RenameOptions {
+                    prepare_provider: Some(true),
+                    work_done_progress_options: WorkDoneProgressOptions {
+                        work_done_progress: None,
+                    },
+                })),
                 completion_provider: Some(CompletionOptions {
                     trigger_characters: Some(vec![
                         String::from("#"),
                         String::from("."),
                         String::from("@"),
+                        String::from("("),
+                        String::from("\""),
+                        String::from("/"),
                     ]),
                     ..Default::default()
                 }),
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::INCREMENTAL,
+                text_document_sync: Some(TextDocumentSyncCapability::Options(
+                    TextDocumentSyncOptions {
+                        open_close: Some(true),
+                        change: Some(TextDocumentSyncKind::INCREMENTAL),
+                        // Some clients only send `didSave` when asked to, which `OnSave` export
+                        // relies on
+                        save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                        ..Default::default()
+                    },
                 )),
+                // Clients which don't pull diagnostics have them published instead
+                diagnostic_provider: params.supports_pull_diagnostics().then(|| {
+                    DiagnosticServerCapabilities::Options(DiagnosticOptions {
+                        identifier: Some("typst-lsp".to_owned()),
+                        // Errors in a file can come from the files it imports
+                        inter_file_dependencies: true,
+                        workspace_diagnostics: true,
+                        work_done_progress_options: Default::default(),
+                    })
+                }),
                 execute_command_provider: Some(ExecuteCommandOptions {
                     commands: LspCommand::all_as_string(),
                     work_done_progress_options: WorkDoneProgressOptions {
@@ -75,12 +164,34 @@ impl LanguageServer for TypstServer {
                 .await;
         }
 
+        let idle = Arc::clone(&self.idle);
+        let compile_worker = Arc::clone(&self.compile_worker);
+        let client = self.client.clone();
+        tokio::spawn(async move { idle.run(client, &compile_worker).await });
+
+        // The project's settings apply before the client sends its own, if it does at all
+        if self
+            .get_project_manifest_path()
+            .map_or(false, |manifest_path| manifest_path.is_file())
+        {
+            self.reapply_settings().await;
+        }
+
         self.client
             .log_message(MessageType::INFO, "server initialized!")
             .await;
     }
 
     async fn shutdown(&self) -> jsonrpc::Result<()> {
+        // The process exits soon after, which would cut off exports being written
+        if !self.pending_exports.wait(SHUTDOWN_EXPORT_TIMEOUT).await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    "Shutting down before pending exports finished",
+                )
+                .await;
+        }
         Ok(())
     }
 
@@ -89,10 +200,16 @@ impl LanguageServer for TypstServer {
         let text = params.text_document.text;
 
         let mut workspace = self.workspace.write().await;
-        workspace.sources.insert_open(&uri, text);
-
-        let workspace = workspace.downgrade();
-        let config = self.config.read().await;
+        if workspace.sources.insert_open(&uri, text).is_err() {
+            drop(workspace);
+            self.client
+                .show_message(
+                    MessageType::ERROR,
+                    format!("Could not open {uri}: too many files are loaded in the workspace"),
+                )
+                .await;
+            return;
+        }
 
         let source_id = workspace
             .sources
@@ -101,12 +218,19 @@ impl LanguageServer for TypstServer {
 
         drop(workspace);
 
+        // An edit made right after opening the file checks it instead
+        if !self.debounce_edit(source_id).await {
+            return;
+        }
+
         let world = self.get_world_with_main(source_id).await;
+        let config = self.config.read().await;
         let source = world
             .get_workspace()
             .sources
             .get_open_source_by_id(source_id);
         self.on_source_changed(&world, &config, source).await;
+        self.warm_compile(&world, &config, source_id);
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
@@ -115,51 +239,99 @@ impl LanguageServer for TypstServer {
         let mut workspace = self.workspace.write().await;
         workspace.sources.close(&uri);
 
-        self.client.publish_diagnostics(uri, Vec::new(), None).await;
+        let config = self.config.read().await;
+        if config.closed_source_timeout_secs > 0 {
+            let timeout = Duration::from_secs(config.closed_source_timeout_secs);
+            workspace.sources.evict_idle(timeout);
+        }
+
+        // Diagnostics of files imported by open files stay, since they're still compiled. Clients
+        // pulling diagnostics drop those of closed files themselves, unless they're published to
+        // them too.
+        let const_config = self.get_const_config();
+        let is_pushed =
+            !const_config.supports_pull_diagnostics || !const_config.supports_diagnostic_refresh;
+        if is_pushed && !self.is_dependency_of_open_source(&workspace, &uri) {
+            self.client.publish_diagnostics(uri, Vec::new(), None).await;
+        }
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri;
         let changes = params.content_changes;
 
+        // Counted before waiting for the workspace, which is locked while compiling
+        self.start_edit();
+        let resumed = self.idle.record_edit();
+        if resumed {
+            // Changes to other files may have been skipped while idle. They're caught up on by
+            // whichever edit survives the debounce, which may not be this one.
+            self.mark_diagnostics_outdated();
+            self.pending_catch_up.store(true, Ordering::SeqCst);
+            self.client
+                .log_message(MessageType::INFO, "Resuming background work")
+                .await;
+        }
+
         let mut workspace = self.workspace.write().await;
-        let source_id = workspace
-            .sources
-            .get_id_by_uri(&uri)
-            .expect("source should exist after being changed");
+        // The source may not have been added if opening it failed
+        let Some(source_id) = workspace.sources.get_id_by_uri(&uri) else {
+            return;
+        };
 
         let source = workspace.sources.get_mut_open_source_by_id(source_id);
+        let start = Instant::now();
+        let mut affects_diagnostics = false;
         for change in changes {
-            self.apply_single_document_change(source, change);
+            affects_diagnostics |= self.apply_single_document_change(source, change);
+        }
+        let parse_duration = start.elapsed();
+        if affects_diagnostics {
+            self.mark_diagnostics_outdated();
         }
 
         drop(workspace);
 
+        let config = self.config.read().await;
+        let path = lsp_to_typst::uri_to_path(&uri);
+        self.log_timing(&config, "parse", &path, parse_duration)
+            .await;
+        drop(config);
+
+        if !self.debounce_edit(source_id).await {
+            return;
+        }
+
         let world = self.get_world_with_main(source_id).await;
         let config = self.config.read().await;
 
+        // Exports on type still need to pick up changes to raw blocks
+        if !self.are_diagnostics_outdated() && config.export_pdf != ExportPdfMode::OnType {
+            return;
+        }
+
         let source = world
             .get_workspace()
             .sources
             .get_open_source_by_id(source_id);
 
         self.on_source_changed(&world, &config, source).await;
+        if self.pending_catch_up.swap(false, Ordering::SeqCst) {
+            self.run_open_roots_diagnostics(&world, &config, source_id)
+                .await;
+        }
     }
 
     async fn did_save(&self, params: DidSaveTextDocumentParams) {
         let uri = params.text_document.uri;
 
-        let (world, source_id) = self.get_world_with_main_uri(&uri).await;
+        // Files the client didn't manage to open have nothing to save
+        let Ok((world, source_id)) = self.get_world_with_main_uri(&uri).await else {
+            return;
+        };
         let config = self.config.read().await;
 
-        let source = world
-            .get_workspace()
-            .sources
-            .get_open_source_by_id(source_id);
-
-        if config.export_pdf == ExportPdfMode::OnSave {
-            self.run_diagnostics_and_export(&world, source).await;
-        }
+        self.on_source_saved(&world, &config, source_id).await;
     }
 
     async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
@@ -167,9 +339,27 @@ impl LanguageServer for TypstServer {
 
         let mut workspace = self.workspace.write().await;
 
+        let mut fonts_changed = false;
+        let mut project_changed = false;
+        let mut changed_uris = Vec::new();
         for change in changes {
+            fonts_changed |= Self::is_font_file(&change.uri);
+            project_changed |= self.is_project_manifest(&change.uri);
+            changed_uris.push(change.uri.clone());
             self.handle_file_change_event(&mut workspace, change);
         }
+        drop(workspace);
+
+        if fonts_changed {
+            let config = self.config.read().await;
+            let (font_paths, font_config) = (config.font_paths.clone(), config.fonts.clone());
+            drop(config);
+            self.reload_fonts(&font_paths, &font_config).await;
+        }
+        if project_changed {
+            self.reapply_settings().await;
+        }
+        self.update_dependents(&changed_uris).await;
     }
 
     async fn execute_command(
@@ -186,6 +376,54 @@ impl LanguageServer for TypstServer {
             Some(LspCommand::ExportPdf) => {
                 self.command_export_pdf(arguments).await?;
             }
+            Some(LspCommand::ExportPdfBytes) => {
+                return self.command_export_pdf_bytes(arguments).await.map(Some);
+            }
+            Some(LspCommand::ExportPdfa) => {
+                self.command_export_pdfa(arguments).await?;
+            }
+            Some(LspCommand::ExportPng) => {
+                self.command_export_png(arguments).await?;
+            }
+            Some(LspCommand::ExportPages) => {
+                self.command_export_pages(arguments).await?;
+            }
+            Some(LspCommand::ExportAll) => {
+                return self.command_export_all(arguments).await.map(Some);
+            }
+            Some(LspCommand::ListFonts) => {
+                return self.command_list_fonts().await.map(Some);
+            }
+            Some(LspCommand::Check) => {
+                return self.command_check(arguments).await.map(Some);
+            }
+            Some(LspCommand::DebugWorld) => {
+                return self.command_debug_world(arguments).await.map(Some);
+            }
+            Some(LspCommand::DebugAst) => {
+                return self.command_debug_ast(arguments).await.map(Some);
+            }
+            Some(LspCommand::Version) => {
+                return self.command_version().map(Some);
+            }
+            Some(LspCommand::CompileSelection) => {
+                return self.command_compile_selection(arguments).await.map(Some);
+            }
+            Some(LspCommand::CompileString) => {
+                return self.command_compile_string(arguments).await.map(Some);
+            }
+            Some(LspCommand::ReloadConfig) => {
+                self.command_reload_config().await?;
+            }
+            Some(LspCommand::SetMainFile) => {
+                self.command_set_main_file(arguments).await?;
+            }
+            Some(LspCommand::ForgetSource) => {
+                self.command_forget_source(arguments).await?;
+            }
+            Some(LspCommand::IndexWorkspace) => {
+                return self.command_index_workspace().await.map(Some);
+            }
             None => {
                 return Err(jsonrpc::Error::method_not_found());
             }
@@ -193,11 +431,27 @@ impl LanguageServer for TypstServer {
         Ok(None)
     }
 
+    async fn diagnostic(
+        &self,
+        params: DocumentDiagnosticParams,
+    ) -> jsonrpc::Result<DocumentDiagnosticReportResult> {
+        Ok(self
+            .get_document_diagnostics(&params.text_document.uri)
+            .await)
+    }
+
+    async fn workspace_diagnostic(
+        &self,
+        _: WorkspaceDiagnosticParams,
+    ) -> jsonrpc::Result<WorkspaceDiagnosticReportResult> {
+        Ok(self.get_workspace_diagnostics().await)
+    }
+
     async fn hover(&self, params: HoverParams) -> jsonrpc::Result<Option<Hover>> {
         let uri = &params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
 
-        let (world, source_id) = self.get_world_with_main_uri(uri).await;
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
         let source = world
             .get_workspace()
             .sources
@@ -206,6 +460,406 @@ impl LanguageServer for TypstServer {
         Ok(self.get_hover(&world, source, position))
     }
 
+    async fn goto_definition(
+        &self,
+        params: GotoDefinitionParams,
+    ) -> jsonrpc::Result<Option<GotoDefinitionResponse>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.get_definition(&world, source, position))
+    }
+
+    async fn goto_type_definition(
+        &self,
+        params: GotoTypeDefinitionParams,
+    ) -> jsonrpc::Result<Option<GotoTypeDefinitionResponse>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.get_type_definition(&world, source, position))
+    }
+
+    async fn references(&self, params: ReferenceParams) -> jsonrpc::Result<Option<Vec<Location>>> {
+        let uri = &params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+        let include_declaration = params.context.include_declaration;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        self.cache_workspace_sources(world.get_workspace());
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        match &params.partial_result_params.partial_result_token {
+            Some(token) => Ok(self
+                .stream_references(&world, source, position, include_declaration, token)
+                .await),
+            None => Ok(self.get_references(&world, source, position, include_declaration)),
+        }
+    }
+
+    async fn document_highlight(
+        &self,
+        params: DocumentHighlightParams,
+    ) -> jsonrpc::Result<Option<Vec<DocumentHighlight>>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.get_document_highlights(&world, source, position))
+    }
+
+    async fn linked_editing_range(
+        &self,
+        params: LinkedEditingRangeParams,
+    ) -> jsonrpc::Result<Option<LinkedEditingRanges>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.get_linked_editing_ranges(source, position))
+    }
+
+    async fn prepare_rename(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> jsonrpc::Result<Option<PrepareRenameResponse>> {
+        let uri = &params.text_document.uri;
+        let position = params.position;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.prepare_rename_at_position(&world, source, position))
+    }
+
+    async fn rename(&self, params: RenameParams) -> jsonrpc::Result<Option<WorkspaceEdit>> {
+        let uri = &params.text_document_position.text_document.uri;
+        let position = params.text_document_position.position;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        self.cache_workspace_sources(world.get_workspace());
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        self.rename_at_position(&world, source, position, &params.new_name)
+    }
+
+    async fn document_symbol(
+        &self,
+        params: DocumentSymbolParams,
+    ) -> jsonrpc::Result<Option<DocumentSymbolResponse>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let symbols = self.get_document_symbols(source);
+        Ok(Some(DocumentSymbolResponse::Nested(symbols)))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> jsonrpc::Result<Option<Vec<SymbolInformation>>> {
+        let workspace = self.workspace.read().await;
+        self.cache_workspace_sources(&workspace);
+
+        let token = &params.partial_result_params.partial_result_token;
+        let mut symbols = Vec::new();
+        for source in workspace.sources.get_sources() {
+            let source_symbols = self.get_workspace_symbols(source, &params.query);
+            match token {
+                // Send each file's symbols as soon as they're found, so that the first show up
+                // before the whole workspace was searched
+                Some(token) if !source_symbols.is_empty() => {
+                    self.send_partial_result(token, json!(source_symbols)).await;
+                }
+                Some(_) => {}
+                None => symbols.extend(source_symbols),
+            }
+        }
+
+        Ok(Some(symbols))
+    }
+
+    async fn semantic_tokens_full(
+        &self,
+        params: SemanticTokensParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensResult>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let tokens = self.get_semantic_tokens(source, None);
+        Ok(Some(SemanticTokensResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: tokens,
+        })))
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> jsonrpc::Result<Option<SemanticTokensRangeResult>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let range = LspRange::new(params.range, self.get_const_config().position_encoding);
+        let typst_range = lsp_to_typst::range(&range, source.as_ref());
+
+        let tokens = self.get_semantic_tokens(source, Some(typst_range));
+        Ok(Some(SemanticTokensRangeResult::Tokens(SemanticTokens {
+            result_id: None,
+            data: tokens,
+        })))
+    }
+
+    async fn folding_range(
+        &self,
+        params: FoldingRangeParams,
+    ) -> jsonrpc::Result<Option<Vec<FoldingRange>>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(Some(self.get_folding_ranges(source)))
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> jsonrpc::Result<Option<Vec<SelectionRange>>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(Some(self.get_selection_ranges(source, &params.positions)))
+    }
+
+    async fn formatting(
+        &self,
+        params: DocumentFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let format_config = FormatConfig::from_options(&params.options);
+        Ok(Some(self.format_document(source, &format_config)))
+    }
+
+    async fn range_formatting(
+        &self,
+        params: DocumentRangeFormattingParams,
+    ) -> jsonrpc::Result<Option<Vec<TextEdit>>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let range = LspRange::new(params.range, self.get_const_config().position_encoding);
+        let typst_range = lsp_to_typst::range(&range, source.as_ref());
+
+        let format_config = FormatConfig::from_options(&params.options);
+        Ok(Some(self.format_range(source, typst_range, &format_config)))
+    }
+
+    async fn prepare_call_hierarchy(
+        &self,
+        params: CallHierarchyPrepareParams,
+    ) -> jsonrpc::Result<Option<Vec<CallHierarchyItem>>> {
+        let uri = &params.text_document_position_params.text_document.uri;
+        let position = params.text_document_position_params.position;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.prepare_call_hierarchy(&world, source, position))
+    }
+
+    async fn incoming_calls(
+        &self,
+        params: CallHierarchyIncomingCallsParams,
+    ) -> jsonrpc::Result<Option<Vec<CallHierarchyIncomingCall>>> {
+        let uri = &params.item.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        self.cache_workspace_sources(world.get_workspace());
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.get_incoming_calls(&world, source, &params.item))
+    }
+
+    async fn outgoing_calls(
+        &self,
+        params: CallHierarchyOutgoingCallsParams,
+    ) -> jsonrpc::Result<Option<Vec<CallHierarchyOutgoingCall>>> {
+        let uri = &params.item.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.get_outgoing_calls(&world, source, &params.item))
+    }
+
+    async fn code_lens(&self, params: CodeLensParams) -> jsonrpc::Result<Option<Vec<CodeLens>>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(Some(self.get_code_lenses(source, uri)))
+    }
+
+    async fn document_link(
+        &self,
+        params: DocumentLinkParams,
+    ) -> jsonrpc::Result<Option<Vec<DocumentLink>>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let workspace = world.get_workspace();
+        let source = workspace.sources.get_open_source_by_id(source_id);
+
+        Ok(Some(self.get_document_links(workspace, source)))
+    }
+
+    async fn document_color(
+        &self,
+        params: DocumentColorParams,
+    ) -> jsonrpc::Result<Vec<ColorInformation>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.get_document_colors(&world, source))
+    }
+
+    async fn color_presentation(
+        &self,
+        params: ColorPresentationParams,
+    ) -> jsonrpc::Result<Vec<ColorPresentation>> {
+        let uri = &params.text_document.uri;
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let range = LspRange::new(params.range, self.get_const_config().position_encoding);
+        Ok(self.get_color_presentations(&world, source, range, params.color))
+    }
+
+    async fn inlay_hint(&self, params: InlayHintParams) -> jsonrpc::Result<Option<Vec<InlayHint>>> {
+        let uri = &params.text_document.uri;
+
+        if !self.config.read().await.inlay_hints {
+            return Ok(None);
+        }
+
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        let range = LspRange::new(params.range, self.get_const_config().position_encoding);
+        let typst_range = lsp_to_typst::range(&range, source.as_ref());
+
+        Ok(Some(self.get_inlay_hints(&world, source, uri, typst_range)))
+    }
+
+    async fn inlay_hint_resolve(&self, hint: InlayHint) -> jsonrpc::Result<InlayHint> {
+        let Some(data) = hint.data.as_ref().and_then(InlayHintData::from_json) else {
+            return Ok(hint);
+        };
+
+        // The document may have been closed since the hint was sent, so it's left as it is
+        let Ok((world, source_id)) = self.get_world_with_main_uri(&data.uri).await else {
+            return Ok(hint);
+        };
+        let source = world
+            .get_workspace()
+            .sources
+            .get_open_source_by_id(source_id);
+
+        Ok(self.resolve_inlay_hint(&world, source, &data, hint))
+    }
+
     async fn completion(
         &self,
         params: CompletionParams,
@@ -217,30 +871,21 @@ impl LanguageServer for TypstServer {
             .map(|context| context.trigger_kind == CompletionTriggerKind::INVOKED)
             .unwrap_or(false);
 
-        let (world, source_id) = self.get_world_with_main_uri(uri).await;
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
 
         let source = world
             .get_workspace()
             .sources
             .get_open_source_by_id(source_id);
 
-        let typst_offset = lsp_to_typst::position_to_offset(
-            position,
-            self.get_const_config().position_encoding,
-            source.as_ref(),
-        );
-
-        let completions = autocomplete(&world, &[], source.as_ref(), typst_offset, explicit);
-
-        match completions {
-            Some((_, c)) => {
-                let lsp_completions = c.iter().map(typst_to_lsp::completion).collect();
-                return Ok(Some(CompletionResponse::Array(lsp_completions)));
-            }
-            None => {
-                return Ok(None);
-            }
+        if let Some(context) = self.get_path_completion_context(&world, source, position) {
+            // Don't hold up the workspace while reading directories
+            drop(world);
+            return Ok(Some(self.get_path_completions(context).await));
         }
+
+        let config = self.config.read().await;
+        Ok(self.get_completions(&world, source, position, explicit, &config.snippets))
     }
 
     async fn signature_help(
@@ -250,7 +895,7 @@ impl LanguageServer for TypstServer {
         let uri = &params.text_document_position_params.text_document.uri;
         let position = params.text_document_position_params.position;
 
-        let (world, source_id) = self.get_world_with_main_uri(uri).await;
+        let (world, source_id) = self.get_world_with_main_uri(uri).await?;
 
         let source = world
             .get_workspace()
@@ -261,29 +906,19 @@ impl LanguageServer for TypstServer {
     }
 
     async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
-        let settings = params.settings;
-        let mut config = self.config.write().await;
-        if let JsonValue::Object(settings) = settings {
-            let export_pdf = settings
-                .get("exportPdf")
-                .map(|val| match val {
-                    JsonValue::String(val) => match val.as_str() {
-                        "never" => ExportPdfMode::Never,
-                        "onSave" => ExportPdfMode::OnSave,
-                        "onType" => ExportPdfMode::OnType,
-                        _ => ExportPdfMode::OnSave,
-                    },
-                    _ => ExportPdfMode::OnSave,
-                })
-                .unwrap_or_default();
-            config.export_pdf = export_pdf;
-            self.client
-                .log_message(MessageType::INFO, "New settings applied")
-                .await;
-        } else {
-            self.client
-                .log_message(MessageType::ERROR, "Got invalid configuration object")
-                .await;
+        // Clients using the pull model send no settings, so ask for them
+        let settings = match params.settings {
+            JsonValue::Object(settings) => Some(settings),
+            JsonValue::Null => self.pull_settings().await,
+            _ => None,
+        };
+        match settings {
+            Some(settings) => self.apply_settings(&settings).await,
+            None => {
+                self.client
+                    .log_message(MessageType::ERROR, "Got invalid configuration object")
+                    .await
+            }
         }
     }
 }
@@ -0,0 +1,148 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use tower_lsp::lsp_types::{DiagnosticRelatedInformation, Location, Url};
+
+use crate::lsp_typst_boundary::{LspDiagnostic, LspRawRange};
+
+use super::TypstServer;
+
+/// Start of the message of Typst's error for files which don't exist, which is followed by the
+/// path and a closing parenthesis
+const NOT_FOUND_PREFIX: &str = "file not found (searched at ";
+
+/// Most characters a file's name can differ by from the missing one to be suggested in its place
+const MAX_SUGGESTION_DISTANCE: usize = 3;
+
+impl TypstServer {
+    /// Suggests a file with a similar name in the same directory for each file which wasn't
+    /// found, like a misspelled import. The suggestion is added to the message, and linked to as
+    /// related information.
+    pub fn suggest_missing_files(diagnostics: &mut [LspDiagnostic]) {
+        for diagnostic in diagnostics {
+            let Some(missing_path) = diagnostic
+                .message
+                .strip_prefix(NOT_FOUND_PREFIX)
+                .and_then(|rest| rest.strip_suffix(')'))
+            else {
+                continue;
+            };
+            let Some(suggestion) = Self::find_similar_file(Path::new(missing_path)) else {
+                continue;
+            };
+            let Ok(suggestion_uri) = Url::from_file_path(&suggestion) else {
+                continue;
+            };
+
+            let file_name = suggestion.file_name().unwrap_or_default().to_string_lossy();
+            diagnostic.message = format!("{}; did you mean `{file_name}`?", diagnostic.message);
+            diagnostic
+                .related_information
+                .get_or_insert_with(Vec::new)
+                .push(DiagnosticRelatedInformation {
+                    location: Location::new(suggestion_uri, LspRawRange::default()),
+                    message: format!("`{file_name}` has a similar name"),
+                });
+        }
+    }
+
+    /// Finds the file in the directory of the missing one whose name is closest to its name, if
+    /// any is close enough
+    fn find_similar_file(missing_path: &Path) -> Option<PathBuf> {
+        let dir = missing_path.parent()?;
+        let missing_name = missing_path.file_name()?.to_str()?.to_lowercase();
+
+        fs::read_dir(dir)
+            .ok()?
+            .filter_map(Result::ok)
+            .filter(|entry| {
+                entry
+                    .file_type()
+                    .map_or(false, |file_type| file_type.is_file())
+            })
+            .filter_map(|entry| {
+                let name = entry.file_name().to_str()?.to_lowercase();
+                let distance = Self::edit_distance(&missing_name, &name);
+                Some((distance, entry.path()))
+            })
+            .filter(|(distance, _)| *distance <= MAX_SUGGESTION_DISTANCE)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, path)| path)
+    }
+
+    /// Number of characters which have to be inserted, removed or replaced to turn one string into
+    /// the other
+    fn edit_distance(a: &str, b: &str) -> usize {
+        let b: Vec<char> = b.chars().collect();
+        let mut previous_row: Vec<usize> = (0..=b.len()).collect();
+        for (i, a_char) in a.chars().enumerate() {
+            let mut row = vec![i + 1];
+            for (j, b_char) in b.iter().enumerate() {
+                let replace = previous_row[j] + usize::from(a_char != *b_char);
+                let remove = previous_row[j + 1] + 1;
+                let insert = row[j] + 1;
+                row.push(replace.min(remove).min(insert));
+            }
+            previous_row = row;
+        }
+        previous_row[b.len()]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use typst::diag::FileError;
+
+    use crate::test_utils::TempDir;
+
+    use super::*;
+
+    /// Writes empty files with the names to a new directory, and returns it
+    fn write_files(test_name: &str, names: &[&str]) -> TempDir {
+        let dir = TempDir::new(test_name);
+        for name in names {
+            dir.write(name, "");
+        }
+        dir
+    }
+
+    #[test]
+    fn edit_distance() {
+        assert_eq!(TypstServer::edit_distance("main.typ", "main.typ"), 0);
+        assert_eq!(TypstServer::edit_distance("", "abc"), 3);
+        assert_eq!(TypstServer::edit_distance("kitten", "sitting"), 3);
+        assert_eq!(TypstServer::edit_distance("chaptre.typ", "chapter.typ"), 2);
+    }
+
+    #[test]
+    fn similar_file_is_found() {
+        let dir = write_files("similar-file", &["chapter.typ", "notes.typ"]);
+
+        let similar = TypstServer::find_similar_file(&dir.path().join("Chaptre.typ"));
+        let unrelated = TypstServer::find_similar_file(&dir.path().join("bibliography.yml"));
+
+        assert_eq!(similar, Some(dir.path().join("chapter.typ")));
+        assert_eq!(unrelated, None);
+    }
+
+    #[test]
+    fn not_found_error_gets_suggestion() {
+        let dir = write_files("not-found-suggestion", &["chapter.typ"]);
+        let missing_path = dir.path().join("chaptre.typ");
+        let mut diagnostics = [LspDiagnostic {
+            message: FileError::NotFound(missing_path).to_string(),
+            ..Default::default()
+        }];
+
+        TypstServer::suggest_missing_files(&mut diagnostics);
+
+        assert!(diagnostics[0]
+            .message
+            .ends_with("; did you mean `chapter.typ`?"));
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        assert_eq!(
+            related[0].location.uri,
+            Url::from_file_path(dir.path().join("chapter.typ")).unwrap()
+        );
+    }
+}
@@ -1,23 +1,61 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize};
 use std::sync::Arc;
 
 use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
+use serde_json::{Map, Value as JsonValue};
 use tokio::sync::RwLock;
 use tower_lsp::lsp_types::Url;
-use tower_lsp::Client;
+use tower_lsp::{jsonrpc, Client};
 
-use crate::config::{Config, ConstConfig};
+use crate::config::{Config, ConstConfig, FontConfig};
 use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::LspDiagnostic;
 use crate::workspace::source_manager::SourceId;
 use crate::workspace::Workspace;
 
+use self::idle::IdleDetector;
+use self::shutdown::PendingExports;
+use self::typst_compiler::CompileWorker;
+
+pub mod auto_import;
+pub mod call_hierarchy;
+pub mod code_lens;
+pub mod color;
 pub mod command;
+pub mod compile_selection;
+pub mod completion;
+pub mod definition;
 pub mod diagnostics;
 pub mod document;
 pub mod export;
+pub mod export_directive;
+pub mod folding;
+pub mod format;
+pub mod highlight;
 pub mod hover;
+pub mod idle;
+pub mod inlay;
+pub mod linked_editing;
+pub mod links;
+pub mod lint;
 pub mod log;
 pub mod lsp;
+pub mod missing_file;
+pub mod partial_result;
+pub mod progress;
+pub mod references;
+pub mod rename;
+pub mod selection;
+pub mod semantic_tokens;
+pub mod settings;
+pub mod shutdown;
 pub mod signature;
+pub mod status;
+pub mod symbols;
+pub mod syntax_tree;
 pub mod typst_compiler;
 pub mod watch;
 
@@ -26,6 +64,42 @@ pub struct TypstServer {
     workspace: Arc<RwLock<Workspace>>,
     config: Arc<RwLock<Config>>,
     const_config: OnceCell<ConstConfig>,
+    /// Number of edits made to each source, so that diagnostics for older edits can be skipped
+    edit_counts: Mutex<HashMap<SourceId, u64>>,
+    /// Number of changes received to any source, so that diagnostics computed before the latest
+    /// change aren't published
+    edit_generation: AtomicU64,
+    /// Whether sources changed in ways which may affect diagnostics since they were last computed
+    diagnostics_outdated: AtomicBool,
+    /// Number of pages each source last compiled to, for the compile status of sources which
+    /// are only evaluated
+    page_counts: Mutex<HashMap<SourceId, usize>>,
+    /// The diagnostics last computed, which clients pulling diagnostics get, or `None` before any
+    /// were
+    pulled_diagnostics: Mutex<Option<HashMap<Url, Vec<LspDiagnostic>>>>,
+    /// The configured `cache_max_age`, kept here so that compiling doesn't need to lock the config
+    cache_max_age: AtomicUsize,
+    /// The configured `compile_timeout_secs`, kept here for the same reason
+    compile_timeout_secs: AtomicU64,
+    /// The configured `strict_sources`, kept here for the same reason
+    strict_sources: AtomicBool,
+    /// Shared with the task which detects idleness, which evicts the incremental cache on it
+    compile_worker: Arc<CompileWorker>,
+    /// Files other than sources and fonts which have been loaded, and are watched for changes
+    watched_resources: Mutex<HashSet<Url>>,
+    /// Shared with the task which detects idleness, so that it can outlive any single request
+    idle: Arc<IdleDetector>,
+    /// Whether background work resumed after being idle, and the open files still have to be
+    /// checked for changes skipped in the meantime
+    pending_catch_up: AtomicBool,
+    /// The configured `main_file`, loaded when the setting changes rather than on each edit, or
+    /// `None` if no file is pinned
+    pinned_main: Mutex<Option<SourceId>>,
+    /// Exports being written, which shutting down waits for
+    pending_exports: PendingExports,
+    /// The settings last received from the client, to merge the project's settings under again
+    /// when they change
+    client_settings: Mutex<Map<String, JsonValue>>,
 }
 
 impl TypstServer {
@@ -35,6 +109,21 @@ impl TypstServer {
             workspace: Default::default(),
             config: Default::default(),
             const_config: Default::default(),
+            edit_counts: Default::default(),
+            edit_generation: Default::default(),
+            diagnostics_outdated: Default::default(),
+            page_counts: Default::default(),
+            pulled_diagnostics: Default::default(),
+            cache_max_age: AtomicUsize::new(Config::default().cache_max_age),
+            compile_timeout_secs: AtomicU64::new(Config::default().compile_timeout_secs),
+            strict_sources: AtomicBool::new(Config::default().strict_sources),
+            compile_worker: Default::default(),
+            watched_resources: Default::default(),
+            idle: Default::default(),
+            pending_catch_up: Default::default(),
+            pinned_main: Default::default(),
+            pending_exports: Default::default(),
+            client_settings: Default::default(),
         }
     }
 
@@ -44,17 +133,97 @@ impl TypstServer {
             .expect("const config should be initialized")
     }
 
-    pub async fn get_world_with_main_uri(&self, main: &Url) -> (WorkspaceWorld, SourceId) {
-        let workspace = self.workspace.read().await;
+    /// Gets a world compiling the open document. Fails for documents which aren't open, like
+    /// those the server refused to open because it ran out of source ids.
+    pub async fn get_world_with_main_uri(
+        &self,
+        main: &Url,
+    ) -> jsonrpc::Result<(WorkspaceWorld, SourceId)> {
+        let workspace = Arc::clone(&self.workspace).read_owned().await;
         let source_id = workspace
             .sources
             .get_id_by_uri(main)
-            .expect("source should exist");
-        drop(workspace);
-        (self.get_world_with_main(source_id).await, source_id)
+            .filter(|&id| workspace.sources.get_source_by_id(id).is_some())
+            .ok_or_else(|| jsonrpc::Error::invalid_params(format!("{main} is not open")))?;
+        Ok((WorkspaceWorld::new(workspace, source_id), source_id))
     }
 
     pub async fn get_world_with_main(&self, main: SourceId) -> WorkspaceWorld {
         WorkspaceWorld::new(Arc::clone(&self.workspace).read_owned().await, main)
     }
+
+    /// Gets a world with the text as its main source, without adding it to the workspace
+    pub async fn get_detached_world(&self, text: String) -> WorkspaceWorld {
+        WorkspaceWorld::new_detached(Arc::clone(&self.workspace).read_owned().await, text, None)
+    }
+
+    /// Rebuilds the fonts to include those in `font_paths`, and evicts cached compilations so they
+    /// pick up the new fonts
+    pub async fn reload_fonts(&self, font_paths: &[PathBuf], font_config: &FontConfig) {
+        let font_paths = self.resolve_font_paths(font_paths);
+        self.workspace
+            .write()
+            .await
+            .reload_fonts(&font_paths, font_config);
+        self.compile_worker.evict(0);
+    }
+
+    /// Rebuilds the fonts like `reload_fonts`, and watches the font paths so that fonts are
+    /// reloaded when font files are added, changed or removed
+    pub async fn reload_and_watch_fonts(&self, font_paths: &[PathBuf], font_config: &FontConfig) {
+        self.reload_fonts(font_paths, font_config).await;
+        self.update_font_watcher(&self.resolve_font_paths(font_paths))
+            .await;
+    }
+
+    pub fn resolve_font_paths(&self, font_paths: &[PathBuf]) -> Vec<PathBuf> {
+        font_paths
+            .iter()
+            .map(|path| self.resolve_workspace_path(path))
+            .collect()
+    }
+
+    /// Resolves a relative path from the config against the first workspace root
+    pub fn resolve_workspace_path(&self, path: &Path) -> PathBuf {
+        match self.get_const_config().root_paths.first() {
+            Some(root_path) if path.is_relative() => root_path.join(path),
+            _ => path.to_owned(),
+        }
+    }
+
+    /// Sets the size of the largest file Typst code can load
+    pub async fn update_max_resource_size(&self, max_size_mb: Option<u64>) {
+        let max_size = max_size_mb.map(|max_size_mb| max_size_mb.saturating_mul(1024 * 1024));
+        let workspace = self.workspace.read().await;
+        workspace.resources.write().set_max_size(max_size);
+        workspace.sources.clear_documents();
+    }
+
+    /// Sets the root directory absolute paths in imports are resolved against, or the markers used
+    /// to detect it
+    pub async fn update_root(&self, root_path: Option<&Path>, root_markers: Vec<String>) {
+        let root_path = root_path.map(|path| self.resolve_workspace_path(path));
+        self.workspace
+            .write()
+            .await
+            .set_root(root_path, root_markers);
+    }
+
+    /// Sets the globs of paths to skip when loading every file in the workspace
+    pub async fn update_index_exclude(&self, index_exclude: Vec<String>) {
+        self.workspace
+            .write()
+            .await
+            .set_index_exclude(index_exclude);
+    }
+
+    /// Loads every Typst file in the client's workspace folders, not just those which are open or
+    /// imported, for queries that span the whole workspace
+    pub fn cache_workspace_sources(&self, workspace: &Workspace) {
+        for root_path in &self.get_const_config().root_paths {
+            workspace
+                .sources
+                .cache_dir(root_path, workspace.get_index_exclude());
+        }
+    }
 }
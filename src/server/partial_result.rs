@@ -0,0 +1,24 @@
+use serde_json::{json, Value as JsonValue};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::ProgressToken;
+
+use super::TypstServer;
+
+/// Notification carrying part of a request's result, sent as `$/progress` with the
+/// `partialResultToken` the client passed. `lsp_types` only knows of `$/progress` reporting work
+/// done, so the params are built by hand.
+pub enum PartialResult {}
+
+impl Notification for PartialResult {
+    type Params = JsonValue;
+    const METHOD: &'static str = "$/progress";
+}
+
+impl TypstServer {
+    /// Sends a batch of results, as a JSON array, for the request the token came with. Once any
+    /// batch was sent, the request itself must respond with no results.
+    pub async fn send_partial_result(&self, token: &ProgressToken, results: JsonValue) {
+        let params = json!({ "token": token, "value": results });
+        self.client.send_notification::<PartialResult>(params).await;
+    }
+}
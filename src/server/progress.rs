@@ -0,0 +1,87 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tower_lsp::lsp_types::notification::Progress as ProgressNotification;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
+use tower_lsp::lsp_types::{
+    NumberOrString, ProgressParams, ProgressParamsValue, WorkDoneProgress, WorkDoneProgressBegin,
+    WorkDoneProgressCreateParams, WorkDoneProgressEnd, WorkDoneProgressReport,
+};
+
+use super::TypstServer;
+
+/// Used to make a unique token for each task whose progress is reported
+static NEXT_PROGRESS_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A task whose progress may be shown by the client. Quiet tasks, like those run in the
+/// background, and tasks in clients which don't support progress don't report anything.
+#[derive(Debug, Default)]
+pub struct Progress {
+    token: Option<NumberOrString>,
+}
+
+impl Progress {
+    pub fn quiet() -> Self {
+        Self::default()
+    }
+}
+
+impl TypstServer {
+    pub async fn begin_progress(&self, title: &str) -> Progress {
+        if !self.get_const_config().supports_work_done_progress {
+            return Progress::quiet();
+        }
+
+        let id = NEXT_PROGRESS_ID.fetch_add(1, Ordering::Relaxed);
+        let token = NumberOrString::String(format!("typst-lsp/progress/{id}"));
+        let create_params = WorkDoneProgressCreateParams {
+            token: token.clone(),
+        };
+        if self
+            .client
+            .send_request::<WorkDoneProgressCreate>(create_params)
+            .await
+            .is_err()
+        {
+            return Progress::quiet();
+        }
+
+        let begin = WorkDoneProgress::Begin(WorkDoneProgressBegin {
+            title: title.to_owned(),
+            cancellable: Some(false),
+            message: None,
+            percentage: Some(0),
+        });
+        self.send_progress(&token, begin).await;
+
+        Progress { token: Some(token) }
+    }
+
+    pub async fn report_progress(&self, progress: &Progress, message: String, percentage: u32) {
+        if let Some(token) = &progress.token {
+            let report = WorkDoneProgress::Report(WorkDoneProgressReport {
+                cancellable: Some(false),
+                message: Some(message),
+                percentage: Some(percentage.min(100)),
+            });
+            self.send_progress(token, report).await;
+        }
+    }
+
+    pub async fn end_progress(&self, progress: Progress, message: &str) {
+        if let Some(token) = &progress.token {
+            let end = WorkDoneProgress::End(WorkDoneProgressEnd {
+                message: Some(message.to_owned()),
+            });
+            self.send_progress(token, end).await;
+        }
+    }
+
+    async fn send_progress(&self, token: &NumberOrString, value: WorkDoneProgress) {
+        self.client
+            .send_notification::<ProgressNotification>(ProgressParams {
+                token: token.clone(),
+                value: ProgressParamsValue::WorkDone(value),
+            })
+            .await;
+    }
+}
@@ -0,0 +1,167 @@
+use serde_json::json;
+use tower_lsp::lsp_types::{Location, ProgressToken};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{lsp_to_typst, LspPosition, TypstOffset, TypstSourceId};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// What references are searched for. It doesn't borrow from the syntax tree, so that it can be
+/// kept while results are sent to the client.
+enum ReferenceTarget {
+    /// Identifiers resolving to the binding named `name` at `offset` in the source
+    Binding {
+        source_id: TypstSourceId,
+        offset: TypstOffset,
+        name: String,
+    },
+    /// References to and declarations of the label with the name
+    Label(String),
+}
+
+impl TypstServer {
+    pub fn get_references(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+        include_declaration: bool,
+    ) -> Option<Vec<Location>> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        let leaf = self.get_leaf(source, typst_offset)?;
+
+        let references = self
+            .find_references(world, source, &leaf, include_declaration)?
+            .into_iter()
+            .filter_map(|(source, node)| self.location(source.as_ref(), node.range()))
+            .collect();
+
+        Some(references)
+    }
+
+    /// Like `get_references`, but sends the references in each file to the client as soon as
+    /// the file was searched, rather than after searching the whole workspace
+    pub async fn stream_references(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+        include_declaration: bool,
+        token: &ProgressToken,
+    ) -> Option<Vec<Location>> {
+        let target = {
+            let typst_offset = lsp_to_typst::position_to_offset(
+                position,
+                self.get_const_config().position_encoding,
+                source.as_ref(),
+            );
+            let leaf = self.get_leaf(source, typst_offset)?;
+            self.get_reference_target(world, source, &leaf)?
+        };
+
+        for candidate_source in world.get_workspace().sources.get_sources() {
+            let locations: Vec<Location> = self
+                .find_references_in(world, &target, candidate_source, include_declaration)
+                .into_iter()
+                .filter_map(|node| self.location(candidate_source.as_ref(), node.range()))
+                .collect();
+            if !locations.is_empty() {
+                self.send_partial_result(token, json!(locations)).await;
+            }
+        }
+
+        Some(Vec::new())
+    }
+
+    /// Finds all nodes in the workspace which refer to the same binding or label as `leaf`
+    pub fn find_references<'a>(
+        &self,
+        world: &'a WorkspaceWorld,
+        source: &'a Source,
+        leaf: &LinkedNode<'a>,
+        include_declaration: bool,
+    ) -> Option<Vec<(&'a Source, LinkedNode<'a>)>> {
+        let target = self.get_reference_target(world, source, leaf)?;
+
+        let references = world
+            .get_workspace()
+            .sources
+            .get_sources()
+            .into_iter()
+            .flat_map(|candidate_source| {
+                self.find_references_in(world, &target, candidate_source, include_declaration)
+                    .into_iter()
+                    .map(move |node| (candidate_source, node))
+            })
+            .collect();
+
+        Some(references)
+    }
+
+    fn get_reference_target(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        leaf: &LinkedNode,
+    ) -> Option<ReferenceTarget> {
+        match leaf.kind() {
+            SyntaxKind::Ident | SyntaxKind::MathIdent => {
+                let (definition_source, definition) =
+                    self.resolve_definition(world, source, leaf)?;
+                Some(ReferenceTarget::Binding {
+                    source_id: definition_source.as_ref().id(),
+                    offset: definition.offset(),
+                    name: leaf.text().to_string(),
+                })
+            }
+            SyntaxKind::Ref | SyntaxKind::Label | SyntaxKind::RefMarker => self
+                .get_label_name(&self.get_label_node(leaf.clone()))
+                .map(ReferenceTarget::Label),
+            _ => None,
+        }
+    }
+
+    /// Finds the nodes in one source which refer to the target
+    fn find_references_in<'a>(
+        &self,
+        world: &'a WorkspaceWorld,
+        target: &ReferenceTarget,
+        candidate_source: &'a Source,
+        include_declaration: bool,
+    ) -> Vec<LinkedNode<'a>> {
+        let root = LinkedNode::new(candidate_source.as_ref().root());
+        let descendants = self.get_descendants(&root).into_iter();
+
+        match target {
+            ReferenceTarget::Binding {
+                source_id,
+                offset,
+                name,
+            } => descendants
+                .filter(|node| {
+                    matches!(node.kind(), SyntaxKind::Ident | SyntaxKind::MathIdent)
+                        && node.text() == name.as_str()
+                })
+                .filter(|node| {
+                    self.resolve_definition(world, candidate_source, node)
+                        .map_or(false, |(resolved_source, resolved)| {
+                            resolved_source.as_ref().id() == *source_id
+                                && resolved.offset() == *offset
+                        })
+                })
+                .filter(|node| include_declaration || !self.is_binding_site(node))
+                .collect(),
+            ReferenceTarget::Label(name) => descendants
+                .filter(|node| matches!(node.kind(), SyntaxKind::Ref | SyntaxKind::Label))
+                .filter(|node| self.get_label_name(node).as_deref() == Some(name.as_str()))
+                .filter(|node| include_declaration || !self.is_label_declaration(node))
+                .collect(),
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::{PrepareRenameResponse, TextEdit, Url, WorkspaceEdit};
+use typst::syntax::{is_id_continue, is_id_start, LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    /// Gets the range of the identifier or label at the position and its current name, without
+    /// the delimiters of labels and references. Identifiers which aren't bound in the workspace,
+    /// like those of the standard library, can't be renamed, so they get `None`, as does anything
+    /// which isn't an identifier or label.
+    pub fn prepare_rename_at_position(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+    ) -> Option<PrepareRenameResponse> {
+        let leaf = self.get_leaf_at_position(source, position)?;
+        let (typst_range, placeholder) = match leaf.kind() {
+            SyntaxKind::Ident | SyntaxKind::MathIdent => {
+                self.resolve_definition(world, source, &leaf)?;
+                (leaf.range(), leaf.text().to_string())
+            }
+            SyntaxKind::Label | SyntaxKind::Ref => (
+                self.get_label_name_range(&leaf)?,
+                self.get_label_name(&leaf)?,
+            ),
+            _ => return None,
+        };
+
+        let lsp_range = typst_to_lsp::range(
+            typst_range,
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+        Some(PrepareRenameResponse::RangeWithPlaceholder {
+            range: lsp_range.raw_range,
+            placeholder,
+        })
+    }
+
+    pub fn rename_at_position(
+        &self,
+        world: &WorkspaceWorld,
+        source: &Source,
+        position: LspPosition,
+        new_name: &str,
+    ) -> Result<Option<WorkspaceEdit>> {
+        let Some(leaf) = self.get_leaf_at_position(source, position) else {
+            return Ok(None);
+        };
+
+        let is_label = matches!(leaf.kind(), SyntaxKind::Label | SyntaxKind::Ref);
+        let is_valid = if is_label {
+            Self::is_label_name(new_name)
+        } else {
+            Self::is_ident(new_name)
+        };
+        if !is_valid {
+            return Err(Error::invalid_params(format!(
+                "`{new_name}` is not a valid Typst {}",
+                if is_label { "label" } else { "identifier" }
+            )));
+        }
+
+        let Some(references) = self.find_references(world, source, &leaf, true) else {
+            return Ok(None);
+        };
+
+        let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+        for (reference_source, node) in references {
+            let typst_range = match node.kind() {
+                SyntaxKind::Label | SyntaxKind::Ref => self.get_label_name_range(&node),
+                _ => Some(node.range()),
+            };
+            let Some(typst_range) = typst_range else {
+                continue;
+            };
+
+            let uri = reference_source.uri().clone();
+            let lsp_range = typst_to_lsp::range(
+                typst_range,
+                reference_source.as_ref(),
+                self.get_const_config().position_encoding,
+            );
+
+            changes.entry(uri).or_default().push(TextEdit {
+                range: lsp_range.raw_range,
+                new_text: new_name.to_owned(),
+            });
+        }
+
+        Ok(Some(WorkspaceEdit {
+            changes: Some(changes),
+            ..Default::default()
+        }))
+    }
+
+    /// Gets the leaf at the position, or the reference it's the marker of
+    fn get_leaf_at_position<'a>(
+        &self,
+        source: &'a Source,
+        position: LspPosition,
+    ) -> Option<LinkedNode<'a>> {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+        self.get_leaf(source, typst_offset)
+            .map(|leaf| self.get_label_node(leaf))
+    }
+
+    fn is_ident(name: &str) -> bool {
+        let mut chars = name.chars();
+        chars.next().map_or(false, is_id_start) && chars.all(is_id_continue)
+    }
+
+    fn is_label_name(name: &str) -> bool {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| is_id_continue(c) || matches!(c, ':' | '.'))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use tokio::sync::RwLock;
+    use tower_lsp::LspService;
+
+    use crate::config::{ConstConfig, PositionEncoding};
+    use crate::lsp_typst_boundary::LspRange;
+    use crate::workspace::Workspace;
+
+    use super::*;
+
+    /// Opens the text as a file, then prepares renaming at the end of the first occurrence of `at`
+    /// in it, returning the text in the range to rename along with the placeholder
+    fn prepare_rename(text: &str, at: &str) -> Option<(String, String)> {
+        let path = std::env::temp_dir().join("typst-lsp-prepare-rename.typ");
+        let uri = Url::from_file_path(path).unwrap();
+        let mut workspace = Workspace::default();
+        workspace
+            .sources
+            .insert_open(&uri, text.to_owned())
+            .unwrap();
+        let id = workspace.sources.get_id_by_uri(&uri).unwrap();
+        let world = WorkspaceWorld::new(
+            Arc::new(RwLock::new(workspace)).try_read_owned().unwrap(),
+            id,
+        );
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let server = service.inner();
+        server
+            .const_config
+            .set(ConstConfig {
+                position_encoding: PositionEncoding::Utf16,
+                root_paths: Vec::new(),
+                supports_work_done_progress: false,
+                supports_pull_diagnostics: false,
+                supports_diagnostic_refresh: false,
+            })
+            .unwrap();
+
+        let source = world.get_workspace().sources.get_open_source_by_id(id);
+        let offset = text.find(at).unwrap() + at.len();
+        let position =
+            typst_to_lsp::offset_to_position(offset, PositionEncoding::Utf16, source.as_ref());
+        let PrepareRenameResponse::RangeWithPlaceholder { range, placeholder } =
+            server.prepare_rename_at_position(&world, source, position)?
+        else {
+            panic!("rename should be prepared with a placeholder");
+        };
+        let range = lsp_to_typst::range(
+            &LspRange::new(range, PositionEncoding::Utf16),
+            source.as_ref(),
+        );
+        Some((text[range].to_owned(), placeholder))
+    }
+
+    #[test]
+    fn prepare_rename_label() {
+        let prepared = prepare_rename("= Intro <intro>\nSee @intro.", "<intro>");
+        assert_eq!(prepared, Some(("intro".to_owned(), "intro".to_owned())));
+    }
+
+    #[test]
+    fn prepare_rename_ref() {
+        let prepared = prepare_rename("= Intro <intro>\nSee @intro[Section].", "@intro");
+        assert_eq!(prepared, Some(("intro".to_owned(), "intro".to_owned())));
+    }
+
+    #[test]
+    fn prepare_rename_binding() {
+        let prepared = prepare_rename("#let total = 1\n#total", "#total");
+        assert_eq!(prepared, Some(("total".to_owned(), "total".to_owned())));
+    }
+
+    #[test]
+    fn prepare_rename_standard_library_ident() {
+        assert_eq!(prepare_rename("#strong[Bold]", "strong"), None);
+    }
+
+    #[test]
+    fn prepare_rename_keyword() {
+        assert_eq!(prepare_rename("#let total = 1", "let"), None);
+    }
+}
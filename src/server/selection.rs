@@ -0,0 +1,190 @@
+use tower_lsp::lsp_types::SelectionRange;
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::{lsp_to_typst, typst_to_lsp, LspPosition, LspRawRange, TypstRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    pub fn get_selection_ranges(
+        &self,
+        source: &Source,
+        positions: &[LspPosition],
+    ) -> Vec<SelectionRange> {
+        positions
+            .iter()
+            .map(|position| self.get_selection_range(source, *position))
+            .collect()
+    }
+
+    /// Gets the chain of ranges from the innermost node at the position up to the whole file
+    fn get_selection_range(&self, source: &Source, position: LspPosition) -> SelectionRange {
+        let typst_offset = lsp_to_typst::position_to_offset(
+            position,
+            self.get_const_config().position_encoding,
+            source.as_ref(),
+        );
+
+        let mut typst_ranges: Vec<TypstRange> = Vec::new();
+        let mut push_range = |typst_range: TypstRange| {
+            if typst_ranges.last() != Some(&typst_range) {
+                typst_ranges.push(typst_range);
+            }
+        };
+
+        let mut node = self.get_leaf(source, typst_offset);
+        while let Some(current) = node {
+            push_range(current.range());
+
+            let parent = current.parent().cloned();
+            if let Some(parent) = &parent {
+                if parent.kind() == SyntaxKind::Markup {
+                    push_range(self.get_paragraph_range(parent, &current));
+                }
+            }
+            node = parent;
+        }
+
+        let selection_range = typst_ranges
+            .into_iter()
+            .rev()
+            .fold(None, |parent, typst_range| {
+                Some(SelectionRange {
+                    range: self.selection_lsp_range(source, typst_range),
+                    parent: parent.map(Box::new),
+                })
+            });
+
+        selection_range.unwrap_or_else(|| SelectionRange {
+            range: LspRawRange::new(position, position),
+            parent: None,
+        })
+    }
+
+    /// Gets the range of the paragraph in `markup` which contains `child`, i.e. the nodes between
+    /// the surrounding paragraph breaks. A break belongs to no paragraph, so it's its own range,
+    /// which keeps each range containing the ones inside it.
+    fn get_paragraph_range(&self, markup: &LinkedNode, child: &LinkedNode) -> TypstRange {
+        let is_break = |node: &LinkedNode| node.kind() == SyntaxKind::Parbreak;
+        if is_break(child) {
+            return child.range();
+        }
+        let children: Vec<_> = markup.children().collect();
+
+        let index = children
+            .iter()
+            .position(|node| node.offset() == child.offset())
+            .unwrap_or_default();
+        let start = children[..index]
+            .iter()
+            .rposition(is_break)
+            .map_or(markup.offset(), |break_index| {
+                children[break_index].range().end
+            });
+        let end = children[index..]
+            .iter()
+            .position(is_break)
+            .map_or(markup.range().end, |break_index| {
+                children[index + break_index].offset()
+            });
+
+        start..end
+    }
+
+    fn selection_lsp_range(&self, source: &Source, typst_range: TypstRange) -> LspRawRange {
+        typst_to_lsp::range(
+            typst_range,
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        )
+        .raw_range
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::lsp_types::Url;
+    use tower_lsp::LspService;
+
+    use crate::config::{ConstConfig, PositionEncoding};
+    use crate::lsp_typst_boundary::LspRange;
+    use crate::workspace::source_manager::SourceManager;
+
+    use super::*;
+
+    /// Gets the ranges selected when expanding the selection from the offset, from the innermost
+    fn get_ranges(text: &str, offset: usize) -> Vec<TypstRange> {
+        let uri = Url::parse("untitled:selection").unwrap();
+        let mut sources = SourceManager::default();
+        sources.insert_open(&uri, text.to_owned()).unwrap();
+        let source = sources.get_open_source_by_id(sources.get_id_by_uri(&uri).unwrap());
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let server = service.inner();
+        server
+            .const_config
+            .set(ConstConfig {
+                position_encoding: PositionEncoding::Utf16,
+                root_paths: Vec::new(),
+                supports_work_done_progress: false,
+                supports_pull_diagnostics: false,
+                supports_diagnostic_refresh: false,
+            })
+            .unwrap();
+
+        let position =
+            typst_to_lsp::offset_to_position(offset, PositionEncoding::Utf16, source.as_ref());
+        let mut selection_range = Some(server.get_selection_range(source, position));
+        let mut ranges = Vec::new();
+        while let Some(current) = selection_range {
+            let range = LspRange::new(current.range, PositionEncoding::Utf16);
+            ranges.push(lsp_to_typst::range(&range, source.as_ref()));
+            selection_range = current.parent.map(|parent| *parent);
+        }
+        ranges
+    }
+
+    fn contains(outer: &TypstRange, inner: &TypstRange) -> bool {
+        outer.start <= inner.start && inner.end <= outer.end
+    }
+
+    #[test]
+    fn word_expands_to_paragraph() {
+        let text = "First paragraph\n\nSecond *bold* words\n\nThird";
+        let offset = text.find("bold").unwrap();
+        let ranges = get_ranges(text, offset);
+
+        let paragraph_start = text.find("Second").unwrap();
+        let paragraph_end = text.find("words").unwrap() + "words".len();
+        assert!(ranges.contains(&(paragraph_start..paragraph_end)));
+        assert_eq!(ranges.last(), Some(&(0..text.len())));
+    }
+
+    #[test]
+    fn paragraph_break_is_its_own_range() {
+        let text = "First paragraph\n\nSecond paragraph";
+        let offset = text.find('\n').unwrap() + 1;
+        let ranges = get_ranges(text, offset);
+
+        let break_start = text.find('\n').unwrap();
+        assert_eq!(ranges[0], break_start..break_start + 2);
+        assert_eq!(ranges.last(), Some(&(0..text.len())));
+    }
+
+    #[test]
+    fn each_range_contains_the_previous() {
+        let text = "= Heading\n\nSome _text_ here\n\n\n- item $x + 1$\n\nEnd";
+        for offset in 0..=text.len() {
+            let ranges = get_ranges(text, offset);
+            for pair in ranges.windows(2) {
+                assert!(
+                    contains(&pair[1], &pair[0]),
+                    "{:?} should contain {:?} at offset {offset}",
+                    pair[1],
+                    pair[0]
+                );
+            }
+        }
+    }
+}
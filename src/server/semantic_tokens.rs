@@ -0,0 +1,232 @@
+use tower_lsp::lsp_types::{
+    SemanticToken, SemanticTokenModifier, SemanticTokenType, SemanticTokensLegend,
+};
+use typst::ide::{highlight, Tag};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::ext::StrExt;
+use crate::lsp_typst_boundary::{typst_to_lsp, LspPosition, TypstRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// Token types in the order of the legend, so that they can be converted to their index
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+enum TokenType {
+    Comment,
+    String,
+    Keyword,
+    Operator,
+    Number,
+    Function,
+    Parameter,
+    Variable,
+    Punctuation,
+    Escape,
+    Strong,
+    Emph,
+    Link,
+    Raw,
+    Label,
+    Ref,
+    Heading,
+    ListMarker,
+    ListTerm,
+    Delimiter,
+    Error,
+}
+
+const TOKEN_TYPES: [SemanticTokenType; 21] = [
+    SemanticTokenType::COMMENT,
+    SemanticTokenType::STRING,
+    SemanticTokenType::KEYWORD,
+    SemanticTokenType::OPERATOR,
+    SemanticTokenType::NUMBER,
+    SemanticTokenType::FUNCTION,
+    SemanticTokenType::PARAMETER,
+    SemanticTokenType::VARIABLE,
+    SemanticTokenType::new("punctuation"),
+    SemanticTokenType::new("escape"),
+    SemanticTokenType::new("strong"),
+    SemanticTokenType::new("emph"),
+    SemanticTokenType::new("link"),
+    SemanticTokenType::new("raw"),
+    SemanticTokenType::new("label"),
+    SemanticTokenType::new("ref"),
+    SemanticTokenType::new("heading"),
+    SemanticTokenType::new("marker"),
+    SemanticTokenType::new("term"),
+    SemanticTokenType::new("delim"),
+    SemanticTokenType::new("error"),
+];
+
+/// Set on tokens inside an equation, so that math can be styled differently from markup
+const MATH_MODIFIER: u32 = 1 << 0;
+
+const TOKEN_MODIFIERS: [SemanticTokenModifier; 1] = [SemanticTokenModifier::new("math")];
+
+impl From<Tag> for TokenType {
+    fn from(tag: Tag) -> Self {
+        match tag {
+            Tag::Comment => Self::Comment,
+            Tag::Punctuation => Self::Punctuation,
+            Tag::Escape => Self::Escape,
+            Tag::Strong => Self::Strong,
+            Tag::Emph => Self::Emph,
+            Tag::Link => Self::Link,
+            Tag::Raw => Self::Raw,
+            Tag::Label => Self::Label,
+            Tag::Ref => Self::Ref,
+            Tag::Heading => Self::Heading,
+            Tag::ListMarker => Self::ListMarker,
+            Tag::ListTerm => Self::ListTerm,
+            Tag::MathDelimiter => Self::Delimiter,
+            Tag::MathOperator => Self::Operator,
+            Tag::Keyword => Self::Keyword,
+            Tag::Operator => Self::Operator,
+            Tag::Number => Self::Number,
+            Tag::String => Self::String,
+            Tag::Function => Self::Function,
+            Tag::Interpolated => Self::Variable,
+            Tag::Error => Self::Error,
+        }
+    }
+}
+
+/// A semantic token with an absolute position, before delta encoding
+struct AbsoluteToken {
+    start: LspPosition,
+    length: u32,
+    token_type: TokenType,
+    modifiers: u32,
+}
+
+pub fn get_semantic_tokens_legend() -> SemanticTokensLegend {
+    SemanticTokensLegend {
+        token_types: TOKEN_TYPES.to_vec(),
+        token_modifiers: TOKEN_MODIFIERS.to_vec(),
+    }
+}
+
+impl TypstServer {
+    /// Gets the semantic tokens of the source, limited to those overlapping `typst_range` if given
+    pub fn get_semantic_tokens(
+        &self,
+        source: &Source,
+        typst_range: Option<TypstRange>,
+    ) -> Vec<SemanticToken> {
+        let root = LinkedNode::new(source.as_ref().root());
+        let mut tokens = Vec::new();
+        self.collect_semantic_tokens(source, &root, None, 0, &typst_range, &mut tokens);
+        Self::delta_encode(tokens)
+    }
+
+    fn collect_semantic_tokens(
+        &self,
+        source: &Source,
+        node: &LinkedNode,
+        inherited: Option<TokenType>,
+        modifiers: u32,
+        typst_range: &Option<TypstRange>,
+        tokens: &mut Vec<AbsoluteToken>,
+    ) {
+        if let Some(typst_range) = typst_range {
+            if node.range().end < typst_range.start || node.range().start > typst_range.end {
+                return;
+            }
+        }
+
+        let modifiers = if node.kind() == SyntaxKind::Equation {
+            modifiers | MATH_MODIFIER
+        } else {
+            modifiers
+        };
+        let token_type = self.get_token_type(node).or(inherited);
+
+        if node.children().next().is_none() {
+            if let Some(token_type) = token_type {
+                if node.kind() != SyntaxKind::Space {
+                    self.push_leaf_tokens(source, node, token_type, modifiers, tokens);
+                }
+            }
+            return;
+        }
+
+        for child in node.children() {
+            self.collect_semantic_tokens(
+                source,
+                &child,
+                token_type,
+                modifiers,
+                typst_range,
+                tokens,
+            );
+        }
+    }
+
+    fn get_token_type(&self, node: &LinkedNode) -> Option<TokenType> {
+        let is_parameter = node.kind() == SyntaxKind::Ident
+            && node.parent().map_or(false, |parent| {
+                parent.kind() == SyntaxKind::Params
+                    || (parent.kind() == SyntaxKind::Named && node.prev_sibling().is_none())
+            });
+        if is_parameter {
+            return Some(TokenType::Parameter);
+        }
+
+        highlight(node).map(Into::into)
+    }
+
+    /// Pushes the tokens for the leaf, one per line, since not all clients support tokens spanning
+    /// multiple lines
+    fn push_leaf_tokens(
+        &self,
+        source: &Source,
+        leaf: &LinkedNode,
+        token_type: TokenType,
+        modifiers: u32,
+        tokens: &mut Vec<AbsoluteToken>,
+    ) {
+        let encoding = self.get_const_config().position_encoding;
+
+        let mut offset = leaf.offset();
+        for line in leaf.text().split('\n') {
+            let line_text = line.trim_end_matches('\r');
+            if !line_text.is_empty() {
+                tokens.push(AbsoluteToken {
+                    start: typst_to_lsp::offset_to_position(offset, encoding, source.as_ref()),
+                    length: line_text.encoded_len(encoding) as u32,
+                    token_type,
+                    modifiers,
+                });
+            }
+            offset += line.len() + 1;
+        }
+    }
+
+    fn delta_encode(tokens: Vec<AbsoluteToken>) -> Vec<SemanticToken> {
+        let mut previous = LspPosition::new(0, 0);
+
+        tokens
+            .into_iter()
+            .map(|token| {
+                let delta_line = token.start.line - previous.line;
+                let delta_start = if delta_line == 0 {
+                    token.start.character - previous.character
+                } else {
+                    token.start.character
+                };
+                previous = token.start;
+
+                SemanticToken {
+                    delta_line,
+                    delta_start,
+                    length: token.length,
+                    token_type: token.token_type as u32,
+                    token_modifiers_bitset: token.modifiers,
+                }
+            })
+            .collect()
+    }
+}
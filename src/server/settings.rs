@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+use std::sync::atomic::Ordering;
+
+use serde_json::{Map, Value as JsonValue};
+use tower_lsp::lsp_types::{ConfigurationItem, MessageType, Url};
+
+use crate::config::Config;
+use crate::workspace::project::read_project_settings;
+
+use super::TypstServer;
+
+/// Section of the client's settings holding typst-lsp's settings
+const SETTINGS_SECTION: &str = "typst-lsp";
+
+impl TypstServer {
+    /// Asks the client for the current settings, or `None` if it doesn't answer with any
+    pub async fn pull_settings(&self) -> Option<Map<String, JsonValue>> {
+        let item = ConfigurationItem {
+            scope_uri: None,
+            section: Some(SETTINGS_SECTION.to_owned()),
+        };
+        match self.client.configuration(vec![item]).await {
+            Ok(mut values) if !values.is_empty() => match values.swap_remove(0) {
+                JsonValue::Object(settings) => Some(settings),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Replaces the config with the settings, merged over those in the project's `typst.toml`,
+    /// then rebuilds the state derived from them: fonts, the library's auto-imports and inputs,
+    /// the root, resource and index limits, and cache eviction. Diagnostics of the open documents
+    /// are updated, since the rules and fonts they're computed with may have changed. Every
+    /// setting applies without restarting the server.
+    pub async fn apply_settings(&self, settings: &Map<String, JsonValue>) {
+        *self.client_settings.lock() = settings.clone();
+        let settings = &self.merge_project_settings(settings);
+
+        let mut config = self.config.write().await;
+        let old_font_paths = config.font_paths.clone();
+        let old_font_config = config.fonts.clone();
+        let old_auto_imports = config.auto_imports.clone();
+        let old_inputs = config.inputs.clone();
+        config.update(settings);
+        self.cache_max_age
+            .store(config.cache_max_age, Ordering::Relaxed);
+        self.compile_timeout_secs
+            .store(config.compile_timeout_secs, Ordering::Relaxed);
+        self.strict_sources
+            .store(config.strict_sources, Ordering::Relaxed);
+        self.idle.set_timeout(config.idle_timeout_secs);
+        let font_paths = config.font_paths.clone();
+        let font_config = config.fonts.clone();
+        let root_path = config.root_path.clone();
+        let root_markers = config.root_markers.clone();
+        let max_resource_size_mb = config.max_resource_size_mb;
+        let index_exclude = config.index_exclude.clone();
+        let auto_imports = config.auto_imports.clone();
+        let inputs = config.inputs.clone();
+        let main_file = config.main_file.clone();
+        // The workspace is locked after the config elsewhere, so release the config first
+        drop(config);
+
+        if font_paths != old_font_paths || font_config != old_font_config {
+            self.reload_and_watch_fonts(&font_paths, &font_config).await;
+        }
+        self.update_root(root_path.as_deref(), root_markers).await;
+        self.update_max_resource_size(max_resource_size_mb).await;
+        self.update_index_exclude(index_exclude).await;
+        if auto_imports != old_auto_imports || inputs != old_inputs {
+            self.update_library(&auto_imports, &inputs).await;
+        }
+        self.update_pinned_main(main_file.as_deref()).await;
+        self.refresh_open_diagnostics().await;
+
+        self.client
+            .log_message(MessageType::INFO, "New settings applied")
+            .await;
+    }
+
+    /// Applies the settings last received from the client again, after the project's settings
+    /// changed
+    pub async fn reapply_settings(&self) {
+        let settings = self.client_settings.lock().clone();
+        self.apply_settings(&settings).await;
+    }
+
+    /// Gets the `typst.toml` of the first workspace folder, whose settings are used where the
+    /// client's aren't set
+    pub fn get_project_manifest_path(&self) -> Option<PathBuf> {
+        let root_path = self.get_const_config().root_paths.first()?;
+        Some(root_path.join("typst.toml"))
+    }
+
+    /// Whether the file is the project's `typst.toml`
+    pub fn is_project_manifest(&self, uri: &Url) -> bool {
+        self.get_project_manifest_path()
+            .map_or(false, |manifest_path| {
+                uri.to_file_path().ok() == Some(manifest_path)
+            })
+    }
+
+    /// Puts the client's settings over the project's. Clients like VS Code send every setting,
+    /// including those the user never set, so the project's settings also win over client
+    /// settings which just have their default value.
+    fn merge_project_settings(&self, settings: &Map<String, JsonValue>) -> Map<String, JsonValue> {
+        let Some(root_path) = self.get_const_config().root_paths.first() else {
+            return settings.clone();
+        };
+        let mut merged = settings.clone();
+        for (key, value) in read_project_settings(root_path) {
+            let is_set_by_client = settings.get(&key).map_or(false, |client_value| {
+                !Config::is_default_setting(&key, client_value)
+            });
+            if !is_set_by_client {
+                merged.insert(key, value);
+            }
+        }
+        merged
+    }
+
+    /// Recompiles the open documents, and the pinned main file if any, and publishes their
+    /// diagnostics, unless idle
+    pub async fn refresh_open_diagnostics(&self) {
+        self.mark_diagnostics_outdated();
+        if self.idle.is_idle() {
+            return;
+        }
+
+        let first_open_id = self
+            .workspace
+            .read()
+            .await
+            .sources
+            .get_open_ids()
+            .into_iter()
+            .next();
+        let Some(id) = first_open_id else {
+            return;
+        };
+        let world = self.get_world_with_main(id).await;
+        let config = self.config.read().await;
+        let id = self
+            .get_pinned_main(&world.get_workspace().sources)
+            .unwrap_or(id);
+        let world = world.with_main(id);
+        self.run_open_roots_diagnostics(&world, &config, id).await;
+    }
+}
@@ -0,0 +1,49 @@
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Instant};
+
+/// Counts the exports being written, so that shutting down can wait for them to finish rather
+/// than leave truncated files behind
+#[derive(Debug, Default)]
+pub struct PendingExports {
+    count: Mutex<usize>,
+    finished: Notify,
+}
+
+impl PendingExports {
+    /// Marks an export as started. It's finished when the returned guard is dropped.
+    pub fn start(&self) -> PendingExport<'_> {
+        *self.count.lock() += 1;
+        PendingExport(self)
+    }
+
+    /// Waits for the pending exports to finish, returning whether they did before the timeout
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Created before checking, so a notification in between isn't missed
+            let finished = self.finished.notified();
+            if *self.count.lock() == 0 {
+                return true;
+            }
+            if timeout_at(deadline, finished).await.is_err() {
+                return false;
+            }
+        }
+    }
+}
+
+/// An export in progress, which is finished when dropped
+pub struct PendingExport<'a>(&'a PendingExports);
+
+impl Drop for PendingExport<'_> {
+    fn drop(&mut self) {
+        let mut count = self.0.count.lock();
+        *count -= 1;
+        if *count == 0 {
+            self.0.finished.notify_waiters();
+        }
+    }
+}
@@ -31,9 +31,9 @@ impl TypstServer {
 
         self.get_signature_info_at_offset(source, typst_offset, global)
             .map(|signature| SignatureHelp {
+                active_parameter: signature.active_parameter,
                 signatures: vec![signature],
                 active_signature: Some(0),
-                active_parameter: None,
             })
     }
 
@@ -44,10 +44,10 @@ impl TypstServer {
         scope: &Scope,
     ) -> Option<SignatureInformation> {
         let leaf = self.get_leaf(source, typst_offset)?;
-        let (func_ident, args) = self.get_surrounding_function(&leaf)?;
+        let (func_ident, _) = self.get_surrounding_function(&leaf)?;
         let deciding = self.get_deciding(&leaf);
         let func_info = self.get_function_info(scope, &func_ident)?;
-        let current_param_index = self.get_current_param_index(&deciding, func_info, args);
+        let current_param_index = self.get_current_param_index(&deciding, func_info);
 
         let (label, params) = self.get_param_information(func_info);
 
@@ -117,7 +117,6 @@ impl TypstServer {
         &self,
         deciding: &LinkedNode,
         function_info: &FuncInfo,
-        args: ast::Args,
     ) -> Option<usize> {
         match deciding.kind() {
             // After colon: "func(param:|)", "func(param: |)".
@@ -141,10 +140,13 @@ impl TypstServer {
                         .iter()
                         .position(|param| param.named && param.name.starts_with(next.as_str())),
                     None => {
-                        let positional_args_so_far = args
-                            .items()
-                            .filter(|arg| matches!(arg, ast::Arg::Pos(_)))
-                            .count();
+                        // Only the arguments before the cursor decide which parameter is next
+                        let positional_args_so_far = deciding.parent().map_or(0, |args| {
+                            args.children()
+                                .take_while(|arg| arg.offset() < deciding.offset())
+                                .filter(|arg| arg.cast::<ast::Expr>().is_some())
+                                .count()
+                        });
                         function_info
                             .params
                             .iter()
@@ -159,7 +161,7 @@ impl TypstServer {
         }
     }
 
-    fn format_cast_info(info: &CastInfo) -> String {
+    pub fn format_cast_info(info: &CastInfo) -> String {
         match info {
             CastInfo::Any => "any".to_owned(),
             CastInfo::Value(value, _) => value.repr().to_string(),
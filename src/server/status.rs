@@ -0,0 +1,75 @@
+use serde_json::{json, Value as JsonValue};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::DiagnosticSeverity;
+use typst::doc::Document;
+
+use crate::config::Config;
+use crate::lsp_typst_boundary::world::WorkspaceWorld;
+use crate::lsp_typst_boundary::{LspDiagnostic, LspDiagnostics};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// Notification telling the client how compiling a source went, sent after every compilation
+/// triggered by a change to it
+pub enum CompileStatus {}
+
+impl Notification for CompileStatus {
+    type Params = JsonValue;
+    const METHOD: &'static str = "typst-lsp/compileStatus";
+}
+
+impl TypstServer {
+    /// Sends the number of pages the source last compiled to, and how many errors and warnings
+    /// there are across all the files it uses, once the diagnostic settings are applied, like
+    /// they're published. Sources which were only evaluated, which doesn't lay out pages, report
+    /// the page count of their last compilation.
+    pub async fn send_compile_status(
+        &self,
+        world: &WorkspaceWorld,
+        config: &Config,
+        source: &Source,
+        document: Option<&Document>,
+        diagnostics: &LspDiagnostics,
+    ) {
+        let source_id = source.as_ref().id().into();
+        let page_count = match document {
+            Some(document) => {
+                let page_count = document.pages.len();
+                self.page_counts.lock().insert(source_id, page_count);
+                Some(page_count)
+            }
+            None => self.page_counts.lock().get(&source_id).copied(),
+        };
+
+        let diagnostics: Vec<LspDiagnostic> = diagnostics
+            .iter()
+            .flat_map(|(uri, file_diagnostics)| {
+                let mut file_diagnostics = file_diagnostics.clone();
+                self.apply_diagnostic_config(
+                    world.get_workspace(),
+                    config,
+                    uri,
+                    &mut file_diagnostics,
+                );
+                file_diagnostics
+            })
+            .collect();
+        let count = |severity: DiagnosticSeverity| {
+            diagnostics
+                .iter()
+                .filter(|diagnostic| diagnostic.severity == Some(severity))
+                .count()
+        };
+        let error_count = count(DiagnosticSeverity::ERROR);
+
+        let status = json!({
+            "uri": source.uri(),
+            "success": error_count == 0,
+            "pageCount": page_count,
+            "errorCount": error_count,
+            "warningCount": count(DiagnosticSeverity::WARNING),
+        });
+        self.client.send_notification::<CompileStatus>(status).await;
+    }
+}
@@ -0,0 +1,219 @@
+use tower_lsp::lsp_types::{DocumentSymbol, Location, SymbolInformation, SymbolKind};
+use typst::syntax::{LinkedNode, SyntaxKind};
+
+use crate::lsp_typst_boundary::{typst_to_lsp, LspRawRange, TypstOffset, TypstRange};
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+/// A heading whose section hasn't ended yet while building the outline
+struct Section {
+    level: usize,
+    start: TypstOffset,
+    symbol: DocumentSymbol,
+}
+
+enum OutlineItem {
+    Heading(Section),
+    Symbol(DocumentSymbol),
+}
+
+impl TypstServer {
+    /// Gets the outline of the source, where the symbols following a heading are nested under it
+    pub fn get_document_symbols(&self, source: &Source) -> Vec<DocumentSymbol> {
+        let root = LinkedNode::new(source.as_ref().root());
+        let mut items = Vec::new();
+        self.collect_outline_items(source, &root, &mut items);
+
+        let mut symbols = Vec::new();
+        let mut open_sections: Vec<Section> = Vec::new();
+
+        for item in items {
+            match item {
+                OutlineItem::Heading(section) => {
+                    // A heading ends all sections of the same or a deeper level
+                    while let Some(open) = open_sections.pop() {
+                        if open.level < section.level {
+                            open_sections.push(open);
+                            break;
+                        }
+                        let closed = self.close_section(source, open, section.start);
+                        Self::add_outline_symbol(&mut open_sections, &mut symbols, closed);
+                    }
+                    open_sections.push(section);
+                }
+                OutlineItem::Symbol(symbol) => {
+                    Self::add_outline_symbol(&mut open_sections, &mut symbols, symbol)
+                }
+            }
+        }
+
+        let end = source.as_ref().text().len();
+        while let Some(open) = open_sections.pop() {
+            let closed = self.close_section(source, open, end);
+            Self::add_outline_symbol(&mut open_sections, &mut symbols, closed);
+        }
+
+        symbols
+    }
+
+    /// Gets the symbols in the source's outline whose name contains the query, ignoring case. The
+    /// heading a symbol is under, if any, is its container.
+    pub fn get_workspace_symbols(&self, source: &Source, query: &str) -> Vec<SymbolInformation> {
+        let mut symbols = Vec::new();
+        let query = query.to_lowercase();
+        for symbol in self.get_document_symbols(source) {
+            Self::collect_workspace_symbols(source, symbol, None, &query, &mut symbols);
+        }
+        symbols
+    }
+
+    #[allow(deprecated)] // `SymbolInformation::deprecated` is deprecated, but must still be set
+    fn collect_workspace_symbols(
+        source: &Source,
+        symbol: DocumentSymbol,
+        container_name: Option<&str>,
+        query: &str,
+        symbols: &mut Vec<SymbolInformation>,
+    ) {
+        if symbol.name.to_lowercase().contains(query) {
+            symbols.push(SymbolInformation {
+                name: symbol.name.clone(),
+                kind: symbol.kind,
+                tags: None,
+                deprecated: None,
+                location: Location {
+                    uri: source.uri().clone(),
+                    range: symbol.selection_range,
+                },
+                container_name: container_name.map(str::to_owned),
+            });
+        }
+        for child in symbol.children.into_iter().flatten() {
+            Self::collect_workspace_symbols(source, child, Some(&symbol.name), query, symbols);
+        }
+    }
+
+    fn collect_outline_items(
+        &self,
+        source: &Source,
+        node: &LinkedNode,
+        items: &mut Vec<OutlineItem>,
+    ) {
+        match node.kind() {
+            SyntaxKind::Heading => {
+                if let Some(section) = self.heading_section(source, node) {
+                    items.push(OutlineItem::Heading(section));
+                }
+            }
+            SyntaxKind::LetBinding => {
+                let is_function = node
+                    .children()
+                    .any(|child| child.kind() == SyntaxKind::Closure);
+                let kind = if is_function {
+                    SymbolKind::FUNCTION
+                } else {
+                    SymbolKind::VARIABLE
+                };
+
+                for ident in self.get_bound_idents(node) {
+                    let symbol =
+                        self.outline_symbol(source, ident.text().to_string(), kind, node, &ident);
+                    items.push(OutlineItem::Symbol(symbol));
+                }
+            }
+            SyntaxKind::Label if self.is_label_declaration(node) => {
+                let symbol = self.outline_symbol(
+                    source,
+                    node.text().to_string(),
+                    SymbolKind::KEY,
+                    node,
+                    node,
+                );
+                items.push(OutlineItem::Symbol(symbol));
+            }
+            // Bindings inside function bodies are local, so they don't belong in the outline
+            SyntaxKind::Closure => {}
+            _ => {
+                for child in node.children() {
+                    self.collect_outline_items(source, &child, items);
+                }
+            }
+        }
+    }
+
+    fn heading_section(&self, source: &Source, heading: &LinkedNode) -> Option<Section> {
+        let level = self.get_heading_level(heading)?;
+        let title = heading
+            .children()
+            .find(|child| child.kind() == SyntaxKind::Markup)
+            .map(|body| body.get().clone().into_text().trim().to_string())
+            .unwrap_or_default();
+
+        Some(Section {
+            level,
+            start: heading.offset(),
+            symbol: self.outline_symbol(source, title, SymbolKind::STRING, heading, heading),
+        })
+    }
+
+    pub fn get_heading_level(&self, heading: &LinkedNode) -> Option<usize> {
+        let marker = heading
+            .children()
+            .find(|child| child.kind() == SyntaxKind::HeadingMarker)?;
+        Some(marker.len())
+    }
+
+    #[allow(deprecated)] // `DocumentSymbol::deprecated` is deprecated, but must still be set
+    fn outline_symbol(
+        &self,
+        source: &Source,
+        name: String,
+        kind: SymbolKind,
+        node: &LinkedNode,
+        name_node: &LinkedNode,
+    ) -> DocumentSymbol {
+        DocumentSymbol {
+            name,
+            detail: None,
+            kind,
+            tags: None,
+            deprecated: None,
+            range: self.outline_range(source, node.range()),
+            selection_range: self.outline_range(source, name_node.range()),
+            children: None,
+        }
+    }
+
+    /// Extends the heading's range to cover its whole section
+    fn close_section(&self, source: &Source, section: Section, end: TypstOffset) -> DocumentSymbol {
+        DocumentSymbol {
+            range: self.outline_range(source, section.start..end),
+            ..section.symbol
+        }
+    }
+
+    fn add_outline_symbol(
+        open_sections: &mut [Section],
+        symbols: &mut Vec<DocumentSymbol>,
+        symbol: DocumentSymbol,
+    ) {
+        match open_sections.last_mut() {
+            Some(parent) => parent
+                .symbol
+                .children
+                .get_or_insert_with(Vec::new)
+                .push(symbol),
+            None => symbols.push(symbol),
+        }
+    }
+
+    fn outline_range(&self, source: &Source, typst_range: TypstRange) -> LspRawRange {
+        typst_to_lsp::range(
+            typst_range,
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        )
+        .raw_range
+    }
+}
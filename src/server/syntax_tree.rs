@@ -0,0 +1,106 @@
+use serde_json::{json, Value as JsonValue};
+use typst::syntax::LinkedNode;
+
+use crate::lsp_typst_boundary::typst_to_lsp;
+use crate::workspace::source::Source;
+
+use super::TypstServer;
+
+impl TypstServer {
+    /// Describes the syntax tree of the source as nested nodes with their kind, range and
+    /// children. Leaves also have their text.
+    pub fn get_syntax_tree_json(&self, source: &Source) -> JsonValue {
+        let root = LinkedNode::new(source.as_ref().root());
+        self.syntax_node_json(source, &root)
+    }
+
+    /// Describes the leaves of the syntax tree in order, trivia included, so their text adds up
+    /// to the source's text
+    pub fn get_syntax_tokens_json(&self, source: &Source) -> JsonValue {
+        let root = LinkedNode::new(source.as_ref().root());
+        let tokens = self
+            .get_descendants(&root)
+            .iter()
+            .filter(|node| node.children().len() == 0)
+            .map(|leaf| self.syntax_leaf_json(source, leaf))
+            .collect();
+        JsonValue::Array(tokens)
+    }
+
+    fn syntax_node_json(&self, source: &Source, node: &LinkedNode) -> JsonValue {
+        if node.children().len() == 0 {
+            return self.syntax_leaf_json(source, node);
+        }
+
+        let children: Vec<JsonValue> = node
+            .children()
+            .map(|child| self.syntax_node_json(source, &child))
+            .collect();
+        json!({
+            "kind": format!("{:?}", node.kind()),
+            "range": self.syntax_range_json(source, node),
+            "children": children,
+        })
+    }
+
+    fn syntax_leaf_json(&self, source: &Source, leaf: &LinkedNode) -> JsonValue {
+        json!({
+            "kind": format!("{:?}", leaf.kind()),
+            "range": self.syntax_range_json(source, leaf),
+            "text": leaf.text().as_str(),
+        })
+    }
+
+    /// The node's range both as byte offsets and as an LSP range in the configured encoding
+    fn syntax_range_json(&self, source: &Source, node: &LinkedNode) -> JsonValue {
+        let range = node.range();
+        let lsp_range = typst_to_lsp::range(
+            range.clone(),
+            source.as_ref(),
+            self.get_const_config().position_encoding,
+        );
+        json!({
+            "start": range.start,
+            "end": range.end,
+            "lsp": lsp_range.raw_range,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use tower_lsp::LspService;
+
+    use crate::config::{ConstConfig, PositionEncoding};
+
+    use super::*;
+
+    #[test]
+    fn syntax_tokens_add_up_to_text() {
+        let text = "= Title\n#let x = (1, 2) // note\n$a^2$ and *bold* é\n\n#(";
+        let mut source = Source::new_detached();
+        source.replace(text.to_owned());
+
+        let (service, _) = LspService::new(TypstServer::with_client);
+        let server = service.inner();
+        server
+            .const_config
+            .set(ConstConfig {
+                position_encoding: PositionEncoding::Utf16,
+                root_paths: Vec::new(),
+                supports_work_done_progress: false,
+                supports_pull_diagnostics: false,
+                supports_diagnostic_refresh: false,
+            })
+            .unwrap();
+
+        let tokens = server.get_syntax_tokens_json(&source);
+        let token_text: String = tokens
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|token| token["text"].as_str().unwrap())
+            .collect();
+        assert_eq!(token_text, text);
+    }
+}
@@ -1,49 +1,270 @@
+use std::collections::HashSet;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
 use comemo::Track;
+use parking_lot::Mutex;
+use tokio::task;
+use tower_lsp::lsp_types::{DiagnosticSeverity, Url};
+use typst::diag::SourceResult;
 use typst::doc::Document;
 use typst::eval::{Module, Route, Tracer};
 use typst::World;
 
-use crate::lsp_typst_boundary::world::WorkspaceWorld;
-use crate::lsp_typst_boundary::{typst_to_lsp, LspDiagnostics};
+use crate::lsp_typst_boundary::world::{WorkspaceWorld, WorldSnapshot};
+use crate::lsp_typst_boundary::{
+    typst_to_lsp, LspDiagnostic, LspDiagnostics, LspRawRange, TypstSourceId,
+};
 use crate::workspace::source::Source;
+use crate::workspace::source_manager::SourceId;
 
 use super::TypstServer;
 
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A job which is waiting in the queue
+const QUEUED: u8 = 0;
+/// A job which a thread started running
+const STARTED: u8 = 1;
+/// A job which was given up on before it started, so it's skipped
+const CANCELLED: u8 = 2;
+
+/// The thread all compilations and evaluations run on, one after the other. Typst's incremental
+/// cache is kept per thread, so running them on the same thread lets each reuse the work of the
+/// previous ones, and evicting from the cache has to happen there too. A thread stuck on a job
+/// which was given up on is abandoned, and another thread takes over the queue, starting with an
+/// empty cache.
+pub struct CompileWorker {
+    jobs: Mutex<mpsc::Sender<Job>>,
+    queue: Arc<Mutex<mpsc::Receiver<Job>>>,
+    /// Set once the thread taking jobs from the queue is abandoned
+    abandoned: Mutex<Arc<AtomicBool>>,
+}
+
+impl Default for CompileWorker {
+    fn default() -> Self {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(receiver));
+        let abandoned = Self::spawn_thread(Arc::clone(&queue));
+        Self {
+            jobs: Mutex::new(sender),
+            queue,
+            abandoned: Mutex::new(abandoned),
+        }
+    }
+}
+
+/// A job queued on the compile worker
+pub struct PendingJob<T> {
+    result: mpsc::Receiver<thread::Result<T>>,
+    state: Arc<AtomicU8>,
+}
+
+impl CompileWorker {
+    /// Starts a thread running the jobs in the queue until it's abandoned, and returns the flag
+    /// abandoning it
+    fn spawn_thread(queue: Arc<Mutex<mpsc::Receiver<Job>>>) -> Arc<AtomicBool> {
+        let abandoned = Arc::new(AtomicBool::new(false));
+        let thread_abandoned = Arc::clone(&abandoned);
+        thread::Builder::new()
+            .name("typst-compile".to_owned())
+            .spawn(move || loop {
+                let job = {
+                    let queue = queue.lock();
+                    if thread_abandoned.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    // The queue is open as long as the worker exists
+                    let Ok(job) = queue.recv() else {
+                        break;
+                    };
+                    job
+                };
+                job();
+            })
+            .expect("compile thread should start");
+        abandoned
+    }
+
+    /// Queues the job, returning a handle to wait for its result, or for the panic it ended with
+    pub fn submit<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> T + Send + 'static,
+    ) -> PendingJob<T> {
+        let (sender, receiver) = mpsc::channel();
+        let state = Arc::new(AtomicU8::new(QUEUED));
+        let job_state = Arc::clone(&state);
+        let job = Box::new(move || {
+            if job_state
+                .compare_exchange(QUEUED, STARTED, Ordering::SeqCst, Ordering::SeqCst)
+                .is_err()
+            {
+                return;
+            }
+            // Nobody listens anymore if the job timed out
+            let _ = sender.send(panic::catch_unwind(AssertUnwindSafe(job)));
+        });
+        // The queue stays open as long as the worker exists, since its receiver is kept here
+        let _ = self.jobs.lock().send(job);
+        PendingJob {
+            result: receiver,
+            state,
+        }
+    }
+
+    /// Waits for the job to end, giving up on it after the timeout, if any. A job given up on
+    /// before it started is skipped. One given up on while running can't be interrupted, so its
+    /// thread is abandoned and keeps running it, while a new thread runs the jobs queued behind it.
+    pub fn wait<T>(
+        &self,
+        job: PendingJob<T>,
+        timeout: Option<Duration>,
+    ) -> Result<T, CompileFailure> {
+        let received = match timeout {
+            None => job.result.recv().map_err(|_| CompileFailure::Panicked),
+            Some(timeout) => job
+                .result
+                .recv_timeout(timeout)
+                .map_err(|error| match error {
+                    RecvTimeoutError::Timeout => CompileFailure::TimedOut,
+                    RecvTimeoutError::Disconnected => CompileFailure::Panicked,
+                }),
+        };
+        if let Err(CompileFailure::TimedOut) = received {
+            let cancelled = job
+                .state
+                .compare_exchange(QUEUED, CANCELLED, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok();
+            if !cancelled {
+                self.abandon_thread();
+            }
+        }
+        received?.map_err(|_| CompileFailure::Panicked)
+    }
+
+    /// Abandons the thread taking jobs from the queue, which is stuck on a job, to a new thread
+    fn abandon_thread(&self) {
+        let mut abandoned = self.abandoned.lock();
+        abandoned.store(true, Ordering::SeqCst);
+        *abandoned = Self::spawn_thread(Arc::clone(&self.queue));
+    }
+
+    /// Evicts memoized results that haven't been used in the last `max_age` compilations from
+    /// the incremental cache, without waiting for it to happen
+    pub fn evict(&self, max_age: usize) {
+        drop(self.submit(move || comemo::evict(max_age)));
+    }
+}
+
+/// Why a compilation or evaluation ended without a result
+pub enum CompileFailure {
+    TimedOut,
+    Panicked,
+}
+
 impl TypstServer {
     pub fn compile_source(&self, world: &WorkspaceWorld) -> (Option<Document>, LspDiagnostics) {
-        let result = typst::compile(world);
+        let main_id = world.main().id();
+        let result = match self.run_with_timeout(world, main_id, |world| typst::compile(&world)) {
+            Ok(result) => result,
+            Err(failure) => return (None, self.failure_diagnostics(world, main_id, failure)),
+        };
 
+        let missing_sources = world.take_missing_sources();
+        // The Typst version this is built with has no warnings, so successful compilations only
+        // have the server's own, like those about missing sources below
         let (document, errors) = match result {
+            // Documents missing the content of a source aren't kept or exported in strict mode
+            Ok(_) if !missing_sources.is_empty() && self.strict_sources.load(Ordering::Relaxed) => {
+                (None, Default::default())
+            }
             Ok(document) => (Some(document), Default::default()),
             Err(errors) => (Default::default(), errors),
         };
 
-        let diagnostics = typst_to_lsp::source_errors_to_diagnostics(
+        // Detached sources aren't in the source manager
+        if let (Some(document), Some(main_id)) = (&document, world.main_id()) {
+            let sources = &world.get_workspace().sources;
+            sources.set_document(main_id, document.clone());
+        }
+
+        let mut diagnostics = typst_to_lsp::source_errors_to_diagnostics(
             errors.as_ref(),
             world,
             self.get_const_config(),
         );
+        for (uri, diagnostic) in self.missing_source_diagnostics(world, missing_sources) {
+            diagnostics.entry(uri).or_default().push(diagnostic);
+        }
 
-        // Garbage collect incremental cache. This evicts all memoized results that haven't been
-        // used in the last 30 compilations.
-        comemo::evict(30);
+        self.evict_cache();
 
         (document, diagnostics)
     }
 
+    /// Reports that the sources couldn't be loaded and were compiled as empty, as errors if
+    /// `strict_sources` is set, and as warnings otherwise
+    fn missing_source_diagnostics(
+        &self,
+        world: &WorkspaceWorld,
+        source_ids: Vec<SourceId>,
+    ) -> Vec<(Url, LspDiagnostic)> {
+        let severity = if self.strict_sources.load(Ordering::Relaxed) {
+            DiagnosticSeverity::ERROR
+        } else {
+            DiagnosticSeverity::WARNING
+        };
+        source_ids
+            .into_iter()
+            .filter_map(|id| world.source_uri(id.into()))
+            .map(|uri| {
+                let diagnostic = LspDiagnostic {
+                    range: LspRawRange::default(),
+                    severity: Some(severity),
+                    message: "file could not be loaded, so it was compiled as empty".to_owned(),
+                    ..Default::default()
+                };
+                (uri, diagnostic)
+            })
+            .collect()
+    }
+
+    /// Gets the document the main source last compiled to if nothing it may depend on changed
+    /// since, or compiles it otherwise. Errors aren't reported, since they were when compiling
+    /// before.
+    pub fn get_or_compile_document(&self, world: &WorkspaceWorld) -> Option<Document> {
+        if let Some(main_id) = world.main_id() {
+            if let Some(document) = world.get_workspace().sources.get_document(main_id) {
+                return Some(document);
+            }
+        }
+        self.compile_source(world).0
+    }
+
     pub fn eval_source(
         &self,
         world: &WorkspaceWorld,
         source: &Source,
     ) -> (Option<Module>, LspDiagnostics) {
-        let route = Route::default();
-        let mut tracer = Tracer::default();
-        let result = typst::eval::eval(
-            (world as &dyn World).track(),
-            route.track(),
-            tracer.track_mut(),
-            source.as_ref(),
-        );
+        let source_id = source.as_ref().id();
+        let result = self.run_with_timeout(world, source_id, move |world| {
+            let route = Route::default();
+            let mut tracer = Tracer::default();
+            typst::eval::eval(
+                (&world as &dyn World).track(),
+                route.track(),
+                tracer.track_mut(),
+                world.source(source_id),
+            )
+        });
+        let result = match result {
+            Ok(result) => result,
+            Err(failure) => return (None, self.failure_diagnostics(world, source_id, failure)),
+        };
 
         let (module, errors) = match result {
             Ok(module) => (Some(module), Default::default()),
@@ -56,10 +277,149 @@ impl TypstServer {
             self.get_const_config(),
         );
 
-        // Garbage collect incremental cache. This evicts all memoized results that haven't been
-        // used in the last 30 compilations.
-        comemo::evict(30);
+        self.evict_cache();
 
         (module, diagnostics)
     }
+
+    /// Runs compilation on the compile worker, giving up on it after the configured timeout, if
+    /// any. The compilation gets a snapshot of the world with the source and what it depends on,
+    /// so that one which is given up on doesn't keep the workspace locked. Sources it needs which
+    /// aren't loaded, like those imported for the first time, are loaded afterwards, and it's run
+    /// again with them.
+    fn run_with_timeout<T: Send + 'static>(
+        &self,
+        world: &WorkspaceWorld,
+        source_id: TypstSourceId,
+        run: impl FnOnce(WorldSnapshot) -> SourceResult<T> + Clone + Send + 'static,
+    ) -> Result<SourceResult<T>, CompileFailure> {
+        let sources = &world.get_workspace().sources;
+        let mut source_ids = self.load_dependencies(world, source_id.into());
+        loop {
+            let snapshot = world.snapshot(&source_ids);
+            let unresolved = snapshot.unresolved();
+            let run = run.clone();
+            let job = self.compile_worker.submit(move || run(snapshot));
+
+            let timeout_secs = self.compile_timeout_secs.load(Ordering::Relaxed);
+            let timeout = (timeout_secs > 0).then(|| Duration::from_secs(timeout_secs));
+            // Lets the runtime move other tasks off this thread while it waits
+            let result = task::block_in_place(|| self.compile_worker.wait(job, timeout))?;
+
+            let mut loaded_any = false;
+            for uri in unresolved.lock().drain(..) {
+                if let Ok(id) = sources.cache(uri) {
+                    loaded_any |= source_ids.insert(id);
+                }
+            }
+            if !loaded_any {
+                return Ok(result);
+            }
+        }
+    }
+
+    /// Loads the sources the source depends on which are known, and gets their ids along with
+    /// its own
+    pub fn load_dependencies(
+        &self,
+        world: &WorkspaceWorld,
+        source_id: SourceId,
+    ) -> HashSet<SourceId> {
+        let workspace = world.get_workspace();
+        let sources = &workspace.sources;
+        let mut source_ids = HashSet::from([source_id]);
+        for uri in self.get_all_dependencies(workspace, &[source_id]) {
+            // Files which aren't sources, like images, are loaded by Typst when it needs them
+            if sources.get_id_by_uri(&uri).is_some() {
+                source_ids.extend(sources.cache(uri).ok());
+            }
+        }
+        source_ids
+    }
+
+    /// Reports that compiling the source timed out or panicked, at its start
+    fn failure_diagnostics(
+        &self,
+        world: &WorkspaceWorld,
+        source_id: TypstSourceId,
+        failure: CompileFailure,
+    ) -> LspDiagnostics {
+        let Some(uri) = world.source_uri(source_id) else {
+            return LspDiagnostics::new();
+        };
+        let message = match failure {
+            CompileFailure::TimedOut => {
+                let timeout_secs = self.compile_timeout_secs.load(Ordering::Relaxed);
+                format!("compilation timed out after {timeout_secs} seconds")
+            }
+            CompileFailure::Panicked => "compilation panicked; this is a bug in Typst".to_owned(),
+        };
+        let diagnostic = LspDiagnostic {
+            range: LspRawRange::default(),
+            severity: Some(DiagnosticSeverity::ERROR),
+            message,
+            ..Default::default()
+        };
+        LspDiagnostics::from([(uri, vec![diagnostic])])
+    }
+
+    /// Garbage collect incremental cache. This evicts all memoized results that haven't been used
+    /// in the last `cache_max_age` compilations, unless that's 0.
+    fn evict_cache(&self) {
+        let max_age = self.cache_max_age.load(Ordering::Relaxed);
+        if max_age > 0 {
+            self.compile_worker.evict(max_age);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_millis(100));
+
+    #[test]
+    fn job_queued_behind_a_runaway_job_runs() {
+        let worker = CompileWorker::default();
+        let (release, released) = mpsc::channel::<()>();
+        let runaway = worker.submit(move || released.recv().is_ok());
+        let queued = worker.submit(|| 2);
+
+        assert!(matches!(
+            worker.wait(runaway, TIMEOUT),
+            Err(CompileFailure::TimedOut)
+        ));
+        assert!(matches!(worker.wait(queued, TIMEOUT), Ok(2)));
+        drop(release);
+    }
+
+    #[test]
+    fn job_given_up_on_before_it_started_is_skipped() {
+        let worker = CompileWorker::default();
+        let (release, released) = mpsc::channel::<()>();
+        let runaway = worker.submit(move || released.recv().is_ok());
+        let ran = Arc::new(AtomicBool::new(false));
+        let job_ran = Arc::clone(&ran);
+        let skipped = worker.submit(move || job_ran.store(true, Ordering::SeqCst));
+
+        assert!(matches!(
+            worker.wait(skipped, TIMEOUT),
+            Err(CompileFailure::TimedOut)
+        ));
+        release.send(()).unwrap();
+        assert!(matches!(worker.wait(runaway, None), Ok(true)));
+        assert!(matches!(worker.wait(worker.submit(|| ()), None), Ok(())));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn panicking_job_is_reported() {
+        let worker = CompileWorker::default();
+        let job = worker.submit(|| panic!("job panicked"));
+        assert!(matches!(
+            worker.wait::<()>(job, TIMEOUT),
+            Err(CompileFailure::Panicked)
+        ));
+    }
 }
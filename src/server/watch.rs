@@ -1,16 +1,24 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
 use serde_json::to_value;
 use tower_lsp::lsp_types::{
     DidChangeWatchedFilesRegistrationOptions, FileEvent, FileSystemWatcher, GlobPattern,
-    Registration,
+    MessageType, OneOf, Registration, RelativePattern, Unregistration, Url,
 };
 
+use crate::workspace::source_manager::SourceId;
 use crate::workspace::Workspace;
 
 use super::TypstServer;
 
 static WATCH_TYPST_FILES_REGISTRATION_ID: &str = "watch_typst_files";
+static WATCH_FONT_FILES_REGISTRATION_ID: &str = "watch_font_files";
+static WATCH_RESOURCE_FILES_REGISTRATION_ID: &str = "watch_resource_files";
 static WATCH_FILES_METHOD: &str = "workspace/didChangeWatchedFiles";
 
+static FONT_FILES_GLOB: &str = "**/*.{ttf,otf,ttc,otc,TTF,OTF,TTC,OTC}";
+
 impl TypstServer {
     pub fn get_watcher_registration(&self) -> Registration {
         Registration {
@@ -18,17 +26,176 @@ impl TypstServer {
             method: WATCH_FILES_METHOD.to_owned(),
             register_options: Some(
                 to_value(DidChangeWatchedFilesRegistrationOptions {
-                    watchers: vec![FileSystemWatcher {
-                        glob_pattern: GlobPattern::String("**/*.typ".to_owned()),
-                        kind: None,
-                    }],
+                    watchers: vec![
+                        FileSystemWatcher {
+                            glob_pattern: GlobPattern::String("**/*.typ".to_owned()),
+                            kind: None,
+                        },
+                        // Holds project settings
+                        FileSystemWatcher {
+                            glob_pattern: GlobPattern::String("**/typst.toml".to_owned()),
+                            kind: None,
+                        },
+                    ],
                 })
                 .unwrap(),
             ),
         }
     }
 
+    /// Replaces the watchers for font files with ones for the given font files and directories,
+    /// which should already be resolved against the workspace root
+    pub async fn update_font_watcher(&self, font_paths: &[PathBuf]) {
+        let watchers: Vec<FileSystemWatcher> = font_paths
+            .iter()
+            .filter_map(|path| {
+                if path.is_dir() {
+                    Self::relative_watcher(path, FONT_FILES_GLOB.to_owned())
+                } else {
+                    Self::file_watcher(path)
+                }
+            })
+            .collect();
+
+        self.replace_watchers(WATCH_FONT_FILES_REGISTRATION_ID, watchers, "font files")
+            .await;
+    }
+
+    /// Watches the files loaded by Typst code, like images and bibliographies, which weren't
+    /// already watched. Sources and fonts have their own watchers.
+    pub async fn update_resource_watcher(&self, workspace: &Workspace) {
+        let uris = workspace.resources.read().get_uris();
+
+        let watched_resources: Vec<Url> = {
+            let mut watched_resources = self.watched_resources.lock();
+            let new_uris: Vec<Url> = uris
+                .into_iter()
+                .filter(|uri| !Self::is_font_file(uri) && !watched_resources.contains(uri))
+                .collect();
+            if new_uris.is_empty() {
+                return;
+            }
+            watched_resources.extend(new_uris);
+            watched_resources.iter().cloned().collect()
+        };
+
+        let watchers = watched_resources
+            .iter()
+            .filter_map(|uri| Self::file_watcher(&uri.to_file_path().ok()?))
+            .collect();
+        self.replace_watchers(
+            WATCH_RESOURCE_FILES_REGISTRATION_ID,
+            watchers,
+            "loaded files",
+        )
+        .await;
+    }
+
+    async fn replace_watchers(
+        &self,
+        registration_id: &str,
+        watchers: Vec<FileSystemWatcher>,
+        description: &str,
+    ) {
+        // There's nothing to unregister the first time, which the client may report as an error
+        let _ = self
+            .client
+            .unregister_capability(vec![Unregistration {
+                id: registration_id.to_owned(),
+                method: WATCH_FILES_METHOD.to_owned(),
+            }])
+            .await;
+
+        if watchers.is_empty() {
+            return;
+        }
+
+        let registration = Registration {
+            id: registration_id.to_owned(),
+            method: WATCH_FILES_METHOD.to_owned(),
+            register_options: Some(
+                to_value(DidChangeWatchedFilesRegistrationOptions { watchers }).unwrap(),
+            ),
+        };
+        if let Err(error) = self.client.register_capability(vec![registration]).await {
+            self.client
+                .log_message(
+                    MessageType::ERROR,
+                    format!("could not register to watch {description}: {error}"),
+                )
+                .await;
+        }
+    }
+
+    fn file_watcher(path: &Path) -> Option<FileSystemWatcher> {
+        let file_name = path.file_name()?.to_string_lossy().into_owned();
+        Self::relative_watcher(path.parent()?, file_name)
+    }
+
+    fn relative_watcher(dir: &Path, pattern: String) -> Option<FileSystemWatcher> {
+        Some(FileSystemWatcher {
+            glob_pattern: GlobPattern::Relative(RelativePattern {
+                base_uri: OneOf::Right(Url::from_directory_path(dir).ok()?),
+                pattern,
+            }),
+            kind: None,
+        })
+    }
+
     pub fn handle_file_change_event(&self, workspace: &mut Workspace, event: FileEvent) {
         workspace.sources.invalidate_closed(&event.uri);
+        workspace.resources.write().invalidate(&event.uri);
+        workspace.sources.clear_documents();
+    }
+
+    /// Recompiles the open sources which import, include or load any of the files, directly or
+    /// through other files, since whether those files exist or what they contain may have
+    /// changed. Besides the paths written in the sources, this includes those whose compilation
+    /// loaded the files.
+    pub async fn update_dependents(&self, uris: &[Url]) {
+        // Recompiling is background work, which waits for the next edit while idle
+        if self.idle.is_idle() {
+            self.mark_diagnostics_outdated();
+            return;
+        }
+
+        let workspace = self.workspace.read().await;
+        let changed_uris: HashSet<Url> = uris
+            .iter()
+            .map(|uri| workspace.sources.normalize_uri(uri))
+            .collect();
+        let resources = workspace.resources.read();
+        let loading_ids: HashSet<SourceId> = uris
+            .iter()
+            .flat_map(|uri| resources.get_dependents(uri))
+            .collect();
+        drop(resources);
+        let dependent_ids: Vec<SourceId> = workspace
+            .sources
+            .get_open_ids()
+            .into_iter()
+            .filter(|id| {
+                loading_ids.contains(id)
+                    || !self
+                        .get_all_dependencies(&workspace, &[*id])
+                        .is_disjoint(&changed_uris)
+            })
+            .collect();
+        drop(workspace);
+
+        for id in dependent_ids {
+            let world = self.get_world_with_main(id).await;
+            let config = self.config.read().await;
+            let source = world.get_workspace().sources.get_open_source_by_id(id);
+            self.on_source_changed(&world, &config, source).await;
+        }
+    }
+
+    pub fn is_font_file(uri: &Url) -> bool {
+        let extension = uri.path().rsplit_once('.').map(|(_, extension)| extension);
+        matches!(
+            extension,
+            Some("ttf" | "otf" | "ttc" | "otc" | "TTF" | "OTF" | "TTC" | "OTC")
+        )
     }
 }
@@ -0,0 +1,43 @@
+//! Helpers shared by the tests of several modules
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A directory for a test's files, which is removed when dropped, so that it's cleaned up even
+/// when the test fails
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    /// Creates an empty directory named after the test, which tests running at the same time
+    /// don't share
+    pub fn new(test_name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("typst-lsp-{test_name}-{}", std::process::id()));
+        // Left behind by an earlier run which was killed
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self { path }
+    }
+
+    /// Gets the canonical path of the directory, like the paths of the files Typst loads
+    pub fn path(&self) -> PathBuf {
+        self.path.canonicalize().unwrap()
+    }
+
+    /// Writes the file at the path relative to the directory, creating the directories it's in,
+    /// and returns its canonical path
+    pub fn write(&self, name: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> PathBuf {
+        let path = self.path().join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
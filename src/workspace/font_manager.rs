@@ -1,7 +1,7 @@
 //! Derived from https://github.com/typst/typst/blob/main/cli/src/main.rs
 
 use std::fs::File;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::Context;
 use comemo::Prehashed;
@@ -20,6 +20,25 @@ pub struct FontManager {
     fonts: Vec<FontSlot>,
 }
 
+/// Where a font was found.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontOrigin {
+    Embedded,
+    System,
+    /// Found in one of the font paths set by the user
+    Custom,
+}
+
+impl FontOrigin {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Embedded => "embedded",
+            Self::System => "system",
+            Self::Custom => "custom",
+        }
+    }
+}
+
 impl FontManager {
     /// Create a new, empty font manager `Builder`.
     pub fn builder() -> Builder {
@@ -30,6 +49,14 @@ impl FontManager {
         &self.book
     }
 
+    /// Iterate over the info, origin, and file (if not embedded) of every font.
+    pub fn fonts(&self) -> impl Iterator<Item = (&FontInfo, FontOrigin, Option<&Url>)> {
+        self.fonts.iter().enumerate().filter_map(|(id, slot)| {
+            let info = self.book.info(id)?;
+            Some((info, slot.origin, slot.uri.as_ref()))
+        })
+    }
+
     pub fn font(&self, id: usize, resource_manager: &mut ResourceManager) -> Option<Font> {
         let slot = self.fonts.get(id)?;
         slot.get_font(resource_manager).as_ref().cloned().ok()
@@ -41,6 +68,7 @@ struct FontSlot {
     /// If `None`, the font is embedded
     uri: Option<Url>,
     index: u32,
+    origin: FontOrigin,
     font: OnceCell<anyhow::Result<Font>>,
 }
 
@@ -59,35 +87,74 @@ impl FontSlot {
 }
 
 pub struct Builder {
-    book: FontBook,
+    /// Info of the font in the slot at the same index
+    infos: Vec<FontInfo>,
     fonts: Vec<FontSlot>,
+    /// Lowercase names of the families to put first, in order
+    priority: Vec<String>,
 }
 
 impl Builder {
     fn new() -> Self {
         Self {
-            book: FontBook::new(),
+            infos: Vec::new(),
             fonts: Vec::new(),
+            priority: Vec::new(),
         }
     }
 
     /// Build into a `FontManager`.
     pub fn build(self) -> FontManager {
+        let Self {
+            infos,
+            fonts,
+            priority,
+        } = self;
+        let rank = |info: &FontInfo| {
+            let family = info.family.to_lowercase();
+            priority
+                .iter()
+                .position(|prioritized| *prioritized == family)
+                .unwrap_or(priority.len())
+        };
+
+        // The sort is stable, so fonts keep the order they were found in otherwise
+        let mut ranked: Vec<(FontInfo, FontSlot)> = infos.into_iter().zip(fonts).collect();
+        ranked.sort_by_key(|(info, _)| rank(info));
+
+        let mut book = FontBook::new();
+        let mut fonts = Vec::with_capacity(ranked.len());
+        for (info, slot) in ranked {
+            book.push(info);
+            fonts.push(slot);
+        }
         FontManager {
-            book: Prehashed::new(self.book),
-            fonts: self.fonts,
+            book: Prehashed::new(book),
+            fonts,
         }
     }
 
+    /// Put the fonts of the families first, in the given order, ignoring case. When Typst falls
+    /// back to another font for a character, it picks the first of the fonts which suit it
+    /// equally well.
+    pub fn with_priority(mut self, families: &[String]) -> Self {
+        self.priority = families
+            .iter()
+            .map(|family| family.to_lowercase())
+            .collect();
+        self
+    }
+
     /// Add fonts that are embedded in the binary.
     pub fn with_embedded(mut self) -> Self {
         let mut add = |bytes: &'static [u8]| {
             let buffer = Buffer::from_static(bytes);
             for (i, font) in Font::iter(buffer).enumerate() {
-                self.book.push(font.info().clone());
+                self.infos.push(font.info().clone());
                 self.fonts.push(FontSlot {
                     uri: None,
                     index: i as u32,
+                    origin: FontOrigin::Embedded,
                     font: OnceCell::from(Ok(font)),
                 });
             }
@@ -116,26 +183,38 @@ impl Builder {
         self
     }
 
+    /// Include fonts in the given files and directories.
+    pub fn with_font_paths(mut self, font_paths: &[PathBuf]) -> Self {
+        for path in font_paths {
+            if path.is_dir() {
+                self.search_dir(path, FontOrigin::Custom);
+            } else if path.is_file() {
+                self.search_file(path, FontOrigin::Custom);
+            }
+        }
+        self
+    }
+
     /// Search for fonts in the linux system font directories.
     #[cfg(all(unix, not(target_os = "macos")))]
     fn search_system(&mut self) {
-        self.search_dir("/usr/share/fonts");
-        self.search_dir("/usr/local/share/fonts");
+        self.search_dir("/usr/share/fonts", FontOrigin::System);
+        self.search_dir("/usr/local/share/fonts", FontOrigin::System);
 
         if let Some(dir) = dirs::font_dir() {
-            self.search_dir(dir);
+            self.search_dir(dir, FontOrigin::System);
         }
     }
 
     /// Search for fonts in the macOS system font directories.
     #[cfg(target_os = "macos")]
     fn search_system(&mut self) {
-        self.search_dir("/Library/Fonts");
-        self.search_dir("/Network/Library/Fonts");
-        self.search_dir("/System/Library/Fonts");
+        self.search_dir("/Library/Fonts", FontOrigin::System);
+        self.search_dir("/Network/Library/Fonts", FontOrigin::System);
+        self.search_dir("/System/Library/Fonts", FontOrigin::System);
 
         if let Some(dir) = dirs::font_dir() {
-            self.search_dir(dir);
+            self.search_dir(dir, FontOrigin::System);
         }
     }
 
@@ -144,19 +223,22 @@ impl Builder {
     fn search_system(&mut self) {
         let windir = std::env::var("WINDIR").unwrap_or_else(|_| "C:\\Windows".to_string());
 
-        self.search_dir(Path::new(&windir).join("Fonts"));
+        self.search_dir(Path::new(&windir).join("Fonts"), FontOrigin::System);
 
         if let Some(roaming) = dirs::config_dir() {
-            self.search_dir(roaming.join("Microsoft\\Windows\\Fonts"));
+            self.search_dir(
+                roaming.join("Microsoft\\Windows\\Fonts"),
+                FontOrigin::System,
+            );
         }
 
         if let Some(local) = dirs::cache_dir() {
-            self.search_dir(local.join("Microsoft\\Windows\\Fonts"));
+            self.search_dir(local.join("Microsoft\\Windows\\Fonts"), FontOrigin::System);
         }
     }
 
     /// Search for all fonts in a directory recursively.
-    fn search_dir(&mut self, path: impl AsRef<Path>) {
+    fn search_dir(&mut self, path: impl AsRef<Path>, origin: FontOrigin) {
         for entry in WalkDir::new(path)
             .follow_links(true)
             .sort_by(|a, b| a.file_name().cmp(b.file_name()))
@@ -168,13 +250,13 @@ impl Builder {
                 path.extension().and_then(|s| s.to_str()),
                 Some("ttf" | "otf" | "TTF" | "OTF" | "ttc" | "otc" | "TTC" | "OTC"),
             ) {
-                self.search_file(path);
+                self.search_file(path, origin);
             }
         }
     }
 
     /// Index the fonts in the file at the given path.
-    fn search_file(&mut self, path: impl AsRef<Path>) {
+    fn search_file(&mut self, path: impl AsRef<Path>, origin: FontOrigin) {
         let path = path
             .as_ref()
             .canonicalize()
@@ -182,10 +264,11 @@ impl Builder {
         if let Ok(file) = File::open(&path) {
             if let Ok(mmap) = unsafe { Mmap::map(&file) } {
                 for (i, info) in FontInfo::iter(&mmap).enumerate() {
-                    self.book.push(info);
+                    self.infos.push(info);
                     self.fonts.push(FontSlot {
                         uri: Some(Url::from_file_path(&path).unwrap()),
                         index: i as u32,
+                        origin,
                         font: OnceCell::new(),
                     });
                 }
@@ -1,15 +1,23 @@
 //! Holds types relating to the LSP concept of a "workspace". That is, the directories a user has
 //! open in their editor, the files in them, the files they're currently editing, and so on.
 
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
 use comemo::Prehashed;
 use parking_lot::RwLock;
-use typst::eval::Library;
+use typst::eval::{Dict, Library, Module, Scope, Value};
+
+use crate::config::{Config, FontConfig};
 
 use self::font_manager::FontManager;
 use self::resource_manager::ResourceManager;
 use self::source_manager::SourceManager;
 
 pub mod font_manager;
+pub mod package;
+pub mod project;
 pub mod resource;
 pub mod resource_manager;
 pub mod source;
@@ -17,11 +25,22 @@ pub mod source_manager;
 
 pub struct Workspace {
     pub sources: SourceManager,
-    pub resources: RwLock<ResourceManager>,
+    // Shared with snapshots of the workspace which compilations run on, so that they don't hold
+    // a lock on the workspace
+    pub resources: Arc<RwLock<ResourceManager>>,
 
     // Needed so that `Workspace` can implement Typst's `World` trait
-    pub typst_stdlib: Prehashed<Library>,
-    pub fonts: FontManager,
+    pub typst_stdlib: Arc<Prehashed<Library>>,
+    pub fonts: Arc<FontManager>,
+
+    /// Directory set to resolve absolute paths against, instead of detecting it
+    root_path: Option<PathBuf>,
+    /// Names of files marking the root directory when detecting it
+    root_markers: Vec<String>,
+    /// Globs of paths skipped when loading every file in the workspace
+    index_exclude: Vec<String>,
+    /// Names added to the library by auto-imports, along with the auto-import each comes from
+    auto_imports: HashMap<String, String>,
 }
 
 impl Default for Workspace {
@@ -29,8 +48,94 @@ impl Default for Workspace {
         Self {
             sources: Default::default(),
             resources: Default::default(),
-            typst_stdlib: Prehashed::new(typst_library::build()),
-            fonts: FontManager::builder().with_system().with_embedded().build(),
+            typst_stdlib: Arc::new(Prehashed::new(Self::build_library(&BTreeMap::new()))),
+            fonts: Arc::new(Self::build_fonts(&[], &FontConfig::default())),
+            root_path: None,
+            root_markers: Config::default().root_markers,
+            index_exclude: Vec::new(),
+            auto_imports: HashMap::new(),
+        }
+    }
+}
+
+impl Workspace {
+    /// Builds the standard library with a `sys` module, whose `inputs` dictionary holds the
+    /// inputs. Typst's CLI gained `sys.inputs` after the version typst-lsp is built with, so it's
+    /// provided here under the same name.
+    pub fn build_library(inputs: &BTreeMap<String, String>) -> Library {
+        let inputs: Dict = inputs
+            .iter()
+            .map(|(key, value)| (key.as_str().into(), Value::Str(value.as_str().into())))
+            .collect();
+        let mut sys = Scope::new();
+        sys.define("inputs", Value::Dict(inputs));
+
+        let mut library = typst_library::build();
+        let mut global = library.global.scope().clone();
+        global.define("sys", Value::Module(Module::new("sys").with_scope(sys)));
+        library.global = Module::new(library.global.name().clone()).with_scope(global);
+        library
+    }
+
+    /// Replaces the fonts with the system and, unless turned off, embedded fonts, plus those in
+    /// `font_paths`
+    pub fn reload_fonts(&mut self, font_paths: &[PathBuf], font_config: &FontConfig) {
+        self.fonts = Arc::new(Self::build_fonts(font_paths, font_config));
+        self.sources.clear_documents();
+    }
+
+    /// Sets the root directory, which should already be resolved against the workspace root, or
+    /// the markers used to detect it when it's not set
+    pub fn set_root(&mut self, root_path: Option<PathBuf>, root_markers: Vec<String>) {
+        self.root_path = root_path;
+        self.root_markers = root_markers;
+        self.sources.clear_documents();
+    }
+
+    /// Sets the globs of paths to skip when loading every file in the workspace
+    pub fn set_index_exclude(&mut self, index_exclude: Vec<String>) {
+        self.index_exclude = index_exclude;
+    }
+
+    pub fn get_index_exclude(&self) -> &[String] {
+        &self.index_exclude
+    }
+
+    /// Records the names added to the library by auto-imports, and the auto-import each comes from
+    pub fn set_auto_imports(&mut self, auto_imports: HashMap<String, String>) {
+        self.auto_imports = auto_imports;
+    }
+
+    /// Gets the auto-import which added the name to the library, if any
+    pub fn get_auto_import(&self, name: &str) -> Option<&str> {
+        self.auto_imports.get(name).map(String::as_str)
+    }
+
+    /// Gets the directory which absolute paths in the file are resolved against, like the
+    /// `--root` of Typst's CLI. Unless set, it's the closest directory above the file with one of
+    /// the root markers in it.
+    pub fn get_root(&self, path: &Path) -> Option<PathBuf> {
+        if let Some(root_path) = &self.root_path {
+            return Some(root_path.clone());
+        }
+        path.ancestors()
+            .skip(1)
+            .find(|dir| {
+                self.root_markers
+                    .iter()
+                    .any(|marker| dir.join(marker).is_file())
+            })
+            .map(Path::to_path_buf)
+    }
+
+    fn build_fonts(font_paths: &[PathBuf], font_config: &FontConfig) -> FontManager {
+        let mut builder = FontManager::builder().with_system();
+        if font_config.embedded {
+            builder = builder.with_embedded();
         }
+        builder
+            .with_font_paths(font_paths)
+            .with_priority(&font_config.fallback)
+            .build()
     }
 }
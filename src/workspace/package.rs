@@ -0,0 +1,155 @@
+//! Resolves imports of Typst packages, like `#import "@preview/name:1.0.0"`
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use typst::diag::{FileError, FileResult};
+
+/// A package, as written in an import like `@namespace/name:version`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageSpec {
+    pub namespace: String,
+    pub name: String,
+    pub version: String,
+}
+
+impl PackageSpec {
+    /// Finds a package spec in the path Typst resolved an import to. Typst joins the import
+    /// string to the importing file's directory, so the spec is at the end of the path.
+    pub fn from_import_path(path: &Path) -> Option<Self> {
+        let components: Vec<_> = path
+            .components()
+            .filter_map(|component| match component {
+                Component::Normal(component) => component.to_str(),
+                _ => None,
+            })
+            .collect();
+
+        let [.., namespace, name_and_version] = components.as_slice() else {
+            return None;
+        };
+        let namespace = namespace.strip_prefix('@')?;
+        let (name, version) = name_and_version.split_once(':')?;
+
+        Some(Self {
+            namespace: namespace.to_owned(),
+            name: name.to_owned(),
+            version: version.to_owned(),
+        })
+    }
+
+    /// Gets the path to the package's entrypoint, as set in its manifest
+    pub fn resolve_entrypoint(&self) -> FileResult<PathBuf> {
+        let dir = self.get_dir()?;
+        let manifest_path = dir.join("typst.toml");
+        let manifest =
+            fs::read_to_string(&manifest_path).map_err(|_| FileError::NotFound(manifest_path))?;
+        let entrypoint = Self::parse_entrypoint(&manifest).ok_or(FileError::Other)?;
+        Ok(dir.join(entrypoint))
+    }
+
+    /// Gets the directory the package is stored in. Like Typst, packages in the data directory
+    /// take precedence, which is where `@local` packages live. Packages from `@preview` may
+    /// otherwise be in Typst's package cache, if they were downloaded before, e.g. by the Typst
+    /// CLI.
+    fn get_dir(&self) -> FileResult<PathBuf> {
+        let subdir = Path::new("typst/packages")
+            .join(&self.namespace)
+            .join(&self.name)
+            .join(&self.version);
+
+        let data_dir = dirs::data_dir().map(|data_dir| data_dir.join(&subdir));
+        let cache_dir = match self.namespace.as_str() {
+            "preview" => dirs::cache_dir().map(|cache_dir| cache_dir.join(&subdir)),
+            _ => None,
+        };
+
+        let candidates = [data_dir, cache_dir];
+        if let Some(dir) = candidates.iter().flatten().find(|dir| dir.is_dir()) {
+            return Ok(dir.clone());
+        }
+        match candidates.into_iter().flatten().last() {
+            Some(expected_dir) => Err(FileError::NotFound(expected_dir)),
+            None => Err(FileError::Other),
+        }
+    }
+
+    /// Reads the `entrypoint` key from the `[package]` table of a `typst.toml` manifest
+    fn parse_entrypoint(manifest: &str) -> Option<String> {
+        let manifest: toml::Table = manifest.parse().ok()?;
+        let entrypoint = manifest.get("package")?.get("entrypoint")?;
+        entrypoint.as_str().map(str::to_owned)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn package_spec_from_import_path() {
+        let spec = PackageSpec::from_import_path(Path::new("/doc/@preview/example:0.1.0"));
+        assert_eq!(
+            spec,
+            Some(PackageSpec {
+                namespace: "preview".to_owned(),
+                name: "example".to_owned(),
+                version: "0.1.0".to_owned(),
+            })
+        );
+    }
+
+    #[test]
+    fn file_import_path_is_not_package() {
+        assert_eq!(
+            PackageSpec::from_import_path(Path::new("/doc/chapter.typ")),
+            None
+        );
+        assert_eq!(
+            PackageSpec::from_import_path(Path::new("/doc/preview/example:0.1.0")),
+            None
+        );
+        assert_eq!(
+            PackageSpec::from_import_path(Path::new("/doc/@preview/example")),
+            None
+        );
+    }
+
+    #[test]
+    fn entrypoint_is_read_from_package_table() {
+        let manifest = r#"
+            [package]
+            name = "example"
+            version = "0.1.0"
+            entrypoint = 'src/lib.typ'
+
+            [tool.other]
+            entrypoint = "other.typ"
+        "#;
+        assert_eq!(
+            PackageSpec::parse_entrypoint(manifest).as_deref(),
+            Some("src/lib.typ")
+        );
+    }
+
+    #[test]
+    fn entrypoint_outside_package_table_is_ignored() {
+        let manifest = r#"
+            # entrypoint = "commented.typ"
+            [package]
+            name = "example"
+
+            [tool.other]
+            entrypoint = "other.typ"
+        "#;
+        assert_eq!(PackageSpec::parse_entrypoint(manifest), None);
+    }
+
+    #[test]
+    fn invalid_manifest_has_no_entrypoint() {
+        assert_eq!(
+            PackageSpec::parse_entrypoint("[package\nentrypoint = \"lib.typ\""),
+            None
+        );
+    }
+}
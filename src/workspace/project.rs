@@ -0,0 +1,125 @@
+//! Reads settings for typst-lsp from a project's `typst.toml`, so they can be shared with the
+//! repository rather than set in each collaborator's editor
+
+use std::fs;
+use std::path::Path;
+
+use serde_json::{Map, Value as JsonValue};
+use toml::Value as TomlValue;
+
+/// Path of the table of `typst.toml` holding the settings, `[tool.typst-lsp]`, which has the same
+/// names as in the editor, like `exportPdf = "onType"`
+const SETTINGS_TABLE: [&str; 2] = ["tool", "typst-lsp"];
+
+/// Reads the settings from the `typst.toml` in the directory, if there is one. A manifest which
+/// isn't valid TOML has no settings.
+pub fn read_project_settings(dir: &Path) -> Map<String, JsonValue> {
+    fs::read_to_string(dir.join("typst.toml"))
+        .map(|manifest| parse_project_settings(&manifest))
+        .unwrap_or_default()
+}
+
+fn parse_project_settings(manifest: &str) -> Map<String, JsonValue> {
+    let Ok(manifest) = manifest.parse::<TomlValue>() else {
+        return Map::new();
+    };
+    let settings = SETTINGS_TABLE
+        .iter()
+        .try_fold(&manifest, |table, &key| table.get(key));
+    match settings.cloned().map(toml_to_json) {
+        Some(JsonValue::Object(settings)) => settings,
+        _ => Map::new(),
+    }
+}
+
+/// Converts a TOML value to the JSON the editor would send for it. Dates and times become strings,
+/// like they're written.
+fn toml_to_json(value: TomlValue) -> JsonValue {
+    match value {
+        TomlValue::String(string) => JsonValue::String(string),
+        TomlValue::Integer(integer) => JsonValue::from(integer),
+        TomlValue::Float(float) => JsonValue::from(float),
+        TomlValue::Boolean(boolean) => JsonValue::Bool(boolean),
+        TomlValue::Datetime(datetime) => JsonValue::String(datetime.to_string()),
+        TomlValue::Array(array) => JsonValue::Array(array.into_iter().map(toml_to_json).collect()),
+        TomlValue::Table(table) => JsonValue::Object(
+            table
+                .into_iter()
+                .map(|(key, value)| (key, toml_to_json(value)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn settings_from_tool_table() {
+        let manifest = r#"
+[package]
+name = "thesis"
+entrypoint = "main.typ"
+
+[tool.typst-lsp]
+exportPdf = "onType" # export while typing
+pngDpi = 300
+inlayHints = false
+fontPaths = ["fonts", 'assets/fonts']
+"#;
+        let settings = parse_project_settings(manifest);
+
+        assert_eq!(settings.get("exportPdf"), Some(&json!("onType")));
+        assert_eq!(settings.get("pngDpi"), Some(&json!(300)));
+        assert_eq!(settings.get("inlayHints"), Some(&json!(false)));
+        assert_eq!(
+            settings.get("fontPaths"),
+            Some(&json!(["fonts", "assets/fonts"]))
+        );
+        assert_eq!(settings.get("entrypoint"), None);
+    }
+
+    #[test]
+    fn settings_over_multiple_lines() {
+        let manifest = r#"
+[ tool."typst-lsp" ]
+exportFormats = [
+    "pdf",
+    "png", # for the website
+]
+diagnosticSeverity = { unused = "off", deprecated = "hint" }
+"#;
+        let settings = parse_project_settings(manifest);
+
+        assert_eq!(settings.get("exportFormats"), Some(&json!(["pdf", "png"])));
+        assert_eq!(
+            settings.get("diagnosticSeverity"),
+            Some(&json!({ "unused": "off", "deprecated": "hint" }))
+        );
+    }
+
+    #[test]
+    fn settings_from_dotted_keys() {
+        let manifest = r#"
+[tool]
+typst-lsp.pngDpi = 300
+"#;
+        let settings = parse_project_settings(manifest);
+
+        assert_eq!(settings.get("pngDpi"), Some(&json!(300)));
+    }
+
+    #[test]
+    fn malformed_manifest_has_no_settings() {
+        let manifest = r#"
+[tool.typst-lsp]
+exportPdf = "onType
+pngDpi = 300
+"#;
+        assert!(parse_project_settings(manifest).is_empty());
+        assert!(parse_project_settings("[tool]\ntypst-lsp = 1").is_empty());
+    }
+}
@@ -1,20 +1,39 @@
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::fs;
 
 use tower_lsp::lsp_types::Url;
 use typst::diag::{FileError, FileResult};
 
 use super::resource::Resource;
+use super::source_manager::{SourceId, SourceManager};
 
 #[derive(Debug, Default)]
 pub struct ResourceManager {
     resources: HashMap<Url, Resource>,
+    /// The main sources of the compilations which loaded each resource. These are kept when the
+    /// resource is invalidated, so the sources can be compiled again.
+    dependents: HashMap<Url, HashSet<SourceId>>,
+    /// Size in bytes above which files aren't loaded, to not run out of memory
+    max_size: Option<u64>,
 }
 
 impl ResourceManager {
+    pub fn set_max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+    /// Gets the resource, reading it from disk if it isn't cached. Typst's files come with the URI
+    /// of their canonical path, so a file reached through a symbolic link is the same resource as
+    /// its target.
     pub fn get_or_insert_resource(&mut self, uri: Url) -> FileResult<&Resource> {
+        let max_size = self.max_size;
         match self.resources.entry(uri.clone()) {
             Entry::Vacant(entry) => {
+                // Typst has no error for files which are too large
+                if max_size.map_or(false, |max_size| Self::get_file_size(&uri) > max_size) {
+                    return Err(FileError::Other);
+                }
                 // TODO: ideally, we do this through the LSP client instead, and watch the file to
                 // avoid caching old data
                 let resource = Resource::read_file(&uri).map_err(|_| FileError::Other)?;
@@ -23,4 +42,53 @@ impl ResourceManager {
             Entry::Occupied(entry) => Ok(entry.into_mut()),
         }
     }
+
+    /// Records that compiling the source loaded the resource
+    pub fn add_dependent(&mut self, uri: &Url, source_id: SourceId) {
+        if let Some(dependents) = self.dependents.get_mut(uri) {
+            dependents.insert(source_id);
+        } else {
+            self.dependents
+                .insert(uri.clone(), HashSet::from([source_id]));
+        }
+    }
+
+    /// Gets the main sources of the compilations which loaded the resource, which the client may
+    /// name differently from Typst
+    pub fn get_dependents(&self, uri: &Url) -> Vec<SourceId> {
+        self.dependents
+            .get(&Self::get_key(uri))
+            .map_or_else(Vec::new, |dependents| dependents.iter().copied().collect())
+    }
+
+    /// Forgets that compiling the source loaded any resource, once its id is freed for another
+    /// source to use
+    pub fn remove_dependent(&mut self, source_id: SourceId) {
+        self.dependents.retain(|_, dependents| {
+            dependents.remove(&source_id);
+            !dependents.is_empty()
+        });
+    }
+
+    fn get_file_size(uri: &Url) -> u64 {
+        uri.to_file_path()
+            .ok()
+            .and_then(|path| fs::metadata(path).ok())
+            .map_or(0, |metadata| metadata.len())
+    }
+
+    pub fn get_uris(&self) -> Vec<Url> {
+        self.resources.keys().cloned().collect()
+    }
+
+    /// Forget the cached contents of the resource, so it's read again next time it's needed
+    pub fn invalidate(&mut self, uri: &Url) {
+        self.resources.remove(&Self::get_key(uri));
+    }
+
+    /// Gets the URI the resource is stored under, that of its canonical path, or the URI itself
+    /// for files which no longer exist
+    fn get_key(uri: &Url) -> Url {
+        SourceManager::canonicalize_uri(uri).unwrap_or_else(|_| uri.clone())
+    }
 }
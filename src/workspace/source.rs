@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use tower_lsp::lsp_types::Url;
 
 use crate::lsp_typst_boundary::{lsp_to_typst, LspRange, TypstSource};
@@ -8,23 +10,31 @@ use super::source_manager::SourceId;
 #[derive(Debug)]
 pub struct Source {
     inner: TypstSource,
+    /// The URI the client knows the source by, which can't always be derived from its path, e.g.
+    /// for `untitled:` documents
+    uri: Url,
 }
 
 impl Source {
-    pub fn new(id: SourceId, uri: &Url, text: String) -> Self {
-        let typst_path = lsp_to_typst::uri_to_path(uri);
-
+    /// Creates a source at the path, which Typst resolves the source's imports relative to
+    pub fn new(id: SourceId, uri: &Url, path: &Path, text: String) -> Self {
         Self {
-            inner: TypstSource::new(id.into(), &typst_path, text),
+            inner: TypstSource::new(id.into(), path, text),
+            uri: uri.clone(),
         }
     }
 
     pub fn new_detached() -> Self {
         Self {
             inner: TypstSource::detached(""),
+            uri: Url::parse("untitled:detached").expect("URI should be valid"),
         }
     }
 
+    pub fn uri(&self) -> &Url {
+        &self.uri
+    }
+
     pub fn edit(&mut self, replace: &LspRange, with: &str) {
         let typst_replace = lsp_to_typst::range(replace, &self.inner);
         self.inner.edit(typst_replace, with);
@@ -1,18 +1,36 @@
 use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 use std::{fmt, fs, io, mem};
 
 use elsa::sync::{FrozenMap, FrozenVec};
+use ignore::overrides::{Override, OverrideBuilder};
+use ignore::WalkBuilder;
 use once_cell::sync::OnceCell;
+use parking_lot::Mutex;
 use tower_lsp::lsp_types::Url;
 use typst::diag::{FileError, FileResult};
+use typst::doc::Document;
 
-use crate::lsp_typst_boundary::{lsp_to_typst, TypstSourceId};
+use crate::lsp_typst_boundary::{typst_to_lsp, TypstSourceId};
 
 use super::source::Source;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct SourceId(u16);
 
+impl SourceId {
+    /// Gets the id of the source at the index, or `None` if it's past the last id Typst can
+    /// represent. The largest `u16` is Typst's id for detached sources, so it's never used.
+    fn from_index(index: usize) -> Option<Self> {
+        u16::try_from(index)
+            .ok()
+            .filter(|&index| index != TypstSourceId::detached().into_u16())
+            .map(Self)
+    }
+}
+
 impl From<TypstSourceId> for SourceId {
     fn from(typst_id: TypstSourceId) -> Self {
         Self(typst_id.into_u16())
@@ -28,21 +46,27 @@ impl From<SourceId> for TypstSourceId {
 #[derive(Debug)]
 enum InnerSource {
     Open(Source),
-    Closed(OnceCell<Source>),
+    /// A source which isn't open in the LSP client, along with when it was last used. The source
+    /// is read from disk when it's first used, and again if it was evicted since.
+    Closed(OnceCell<Source>, Mutex<Instant>),
 }
 
 impl InnerSource {
+    fn closed(source: Source) -> Self {
+        Self::Closed(OnceCell::with_value(source), Mutex::new(Instant::now()))
+    }
+
     pub fn get_source(&self) -> Option<&Source> {
         match self {
             Self::Open(source) => Some(source),
-            Self::Closed(cell) => cell.get(),
+            Self::Closed(cell, _) => cell.get(),
         }
     }
 
     pub fn get_mut_source(&mut self) -> Option<&mut Source> {
         match self {
             Self::Open(source) => Some(source),
-            Self::Closed(cell) => cell.get_mut(),
+            Self::Closed(cell, _) => cell.get_mut(),
         }
     }
 }
@@ -51,15 +75,106 @@ impl InnerSource {
 pub struct SourceManager {
     ids: FrozenMap<Url, SourceId>,
     sources: FrozenVec<Box<InnerSource>>,
+    /// Directory that documents which aren't files, like `untitled:` ones, are treated as being
+    /// in, so that their imports resolve relative to it
+    untitled_dir: Option<PathBuf>,
+    /// The last document each source compiled to, until anything it may depend on changes
+    documents: Mutex<HashMap<SourceId, Document>>,
+    /// Ids of forgotten sources, which are given to new sources before adding more
+    free_ids: Mutex<Vec<SourceId>>,
+    /// The URI each source is stored under, by the URIs it was added by, so that looking a source
+    /// up doesn't have to find the canonical path again
+    keys: Mutex<HashMap<Url, Url>>,
 }
 
 impl SourceManager {
+    pub fn set_untitled_dir(&mut self, untitled_dir: Option<PathBuf>) {
+        self.untitled_dir = untitled_dir;
+    }
+
+    /// Gets the path Typst knows the document by. Documents which aren't files get a path in the
+    /// untitled directory named after their URI, which doesn't exist on disk.
+    fn get_path(&self, uri: &Url) -> PathBuf {
+        if let Ok(path) = uri.to_file_path() {
+            return path;
+        }
+        // URIs like `untitled:Untitled-1` have no segments, so split the path by hand
+        let name = uri
+            .path()
+            .rsplit('/')
+            .next()
+            .filter(|name| !name.is_empty())
+            .unwrap_or("untitled");
+        match &self.untitled_dir {
+            Some(untitled_dir) => untitled_dir.join(name),
+            None => PathBuf::from(name),
+        }
+    }
+
+    /// Gets the URIs of all sources, as the LSP client knows the open ones
     pub fn get_uris(&self) -> Vec<Url> {
-        self.ids.keys_cloned()
+        self.ids
+            .keys_cloned()
+            .into_iter()
+            .map(|key| {
+                self.ids
+                    .get_copy(&key)
+                    .and_then(|id| self.get_inner_source(id).get_source())
+                    .map_or(key, |source| source.uri().clone())
+            })
+            .collect()
+    }
+
+    /// Gets the ids of the sources which are currently open in the LSP client
+    pub fn get_open_ids(&self) -> Vec<SourceId> {
+        (0..self.sources.len())
+            .filter(|&index| matches!(self.sources.get(index), Some(InnerSource::Open(_))))
+            .filter_map(SourceId::from_index)
+            .collect()
+    }
+
+    /// Whether the source is in memory, which closed sources aren't until they're used, or after
+    /// being evicted
+    pub fn is_loaded(&self, id: SourceId) -> bool {
+        self.get_inner_source(id).get_source().is_some()
     }
 
     pub fn get_id_by_uri(&self, uri: &Url) -> Option<SourceId> {
-        self.ids.get_copy(uri)
+        self.ids.get_copy(&self.normalize_uri(uri))
+    }
+
+    /// Gets the URI the source added by the URI is stored under, or the URI itself if no source
+    /// was added by it. This doesn't read the file system, unlike finding the canonical path.
+    pub fn normalize_uri(&self, uri: &Url) -> Url {
+        self.keys
+            .lock()
+            .get(uri)
+            .cloned()
+            .unwrap_or_else(|| uri.clone())
+    }
+
+    /// Gets the URI to store a source added by the URI under, which is remembered, so that the
+    /// canonical path is only found the first time. The client and Typst can name the same file
+    /// with different URIs, like with `..` in the path from an import, through a symbolic link, or
+    /// with a different drive letter case on Windows. Keying sources by the canonical path makes
+    /// an import find the editor's buffer for a file, rather than reading its saved version from
+    /// disk.
+    fn get_key(&self, uri: &Url) -> io::Result<Url> {
+        if let Some(key) = self.keys.lock().get(uri) {
+            return Ok(key.clone());
+        }
+        let key = Self::canonicalize_uri(uri)?;
+        self.keys.lock().insert(uri.clone(), key.clone());
+        Ok(key)
+    }
+
+    /// Gets the URI of the file's canonical path, which fails if the file doesn't exist. URIs
+    /// which don't name files, like `untitled:` ones, are left as they are.
+    pub fn canonicalize_uri(uri: &Url) -> io::Result<Url> {
+        match uri.to_file_path() {
+            Ok(path) => typst_to_lsp::path_to_uri(&path),
+            Err(()) => Ok(uri.clone()),
+        }
     }
 
     fn get_inner_source(&self, id: SourceId) -> &InnerSource {
@@ -67,6 +182,8 @@ impl SourceManager {
     }
 
     fn get_mut_inner_source(&mut self, id: SourceId) -> &mut InnerSource {
+        // Any document may import the source, so none can be reused once it changes
+        self.clear_documents();
         self.sources.as_mut().get_mut(id.0 as usize).unwrap()
     }
 
@@ -77,31 +194,71 @@ impl SourceManager {
             .expect("open source should exist")
     }
 
+    /// Gets the source if it's loaded, which closed sources aren't until they're used, or after
+    /// being evicted
+    pub fn get_source_by_id(&self, id: SourceId) -> Option<&Source> {
+        self.sources
+            .get(id.0 as usize)
+            .and_then(InnerSource::get_source)
+    }
+
+    /// Gets the URI the source is stored under, whether or not it's loaded
+    pub fn get_uri_by_id(&self, id: SourceId) -> Option<Url> {
+        self.ids
+            .keys_cloned()
+            .into_iter()
+            .find(|uri| self.ids.get_copy(uri) == Some(id))
+    }
+
+    /// Gets all sources which are currently loaded, whether open or closed
+    pub fn get_sources(&self) -> Vec<&Source> {
+        (0..self.sources.len())
+            .filter_map(|index| self.sources.get(index))
+            .filter_map(InnerSource::get_source)
+            .collect()
+    }
+
     pub fn get_mut_open_source_by_id(&mut self, id: SourceId) -> &mut Source {
         self.get_mut_inner_source(id)
             .get_mut_source()
             .expect("open source should exist")
     }
 
-    fn get_next_id(&self) -> SourceId {
-        SourceId(self.sources.len() as u16)
+    /// Gets the id the next source will have, or `None` if there are already as many sources as
+    /// there are ids
+    fn get_next_id(&self) -> Option<SourceId> {
+        SourceId::from_index(self.sources.len())
     }
 
-    pub fn insert_open(&mut self, uri: &Url, text: String) {
+    pub fn insert_open(&mut self, uri: &Url, text: String) -> FileResult<()> {
+        self.clear_documents();
         let next_id = self.get_next_id();
+        let path = self.get_path(uri);
 
-        match self.ids.as_mut().entry(uri.clone()) {
+        // Files which aren't saved yet are stored under the URI the client opened them by
+        let key = self.get_key(uri).unwrap_or_else(|_| uri.clone());
+        match self.ids.as_mut().entry(key) {
             Entry::Occupied(entry) => {
                 let existing_id = *entry.get();
-                let source = Source::new(existing_id, uri, text);
+                let source = Source::new(existing_id, uri, &path, text);
                 *self.get_mut_inner_source(existing_id) = InnerSource::Open(source);
             }
-            Entry::Vacant(entry) => {
-                entry.insert(next_id);
-                let source = Source::new(next_id, uri, text);
-                self.sources.push(Box::new(InnerSource::Open(source)));
-            }
+            Entry::Vacant(entry) => match self.free_ids.get_mut().pop() {
+                Some(free_id) => {
+                    entry.insert(free_id);
+                    let source = Source::new(free_id, uri, &path, text);
+                    *self.get_mut_inner_source(free_id) = InnerSource::Open(source);
+                }
+                None => {
+                    let next_id = next_id.ok_or(FileError::Other)?;
+                    entry.insert(next_id);
+                    let source = Source::new(next_id, uri, &path, text);
+                    self.sources.push(Box::new(InnerSource::Open(source)));
+                }
+            },
         }
+
+        Ok(())
     }
 
     pub fn close(&mut self, uri: &Url) {
@@ -109,50 +266,208 @@ impl SourceManager {
             let inner_source = self.get_mut_inner_source(id);
             if let InnerSource::Open(source) = inner_source {
                 let source = mem::replace(source, Source::new_detached());
-                *inner_source = InnerSource::Closed(OnceCell::with_value(source));
+                *inner_source = InnerSource::closed(source);
             }
         }
     }
 
+    /// Drops a closed source entirely, unlike `close`, which keeps it to be reused. For transient
+    /// documents, like generated previews, which would otherwise each hold on to an id for the
+    /// rest of the session. Using the URI again gives it a fresh source, which may reuse the id.
+    /// Worlds hold the workspace for as long as they compile, so no compilation sees the id change
+    /// hands; cached compilations check the sources they used, so they don't reuse stale ones.
+    /// Returns the freed id, so that what else is kept for it can be dropped too.
+    pub fn forget(&mut self, uri: &Url) -> Option<SourceId> {
+        let key = self.normalize_uri(uri);
+        let id = self.ids.as_mut().remove(&key)?;
+        self.keys
+            .get_mut()
+            .retain(|_, source_key| *source_key != key);
+        // This drops the document it compiled to, along with those of sources importing it
+        *self.get_mut_inner_source(id) =
+            InnerSource::Closed(OnceCell::new(), Mutex::new(Instant::now()));
+        self.free_ids.get_mut().push(id);
+        Some(id)
+    }
+
+    /// Drops the closed source's contents, after its file changed on disk. The client may name
+    /// files it never opened differently from Typst, so their canonical path is looked up.
     pub fn invalidate_closed(&mut self, uri: &Url) {
-        if let Some(id) = self.get_id_by_uri(uri) {
+        let id = self.get_id_by_uri(uri).or_else(|| {
+            let key = Self::canonicalize_uri(uri).ok()?;
+            self.ids.get_copy(&key)
+        });
+        if let Some(id) = id {
             let inner_source = self.get_mut_inner_source(id);
-            if let InnerSource::Closed(cell) = inner_source {
+            if let InnerSource::Closed(cell, _) = inner_source {
                 cell.take();
             }
         }
     }
 
+    /// Drops closed sources which haven't been used for at least the timeout, to be read from disk
+    /// again when next used. Their ids stay valid.
+    pub fn evict_idle(&mut self, timeout: Duration) {
+        // Evicted sources are read from disk again, which may have changed without notice
+        self.clear_documents();
+        for inner_source in self.sources.as_mut().iter_mut() {
+            if let InnerSource::Closed(cell, last_used) = inner_source.as_mut() {
+                if last_used.get_mut().elapsed() >= timeout {
+                    cell.take();
+                }
+            }
+        }
+    }
+
+    /// Gets the document the source last compiled to, if nothing changed since
+    pub fn get_document(&self, id: SourceId) -> Option<Document> {
+        self.documents.lock().get(&id).cloned()
+    }
+
+    /// Remembers the document the source compiled to, for exports to reuse
+    pub fn set_document(&self, id: SourceId, document: Document) {
+        self.documents.lock().insert(id, document);
+    }
+
+    /// Forgets the compiled documents, after a change to something they may depend on, like a
+    /// source, a file they load, or the fonts
+    pub fn clear_documents(&self) {
+        self.documents.lock().clear();
+    }
+
     fn read_source_from_file(id: SourceId, uri: &Url) -> FileResult<Source> {
-        let path = lsp_to_typst::uri_to_path(uri);
-        let text = fs::read_to_string(&path).map_err(|error| match error.kind() {
-            io::ErrorKind::NotFound => FileError::NotFound(path),
+        // Only `file:` URIs name files on disk. Typst has no error for other URIs, and reporting
+        // them as not found would show a meaningless empty path.
+        let path = uri.to_file_path().map_err(|_| FileError::Other)?;
+        let bytes = fs::read(&path).map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => FileError::NotFound(path.clone()),
             io::ErrorKind::PermissionDenied => FileError::AccessDenied,
+            // The error kind for directories isn't stable yet
+            _ if path.is_dir() => FileError::IsDirectory,
             _ => FileError::Other,
         })?;
-        Ok(Source::new(id, uri, text))
+        let text = Self::decode_text(bytes).ok_or(FileError::InvalidUtf8)?;
+        Ok(Source::new(id, uri, &path, text))
+    }
+
+    /// Decodes a file written in UTF-8 or, if it starts with a byte order mark, UTF-16. Files
+    /// which aren't valid UTF-8 and have no byte order mark are assumed to be Latin-1, which any
+    /// bytes are valid in.
+    fn decode_text(bytes: Vec<u8>) -> Option<String> {
+        match bytes.as_slice() {
+            [0xEF, 0xBB, 0xBF, rest @ ..] => String::from_utf8(rest.to_vec()).ok(),
+            [0xFF, 0xFE, rest @ ..] => Self::decode_utf16(rest, u16::from_le_bytes),
+            [0xFE, 0xFF, rest @ ..] => Self::decode_utf16(rest, u16::from_be_bytes),
+            _ => Some(String::from_utf8(bytes).unwrap_or_else(|error| {
+                error.as_bytes().iter().map(|&byte| byte as char).collect()
+            })),
+        }
+    }
+
+    fn decode_utf16(bytes: &[u8], from_bytes: fn([u8; 2]) -> u16) -> Option<String> {
+        if bytes.len() % 2 != 0 {
+            return None;
+        }
+        let units: Vec<u16> = bytes
+            .chunks_exact(2)
+            .map(|unit| from_bytes([unit[0], unit[1]]))
+            .collect();
+        String::from_utf16(&units).ok()
     }
 
     pub fn cache(&self, uri: Url) -> FileResult<SourceId> {
+        let uri = match self.get_id_by_uri(&uri) {
+            Some(_) => self.normalize_uri(&uri),
+            None => self.get_key(&uri).map_err(|_| {
+                uri.to_file_path()
+                    .map_or(FileError::Other, FileError::NotFound)
+            })?,
+        };
         let next_id = self.get_next_id();
 
-        let id = self.ids.get_copy_or_insert(uri.clone(), next_id);
+        let id = match self.get_id_by_uri(&uri) {
+            Some(id) => id,
+            None => {
+                let free_id = self.free_ids.lock().pop();
+                match free_id {
+                    Some(free_id) => {
+                        let id = self.ids.get_copy_or_insert(uri.clone(), free_id);
+                        if id == free_id {
+                            // A document compiled since the id was freed isn't this source's
+                            self.documents.lock().remove(&free_id);
+                        } else {
+                            // Another thread added the source first
+                            self.free_ids.lock().push(free_id);
+                        }
+                        id
+                    }
+                    None => {
+                        let next_id = next_id.ok_or(FileError::Other)?;
+                        self.ids.get_copy_or_insert(uri.clone(), next_id)
+                    }
+                }
+            }
+        };
 
         // TODO: next_id could expire before the new source is inserted; lock across everything, or
         // use a more appropriate structure which handles that automatically
-        if id == next_id {
+        if Some(id) == next_id {
             let source = Self::read_source_from_file(id, &uri)?;
-            self.sources
-                .push(Box::new(InnerSource::Closed(OnceCell::with_value(source))));
+            self.sources.push(Box::new(InnerSource::closed(source)));
         } else {
             let inner_source = self.get_inner_source(id);
-            if let InnerSource::Closed(cell) = inner_source {
+            if let InnerSource::Closed(cell, last_used) = inner_source {
+                *last_used.lock() = Instant::now();
                 cell.get_or_try_init(|| Self::read_source_from_file(id, &uri))?;
             }
         }
 
         Ok(id)
     }
+
+    /// Loads all Typst files in the directory and its subdirectories, so that they can be found
+    /// by workspace-wide queries. Hidden files, files ignored by `.gitignore` or `.ignore` files,
+    /// and files matching the `exclude` globs are skipped, to keep dependencies and build outputs
+    /// out of the queries.
+    pub fn cache_dir(&self, dir: &Path, exclude: &[String]) {
+        for path in Self::find_typst_files(dir, exclude) {
+            // Files which can't be read just won't be included in queries
+            let _ = self.cache_path(&path);
+        }
+    }
+
+    /// Finds the Typst files `cache_dir` loads, without loading them
+    pub fn find_typst_files(dir: &Path, exclude: &[String]) -> Vec<PathBuf> {
+        WalkBuilder::new(dir)
+            .overrides(Self::build_exclude_overrides(dir, exclude))
+            // Respect `.gitignore` files even in directories which aren't Git repositories
+            .require_git(false)
+            .build()
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.into_path())
+            .filter(|path| {
+                path.extension()
+                    .map_or(false, |extension| extension == "typ")
+            })
+            .collect()
+    }
+
+    /// Loads the file at the path, like `cache` does for its URI
+    pub fn cache_path(&self, path: &Path) -> FileResult<SourceId> {
+        let uri =
+            typst_to_lsp::path_to_uri(path).map_err(|_| FileError::NotFound(path.to_path_buf()))?;
+        self.cache(uri)
+    }
+
+    /// Turns the exclude globs into overrides, in which globs starting with `!` exclude paths.
+    /// Invalid globs are skipped.
+    fn build_exclude_overrides(dir: &Path, exclude: &[String]) -> Override {
+        let mut builder = OverrideBuilder::new(dir);
+        for glob in exclude {
+            let _ = builder.add(&format!("!{glob}"));
+        }
+        builder.build().unwrap_or_else(|_| Override::empty())
+    }
 }
 
 impl fmt::Debug for SourceManager {
@@ -160,3 +475,207 @@ impl fmt::Debug for SourceManager {
         f.debug_struct("SourceManager").finish_non_exhaustive()
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::test_utils::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn source_id_from_last_index() {
+        let last_index = u16::MAX as usize - 1;
+        let id = SourceId::from_index(last_index).expect("last index should have an id");
+
+        let typst_id: TypstSourceId = id.into();
+        assert_eq!(typst_id.into_u16(), u16::MAX - 1);
+        assert_eq!(SourceId::from(typst_id), id);
+    }
+
+    #[test]
+    fn source_id_past_last_index() {
+        // Taken by detached sources
+        assert_eq!(SourceId::from_index(u16::MAX as usize), None);
+        assert_eq!(SourceId::from_index(u16::MAX as usize + 1), None);
+        assert_eq!(SourceId::from_index(usize::MAX), None);
+    }
+
+    #[test]
+    fn next_source_id() {
+        let manager = SourceManager::default();
+        assert_eq!(manager.get_next_id(), SourceId::from_index(0));
+    }
+
+    #[test]
+    fn utf8_is_decoded() {
+        let text = "= Überblick 🎉";
+        assert_eq!(
+            SourceManager::decode_text(text.as_bytes().to_vec()).as_deref(),
+            Some(text)
+        );
+    }
+
+    #[test]
+    fn utf8_byte_order_mark_is_dropped() {
+        let bytes = [&[0xEF, 0xBB, 0xBF], "= Title".as_bytes()].concat();
+        assert_eq!(
+            SourceManager::decode_text(bytes).as_deref(),
+            Some("= Title")
+        );
+        // The mark promises UTF-8, so invalid UTF-8 after it isn't guessed at
+        assert_eq!(
+            SourceManager::decode_text(vec![0xEF, 0xBB, 0xBF, 0xFF]),
+            None
+        );
+    }
+
+    #[test]
+    fn utf16_is_decoded_by_byte_order_mark() {
+        let text = "= Titel ü 🎉";
+        let little_endian: Vec<u8> = [0xFF, 0xFE]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect();
+        let big_endian: Vec<u8> = [0xFE, 0xFF]
+            .into_iter()
+            .chain(text.encode_utf16().flat_map(u16::to_be_bytes))
+            .collect();
+
+        assert_eq!(
+            SourceManager::decode_text(little_endian).as_deref(),
+            Some(text)
+        );
+        assert_eq!(
+            SourceManager::decode_text(big_endian).as_deref(),
+            Some(text)
+        );
+    }
+
+    #[test]
+    fn invalid_utf16_is_rejected() {
+        // An odd number of bytes
+        assert_eq!(SourceManager::decode_text(vec![0xFF, 0xFE, 0x3D]), None);
+        // A lone surrogate
+        assert_eq!(
+            SourceManager::decode_text(vec![0xFF, 0xFE, 0x00, 0xD8]),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_utf8_is_decoded_as_latin1() {
+        // `café` written in Latin-1
+        let bytes = vec![0x63, 0x61, 0x66, 0xE9];
+        assert_eq!(SourceManager::decode_text(bytes).as_deref(), Some("café"));
+    }
+
+    #[test]
+    fn cache_prefers_open_buffer() {
+        let dir = TempDir::new("open-buffer");
+        dir.write("chapters/chapter.typ", "");
+        let path = dir.write("imported.typ", "saved");
+
+        // The client names the file directly, while an import goes through `..`
+        let mut manager = SourceManager::default();
+        let open_uri = Url::from_file_path(&path).unwrap();
+        manager
+            .insert_open(&open_uri, "unsaved".to_owned())
+            .unwrap();
+        let import_uri = Url::from_file_path(dir.path().join("chapters/../imported.typ")).unwrap();
+        let id = manager.cache(import_uri).unwrap();
+
+        let source = manager.get_open_source_by_id(id);
+        assert_eq!(source.as_ref().text(), "unsaved");
+        assert_eq!(source.uri(), &open_uri);
+        assert_eq!(manager.get_uris(), vec![open_uri]);
+    }
+
+    #[test]
+    fn forgotten_source_id_is_reused() {
+        let mut manager = SourceManager::default();
+        let preview_uri = Url::parse("untitled:preview-1").unwrap();
+        manager
+            .insert_open(&preview_uri, "first".to_owned())
+            .unwrap();
+        let id = manager.get_id_by_uri(&preview_uri).unwrap();
+
+        manager.forget(&preview_uri);
+        assert_eq!(manager.get_id_by_uri(&preview_uri), None);
+        assert!(manager.get_uris().is_empty());
+        assert!(!manager.is_loaded(id));
+
+        let next_preview_uri = Url::parse("untitled:preview-2").unwrap();
+        manager
+            .insert_open(&next_preview_uri, "second".to_owned())
+            .unwrap();
+        assert_eq!(manager.get_id_by_uri(&next_preview_uri), Some(id));
+        assert_eq!(manager.get_open_source_by_id(id).as_ref().text(), "second");
+        assert_eq!(manager.get_next_id(), SourceId::from_index(1));
+    }
+
+    #[test]
+    fn forgotten_source_id_is_reused_by_cache() {
+        let dir = TempDir::new("reuse");
+        let uri = typst_to_lsp::path_to_uri(&dir.write("chapter.typ", "saved")).unwrap();
+
+        let mut manager = SourceManager::default();
+        let preview_uri = Url::parse("untitled:preview-1").unwrap();
+        manager
+            .insert_open(&preview_uri, "preview".to_owned())
+            .unwrap();
+        let id = manager.get_id_by_uri(&preview_uri).unwrap();
+        manager.set_document(id, Document::default());
+        assert_eq!(manager.forget(&preview_uri), Some(id));
+
+        let cached_id = manager.cache(uri).unwrap();
+        let text = manager
+            .get_source_by_id(cached_id)
+            .map(|source| source.as_ref().text().to_owned());
+
+        assert_eq!(cached_id, id);
+        assert_eq!(text.as_deref(), Some("saved"));
+        assert!(manager.get_document(id).is_none());
+        assert_eq!(manager.get_next_id(), SourceId::from_index(1));
+    }
+
+    #[test]
+    fn source_is_found_by_the_uri_it_was_added_by() {
+        let dir = TempDir::new("added-by");
+        dir.write("chapters/chapter.typ", "");
+        dir.write("imported.typ", "saved");
+
+        let manager = SourceManager::default();
+        let import_uri = Url::from_file_path(dir.path().join("chapters/../imported.typ")).unwrap();
+        let id = manager.cache(import_uri.clone()).unwrap();
+        // Without the file, its canonical path can't be found again
+        fs::remove_dir_all(dir.path()).unwrap();
+
+        assert_eq!(manager.get_id_by_uri(&import_uri), Some(id));
+        assert_eq!(manager.cache(import_uri).ok(), Some(id));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_source_shares_id() {
+        let dir = TempDir::new("symlink");
+        let path = dir.write("shared/template.typ", "saved");
+        let link = dir.path().join("template.typ");
+        std::os::unix::fs::symlink(&path, &link).unwrap();
+
+        // The client opens the file through the symbolic link, while an import names its target
+        let mut manager = SourceManager::default();
+        let link_uri = Url::from_file_path(&link).unwrap();
+        manager
+            .insert_open(&link_uri, "unsaved".to_owned())
+            .unwrap();
+        let target_uri = Url::from_file_path(&path).unwrap();
+        let id = manager.cache(target_uri.clone()).unwrap();
+
+        assert_eq!(manager.get_id_by_uri(&link_uri), Some(id));
+        assert_eq!(manager.get_id_by_uri(&target_uri), Some(id));
+        let source = manager.get_open_source_by_id(id);
+        assert_eq!(source.as_ref().text(), "unsaved");
+        // The client is still told about the file by the URI it opened it with
+        assert_eq!(source.uri(), &link_uri);
+    }
+}